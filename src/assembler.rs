@@ -0,0 +1,1082 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::interpreter::START_ADDR;
+
+const ENTRY_LABEL: &str = "main";
+const MAX_ADDR: usize = 0xFFFF; // XO-CHIP's 64K, which is as far as i := long reaches.
+const MAX_EXPANSIONS: usize = 10_000; // Macros that expand into themselves would otherwise never stop.
+
+// Statements that are a whole instruction on their own.
+const PLAIN_OPS: [(&str, u16); 9] = [
+    ("clear", 0x00E0),
+    ("return", 0x00EE),
+    (";", 0x00EE),
+    ("scroll-right", 0x00FB),
+    ("scroll-left", 0x00FC),
+    ("exit", 0x00FD),
+    ("lowres", 0x00FE),
+    ("hires", 0x00FF),
+    ("audio", 0xF002),
+];
+
+// Statements taking one register, as X.
+const REGISTER_OPS: [(&str, u16); 3] = [("bcd", 0xF033), ("saveflags", 0xF075), ("loadflags", 0xF085)];
+
+// Register to register arithmetic, all 8XYN.
+const ALU_OPS: [(&str, u16); 9] = [
+    (":=", 0x0),
+    ("|=", 0x1),
+    ("&=", 0x2),
+    ("^=", 0x3),
+    ("+=", 0x4),
+    ("-=", 0x5),
+    (">>=", 0x6),
+    ("=-", 0x7),
+    ("<<=", 0xE),
+];
+
+const BINARY_OPS: [&str; 19] = [
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", ">", "<=", ">=", "==", "!=",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AsmErrKind {
+    UnexpectedEnd,
+    Unexpected(String),       // A token that doesn't belong where it is.
+    ExpectedRegister(String),
+    Undefined(String),
+    Redefined(String),
+    OutOfRange(i64),
+    Unbalanced(String),       // An else, end, again or while without what opens it, or a block never closed.
+    RecursiveMacro(String),
+    AssertFailed(String),
+    Unsupported(String),
+    MissingMain,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmErr {
+    pub line: usize, // 1-based, in the source.
+    pub kind: AsmErrKind,
+}
+
+impl fmt::Display for AsmErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AsmErrKind::UnexpectedEnd => write!(f, "unexpected end of program"),
+            AsmErrKind::Unexpected(token) => write!(f, "unexpected '{}'", token),
+            AsmErrKind::ExpectedRegister(token) => write!(f, "expected a register, found '{}'", token),
+            AsmErrKind::Undefined(name) => write!(f, "'{}' is not defined", name),
+            AsmErrKind::Redefined(name) => write!(f, "'{}' is already defined", name),
+            AsmErrKind::OutOfRange(val) => write!(f, "{} is out of range", val),
+            AsmErrKind::Unbalanced(token) => write!(f, "unbalanced '{}'", token),
+            AsmErrKind::RecursiveMacro(name) => write!(f, "macro '{}' expands forever", name),
+            AsmErrKind::AssertFailed(message) => write!(f, "assertion failed {}", message),
+            AsmErrKind::Unsupported(token) => write!(f, "'{}' is not supported", token),
+            AsmErrKind::MissingMain => write!(f, "there is no 'main' label"),
+        }
+    }
+}
+
+// Assembles Octo source into a ROM loaded at 0x200.
+//
+// Octo is the assembly language Octo cartridges carry their programs in: labels, constants, register aliases,
+// macros and :calc expressions, if/else blocks and loops, and the CHIP-8, S-CHIP and XO-CHIP instructions in its
+// own notation. Execution starts at the "main" label, with a jump there unless the code starts with it.
+// :stringmode isn't supported, and the debugger's :breakpoint and :monitor are skipped.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmErr> {
+    let mut assembler = Assembler::new(tokenize(source)?);
+    assembler.program()?;
+    Ok(assembler.rom)
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, AsmErr> {
+    // Tokens are separated by whitespace, with # commenting out the rest of the line and strings kept whole.
+    let mut tokens = Vec::new();
+
+    for (idx, text) in source.lines().enumerate() {
+        let line = idx + 1;
+        let mut rest = text.trim_start();
+
+        while !rest.is_empty() && !rest.starts_with('#') {
+            let len = match rest.strip_prefix('"') {
+                Some(string) => string.find('"').map(|end| end + 2).ok_or_else(|| AsmErr {
+                    line,
+                    kind: AsmErrKind::Unexpected(String::from(rest)),
+                })?,
+                None => rest.find(char::is_whitespace).unwrap_or(rest.len()),
+            };
+
+            tokens.push(Token { text: String::from(&rest[..len]), line });
+            rest = rest[len..].trim_start();
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    // Decimal, 0x hex or 0b binary, any of them negative.
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let val = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -val } else { val })
+}
+
+fn binary(op: &str, lhs: f64, rhs: f64) -> f64 {
+    let (a, b) = (lhs as i64, rhs as i64);
+
+    match op {
+        "+" => lhs + rhs,
+        "-" => lhs - rhs,
+        "*" => lhs * rhs,
+        "/" => lhs / rhs,
+        "%" => lhs % rhs,
+        "&" => (a & b) as f64,
+        "|" => (a | b) as f64,
+        "^" => (a ^ b) as f64,
+        "<<" => a.wrapping_shl(b as u32) as f64,
+        ">>" => a.wrapping_shr(b as u32) as f64,
+        "pow" => lhs.powf(rhs),
+        "min" => lhs.min(rhs),
+        "max" => lhs.max(rhs),
+        "<" => (lhs < rhs) as i64 as f64,
+        ">" => (lhs > rhs) as i64 as f64,
+        "<=" => (lhs <= rhs) as i64 as f64,
+        ">=" => (lhs >= rhs) as i64 as f64,
+        "==" => (lhs == rhs) as i64 as f64,
+        _ => (lhs != rhs) as i64 as f64,
+    }
+}
+
+// How an address is written into what's been emitted, once it's known.
+#[derive(Copy, Clone, Debug)]
+enum Fixup {
+    Nnn,      // The low 12 bits of the instruction at the address.
+    Long,     // A whole 16-bit word, for i := long.
+    High(u8), // The high byte OR'ed with a prefix, and the low byte alone, for :unpack.
+    Low,
+}
+
+#[derive(Clone)]
+enum Reference {
+    Addr(usize),
+    Label(String), // Not defined yet, so it's patched in at the end.
+}
+
+struct PendingFixup {
+    at: usize,
+    fixup: Fixup,
+    label: String,
+    line: usize,
+}
+
+enum Block {
+    If { jump: usize }, // The jump past the body, patched at else or end.
+    Else { jump: usize },
+    Loop { start: usize, breaks: Vec<usize> },
+}
+
+struct Condition {
+    setup: Vec<u16>,    // Instructions that work the condition out into VF first.
+    skip_if_false: u16, // For then, skipping the statement after it.
+    skip_if_true: u16,  // For begin and while, skipping the jump past them.
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+    calls: usize, // What CALLS means in its body, counting up from 0 with each use.
+}
+
+struct Assembler {
+    tokens: Vec<Token>,
+    pos: usize,
+    line: usize,
+    rom: Vec<u8>, // From START_ADDR.
+    here: usize,
+    labels: HashMap<String, usize>,
+    consts: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<PendingFixup>,
+    blocks: Vec<(Block, usize)>, // Each with the line it was opened on.
+    expansions: usize,
+    has_entry: bool, // Whether 0x200 has been settled as main or a jump there.
+}
+
+impl Assembler {
+    fn new(tokens: Vec<Token>) -> Self {
+        Assembler {
+            tokens,
+            pos: 0,
+            line: 1,
+            rom: Vec::new(),
+            here: START_ADDR,
+            labels: HashMap::new(),
+            consts: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            expansions: 0,
+            has_entry: false,
+        }
+    }
+
+    fn program(&mut self) -> Result<(), AsmErr> {
+        while self.pos < self.tokens.len() {
+            self.statement()?;
+        }
+
+        if let Some((block, line)) = self.blocks.last() {
+            let opener = match block {
+                Block::If { .. } => "begin",
+                Block::Else { .. } => "else",
+                Block::Loop { .. } => "loop",
+            };
+            return Err(AsmErr { line: *line, kind: AsmErrKind::Unbalanced(String::from(opener)) });
+        }
+
+        if !self.labels.contains_key(ENTRY_LABEL) {
+            return Err(AsmErr { line: 1, kind: AsmErrKind::MissingMain });
+        }
+
+        for pending in std::mem::take(&mut self.fixups) {
+            self.line = pending.line;
+            let addr = match self.lookup(&pending.label) {
+                Some(addr) => self.int(addr, 0, MAX_ADDR as i64)? as usize,
+                None => return Err(self.err(AsmErrKind::Undefined(pending.label))),
+            };
+            self.patch(pending.at, pending.fixup, addr)?;
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AsmErr> {
+        let token = self.next()?;
+
+        if let Some((_, instr)) = PLAIN_OPS.iter().find(|(name, _)| *name == token) {
+            return self.emit_instr(*instr);
+        }
+
+        if let Some((_, instr)) = REGISTER_OPS.iter().find(|(name, _)| *name == token) {
+            let x = self.register()?;
+            return self.emit_instr(instr | x << 8);
+        }
+
+        if let Some(x) = self.register_of(&token) {
+            return self.register_statement(x);
+        }
+
+        match token.as_str() {
+            ":" => {
+                // Any other label at 0x200 would be pushed along by the jump to main.
+                let name = self.next()?;
+                if !self.has_entry && name != ENTRY_LABEL {
+                    self.emit_entry()?;
+                }
+                self.define_label(name, self.here)
+            }
+            ":const" => {
+                let name = self.next()?;
+                let val = self.value()?;
+                self.define_const(name, val)
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let val = self.braced_expression()?;
+                self.define_const(name, val)
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let reg = match self.peek() {
+                    Some("{") => {
+                        let val = self.braced_expression()?;
+                        self.int(val, 0, 0xF)? as u8
+                    }
+                    _ => self.register()? as u8,
+                };
+                self.aliases.insert(name, reg);
+                Ok(())
+            }
+            ":byte" => {
+                let val = match self.peek() {
+                    Some("{") => self.braced_expression()?,
+                    _ => self.value()?,
+                };
+                let byte = self.int(val, -0x80, 0xFF)? as u8;
+                self.emit(byte)
+            }
+            ":org" => {
+                let val = self.value()?;
+                self.here = self.int(val, START_ADDR as i64, MAX_ADDR as i64)? as usize;
+                Ok(())
+            }
+            ":next" => {
+                // Names the second byte of the next instruction, for code that rewrites its own operands.
+                let name = self.next()?;
+                self.define_label(name, self.here + 1)
+            }
+            ":unpack" => self.unpack(),
+            ":call" => self.emit_reference(0x2000),
+            ":macro" => self.define_macro(),
+            ":assert" => {
+                let message = match self.peek() {
+                    Some(text) if text.starts_with('"') => self.next()?,
+                    _ => String::new(),
+                };
+                let val = self.braced_expression()?;
+                if val == 0.0 {
+                    return Err(self.err(AsmErrKind::AssertFailed(message)));
+                }
+                Ok(())
+            }
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => self.next().and_then(|_| self.next()).map(|_| ()),
+            "jump" => self.emit_reference(0x1000),
+            "jump0" => self.emit_reference(0xB000),
+            "native" => self.emit_reference(0x0000),
+            "i" => self.i_statement(),
+            "save" | "load" => self.load_store(&token),
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.value()?;
+                let n = self.int(n, 0, 0xF)? as u16;
+                self.emit_instr(0xD000 | x << 8 | y << 4 | n)
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let op = match token.as_str() {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit_instr(op | x << 8)
+            }
+            "plane" | "scroll-down" | "scroll-up" => {
+                let n = self.value()?;
+                let n = self.int(n, 0, 0xF)? as u16;
+                match token.as_str() {
+                    "plane" => self.emit_instr(0xF001 | n << 8),
+                    "scroll-down" => self.emit_instr(0x00C0 | n),
+                    _ => self.emit_instr(0x00D0 | n),
+                }
+            }
+            "if" => self.if_statement(),
+            "else" => match self.blocks.pop() {
+                Some((Block::If { jump }, line)) => {
+                    let end_jump = self.here;
+                    self.emit_instr(0x1000)?;
+                    self.patch(jump, Fixup::Nnn, self.here)?;
+                    self.blocks.push((Block::Else { jump: end_jump }, line));
+                    Ok(())
+                }
+                _ => Err(self.err(AsmErrKind::Unbalanced(token))),
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::If { jump }, _)) | Some((Block::Else { jump }, _)) => self.patch(jump, Fixup::Nnn, self.here),
+                _ => Err(self.err(AsmErrKind::Unbalanced(token))),
+            },
+            "loop" => {
+                self.blocks.push((Block::Loop { start: self.here, breaks: Vec::new() }, self.line));
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition()?;
+                self.emit_condition(&condition.setup, condition.skip_if_true)?;
+                let jump = self.here;
+                self.emit_instr(0x1000)?;
+
+                let line = self.line;
+                match self.blocks.iter_mut().rev().find_map(|(block, _)| match block {
+                    Block::Loop { breaks, .. } => Some(breaks),
+                    _ => None,
+                }) {
+                    Some(breaks) => {
+                        breaks.push(jump);
+                        Ok(())
+                    }
+                    None => Err(AsmErr { line, kind: AsmErrKind::Unbalanced(token) }),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some((Block::Loop { start, breaks }, _)) => {
+                    let jump = self.here;
+                    self.emit_instr(0x1000)?;
+                    self.patch(jump, Fixup::Nnn, start)?;
+                    for jump in breaks {
+                        self.patch(jump, Fixup::Nnn, self.here)?;
+                    }
+                    Ok(())
+                }
+                _ => Err(self.err(AsmErrKind::Unbalanced(token))),
+            },
+            ":stringmode" | ":pointer" => Err(self.err(AsmErrKind::Unsupported(token))),
+            _ if token.starts_with(':') => Err(self.err(AsmErrKind::Unexpected(token))),
+            _ if self.macros.contains_key(&token) => self.expand(&token),
+            _ => match parse_number(&token).or_else(|| self.consts.get(&token).copied()) {
+                // A bare number is a byte of data, and a bare name calls the subroutine it labels.
+                Some(val) => {
+                    let byte = self.int(val, -0x80, 0xFF)? as u8;
+                    self.emit(byte)
+                }
+                None => {
+                    let at = self.here;
+                    self.emit_instr(0x2000)?;
+                    let reference = self.label_reference(token)?;
+                    self.refer(at, Fixup::Nnn, reference)
+                }
+            },
+        }
+    }
+
+    fn register_statement(&mut self, x: u16) -> Result<(), AsmErr> {
+        let op = self.next()?;
+        let rhs = self.next()?;
+
+        match (op.as_str(), rhs.as_str()) {
+            (":=", "random") => {
+                let mask = self.byte()?;
+                self.emit_instr(0xC000 | x << 8 | mask as u16)
+            }
+            (":=", "key") => self.emit_instr(0xF00A | x << 8),
+            (":=", "delay") => self.emit_instr(0xF007 | x << 8),
+            _ => match self.register_of(&rhs) {
+                Some(y) => match ALU_OPS.iter().find(|(name, _)| *name == op) {
+                    Some((_, n)) => self.emit_instr(0x8000 | x << 8 | y << 4 | n),
+                    None => Err(self.err(AsmErrKind::Unexpected(op))),
+                },
+                None => {
+                    let n = self.byte_of(&rhs)? as u16;
+                    match op.as_str() {
+                        ":=" => self.emit_instr(0x6000 | x << 8 | n),
+                        "+=" => self.emit_instr(0x7000 | x << 8 | n),
+                        "-=" => self.emit_instr(0x7000 | x << 8 | (n as u8).wrapping_neg() as u16),
+                        _ => Err(self.err(AsmErrKind::Unexpected(op))),
+                    }
+                }
+            },
+        }
+    }
+
+    fn i_statement(&mut self) -> Result<(), AsmErr> {
+        let op = self.next()?;
+
+        match op.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") | Some("bighex") => {
+                    let op = if self.next()? == "hex" { 0xF029 } else { 0xF030 };
+                    let x = self.register()?;
+                    self.emit_instr(op | x << 8)
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.emit_instr(0xF000)?;
+                    let at = self.here;
+                    self.emit_instr(0x0000)?;
+                    let reference = self.reference()?;
+                    self.refer(at, Fixup::Long, reference)
+                }
+                _ => self.emit_reference(0xA000),
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.emit_instr(0xF01E | x << 8)
+            }
+            _ => Err(self.err(AsmErrKind::Unexpected(op))),
+        }
+    }
+
+    fn load_store(&mut self, op: &str) -> Result<(), AsmErr> {
+        // XO-CHIP's ranges, save vx - vy, don't touch I.
+        let x = self.register()?;
+        let is_save = op == "save";
+
+        match self.peek() {
+            Some("-") => {
+                self.next()?;
+                let y = self.register()?;
+                self.emit_instr(0x5000 | x << 8 | y << 4 | if is_save { 0x2 } else { 0x3 })
+            }
+            _ => self.emit_instr(0xF000 | x << 8 | if is_save { 0x55 } else { 0x65 }),
+        }
+    }
+
+    fn unpack(&mut self) -> Result<(), AsmErr> {
+        // Loads an address into v0 and v1, or whichever registers unpack-hi and unpack-lo alias, with a nibble
+        // OR'ed into the top of v0 unless it's long.
+        let prefix = match self.peek() {
+            Some("long") => {
+                self.next()?;
+                0
+            }
+            _ => {
+                let nibble = self.value()?;
+                (self.int(nibble, 0, 0xF)? as u8) << 4
+            }
+        };
+        let hi = *self.aliases.get("unpack-hi").unwrap_or(&0) as u16;
+        let lo = *self.aliases.get("unpack-lo").unwrap_or(&1) as u16;
+
+        let at = self.here;
+        let reference = self.reference()?;
+
+        self.emit_instr(0x6000 | hi << 8)?;
+        self.emit_instr(0x6000 | lo << 8)?;
+        self.refer(at + 1, Fixup::High(prefix), reference.clone())?;
+        self.refer(at + 3, Fixup::Low, reference)
+    }
+
+    fn if_statement(&mut self) -> Result<(), AsmErr> {
+        let line = self.line;
+        let condition = self.condition()?;
+        let token = self.next()?;
+
+        match token.as_str() {
+            "then" => self.emit_condition(&condition.setup, condition.skip_if_false),
+            "begin" => {
+                self.emit_condition(&condition.setup, condition.skip_if_true)?;
+                self.blocks.push((Block::If { jump: self.here }, line));
+                self.emit_instr(0x1000)
+            }
+            _ => Err(self.err(AsmErrKind::Unexpected(token))),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, AsmErr> {
+        let x = self.register()?;
+        let op = self.next()?;
+
+        let skips = |skip_if_false: u16, skip_if_true: u16| Condition { setup: Vec::new(), skip_if_false, skip_if_true };
+        match op.as_str() {
+            "key" => return Ok(skips(0xE0A1 | x << 8, 0xE09E | x << 8)),
+            "-key" => return Ok(skips(0xE09E | x << 8, 0xE0A1 | x << 8)),
+            _ => (),
+        }
+
+        let rhs = self.next()?;
+        let y = self.register_of(&rhs);
+        let n = match y {
+            Some(_) => 0,
+            None => self.byte_of(&rhs)? as u16,
+        };
+        let (equal, not_equal) = match y {
+            Some(y) => (0x5000 | x << 8 | y << 4, 0x9000 | x << 8 | y << 4),
+            None => (0x3000 | x << 8 | n, 0x4000 | x << 8 | n),
+        };
+
+        // Orderings subtract into VF, and skip on the no-borrow flag that leaves. Only 8XY5 is used, since it's the
+        // subtraction every interpreter agrees sets the flag after the result. Each case ends up with the flag set
+        // either when the comparison holds or when it doesn't.
+        let (setup, holds_when_set) = match (op.as_str(), y) {
+            ("==", _) => return Ok(skips(not_equal, equal)),
+            ("!=", _) => return Ok(skips(equal, not_equal)),
+            (">", _) | ("<=", _) => {
+                // VF := rhs; VF -= vx, so the flag is rhs >= vx.
+                let load = match y {
+                    Some(y) => 0x8F00 | y << 4,
+                    None => 0x6F00 | n,
+                };
+                (vec![load, 0x8F05 | x << 4], op == "<=")
+            }
+            ("<", Some(y)) | (">=", Some(y)) => (vec![0x8F00 | x << 4, 0x8F05 | y << 4], op == ">="), // vx >= vy.
+            ("<", None) | (">=", None) if n == 0 => {
+                // Nothing is below 0, so SE vx, vx and SNE vx, vx stand in for always and never skipping.
+                let (always, never) = (0x5000 | x << 8 | x << 4, 0x9000 | x << 8 | x << 4);
+                return Ok(if op == "<" { skips(always, never) } else { skips(never, always) });
+            }
+            ("<", None) | (">=", None) => (vec![0x6F00 | (n - 1), 0x8F05 | x << 4], op == "<"), // n - 1 >= vx.
+            _ => return Err(self.err(AsmErrKind::Unexpected(op))),
+        };
+
+        let (skip_if_set, skip_if_clear) = (0x3F01, 0x3F00);
+        Ok(match holds_when_set {
+            true => Condition { setup, skip_if_false: skip_if_clear, skip_if_true: skip_if_set },
+            false => Condition { setup, skip_if_false: skip_if_set, skip_if_true: skip_if_clear },
+        })
+    }
+
+    fn emit_condition(&mut self, setup: &[u16], skip: u16) -> Result<(), AsmErr> {
+        for instr in setup.iter() {
+            self.emit_instr(*instr)?;
+        }
+        self.emit_instr(skip)
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmErr> {
+        let name = self.next()?;
+        let mut params = Vec::new();
+        loop {
+            match self.next()? {
+                brace if brace == "{" => break,
+                param => params.push(param),
+            }
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.tokens.get(self.pos).cloned().ok_or_else(|| self.err(AsmErrKind::UnexpectedEnd))?;
+            self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name, Macro { params, body, calls: 0 });
+        Ok(())
+    }
+
+    fn expand(&mut self, name: &str) -> Result<(), AsmErr> {
+        // The body goes in place of the call, with each parameter swapped for its argument.
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.err(AsmErrKind::RecursiveMacro(String::from(name))));
+        }
+
+        let param_count = self.macros[name].params.len();
+        let args = (0..param_count).map(|_| self.next()).collect::<Result<Vec<_>, _>>()?;
+
+        let line = self.line;
+        let definition = self.macros.get_mut(name).unwrap();
+        let calls = definition.calls.to_string();
+        definition.calls += 1;
+
+        let expanded: Vec<Token> = definition.body.iter()
+            .map(|token| {
+                let text = match definition.params.iter().position(|param| *param == token.text) {
+                    Some(idx) => args[idx].clone(),
+                    None if token.text == "CALLS" => calls.clone(),
+                    None => token.text.clone(),
+                };
+                Token { text, line }
+            })
+            .collect();
+
+        self.tokens.splice(self.pos..self.pos, expanded);
+        Ok(())
+    }
+
+    fn braced_expression(&mut self) -> Result<f64, AsmErr> {
+        self.expect("{")?;
+        let val = self.expression()?;
+        self.expect("}")?;
+        Ok(val)
+    }
+
+    fn expression(&mut self) -> Result<f64, AsmErr> {
+        // As in Octo, operators have no precedence and group from the right, so 1 + 2 * 3 is 7.
+        let lhs = self.term()?;
+        match self.peek() {
+            Some(op) if BINARY_OPS.contains(&op) => {
+                let op = self.next()?;
+                let rhs = self.expression()?;
+                Ok(binary(&op, lhs, rhs))
+            }
+            _ => Ok(lhs),
+        }
+    }
+
+    fn term(&mut self) -> Result<f64, AsmErr> {
+        let token = self.next()?;
+
+        let val = match token.as_str() {
+            "(" => {
+                let val = self.expression()?;
+                self.expect(")")?;
+                val
+            }
+            "-" => -self.term()?,
+            "~" => !(self.term()? as i64) as f64,
+            "!" => (self.term()? == 0.0) as i64 as f64,
+            "abs" => self.term()?.abs(),
+            "sqrt" => self.term()?.sqrt(),
+            "sign" => self.term()?.signum(),
+            "ceil" => self.term()?.ceil(),
+            "floor" => self.term()?.floor(),
+            "sin" => self.term()?.sin(),
+            "cos" => self.term()?.cos(),
+            "tan" => self.term()?.tan(),
+            "exp" => self.term()?.exp(),
+            "log" => self.term()?.ln(),
+            "@" => {
+                // The byte assembled at an address so far.
+                let addr = self.term()?;
+                let idx = (addr as usize).wrapping_sub(START_ADDR);
+                self.rom.get(idx).copied().unwrap_or(0) as f64
+            }
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            _ => return self.lookup(&token).ok_or_else(|| self.err(AsmErrKind::Undefined(token))),
+        };
+
+        Ok(val)
+    }
+
+    fn define_label(&mut self, name: String, addr: usize) -> Result<(), AsmErr> {
+        if self.labels.contains_key(&name) || self.consts.contains_key(&name) {
+            return Err(self.err(AsmErrKind::Redefined(name)));
+        }
+
+        self.labels.insert(name, addr);
+        Ok(())
+    }
+
+    fn define_const(&mut self, name: String, val: f64) -> Result<(), AsmErr> {
+        if self.labels.contains_key(&name) || self.consts.contains_key(&name) {
+            return Err(self.err(AsmErrKind::Redefined(name)));
+        }
+
+        self.consts.insert(name, val);
+        Ok(())
+    }
+
+    fn lookup(&self, token: &str) -> Option<f64> {
+        parse_number(token)
+            .or_else(|| self.consts.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|&addr| addr as f64))
+    }
+
+    fn value(&mut self) -> Result<f64, AsmErr> {
+        let token = self.next()?;
+        self.lookup(&token).ok_or_else(|| self.err(AsmErrKind::Undefined(token)))
+    }
+
+    fn int(&self, val: f64, min: i64, max: i64) -> Result<i64, AsmErr> {
+        let int = val as i64;
+        if !val.is_finite() || int < min || int > max {
+            return Err(self.err(AsmErrKind::OutOfRange(int)));
+        }
+
+        Ok(int)
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmErr> {
+        let token = self.next()?;
+        self.byte_of(&token)
+    }
+
+    fn byte_of(&self, token: &str) -> Result<u8, AsmErr> {
+        let val = self.lookup(token).ok_or_else(|| self.err(AsmErrKind::Undefined(String::from(token))))?;
+        Ok(self.int(val, -0x80, 0xFF)? as u8)
+    }
+
+    fn register_of(&self, token: &str) -> Option<u16> {
+        if let Some(reg) = self.aliases.get(token) {
+            return Some(*reg as u16);
+        }
+
+        match token.as_bytes() {
+            [b'v', digit] | [b'V', digit] => (*digit as char).to_digit(16).map(|reg| reg as u16),
+            _ => None,
+        }
+    }
+
+    fn register(&mut self) -> Result<u16, AsmErr> {
+        let token = self.next()?;
+        self.register_of(&token).ok_or_else(|| self.err(AsmErrKind::ExpectedRegister(token)))
+    }
+
+    fn reference(&mut self) -> Result<Reference, AsmErr> {
+        let token = self.next()?;
+        self.label_reference(token)
+    }
+
+    fn label_reference(&self, token: String) -> Result<Reference, AsmErr> {
+        match self.lookup(&token) {
+            Some(val) => Ok(Reference::Addr(self.int(val, 0, MAX_ADDR as i64)? as usize)),
+            None => Ok(Reference::Label(token)),
+        }
+    }
+
+    fn emit_reference(&mut self, op: u16) -> Result<(), AsmErr> {
+        let at = self.here;
+        self.emit_instr(op)?;
+        let reference = self.reference()?;
+        self.refer(at, Fixup::Nnn, reference)
+    }
+
+    fn refer(&mut self, at: usize, fixup: Fixup, reference: Reference) -> Result<(), AsmErr> {
+        match reference {
+            Reference::Addr(addr) => self.patch(at, fixup, addr),
+            Reference::Label(label) => {
+                self.fixups.push(PendingFixup { at, fixup, label, line: self.line });
+                Ok(())
+            }
+        }
+    }
+
+    fn patch(&mut self, at: usize, fixup: Fixup, addr: usize) -> Result<(), AsmErr> {
+        let max = match fixup {
+            Fixup::Nnn => 0xFFF,
+            _ => MAX_ADDR,
+        };
+        if addr > max {
+            return Err(self.err(AsmErrKind::OutOfRange(addr as i64)));
+        }
+
+        let idx = at - START_ADDR;
+        match fixup {
+            Fixup::Nnn => {
+                self.rom[idx] = (self.rom[idx] & 0xF0) | (addr >> 8) as u8;
+                self.rom[idx + 1] = addr as u8;
+            }
+            Fixup::Long => self.rom[idx..idx + 2].copy_from_slice(&(addr as u16).to_be_bytes()),
+            Fixup::High(prefix) => self.rom[idx] = prefix | (addr >> 8) as u8,
+            Fixup::Low => self.rom[idx] = addr as u8,
+        }
+
+        Ok(())
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), AsmErr> {
+        if !self.has_entry {
+            self.emit_entry()?;
+        }
+
+        if self.here > MAX_ADDR {
+            return Err(self.err(AsmErrKind::OutOfRange(self.here as i64)));
+        }
+
+        let idx = self.here - START_ADDR;
+        if idx >= self.rom.len() {
+            self.rom.resize(idx + 1, 0);
+        }
+
+        self.rom[idx] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_entry(&mut self) -> Result<(), AsmErr> {
+        // Before the first byte goes anywhere: programs whose code starts with main run straight into it, and the
+        // rest get a jump there at 0x200.
+        self.has_entry = true;
+        if self.labels.get(ENTRY_LABEL) == Some(&START_ADDR) {
+            return Ok(());
+        }
+
+        let here = self.here;
+        self.here = START_ADDR;
+        self.emit_instr(0x1000)?;
+        self.refer(START_ADDR, Fixup::Nnn, Reference::Label(String::from(ENTRY_LABEL)))?;
+        self.here = if here == START_ADDR { self.here } else { here };
+        Ok(())
+    }
+
+    fn emit_instr(&mut self, instr: u16) -> Result<(), AsmErr> {
+        let [high, low] = instr.to_be_bytes();
+        self.emit(high)?;
+        self.emit(low)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|token| token.text.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, AsmErr> {
+        let token = self.tokens.get(self.pos).ok_or_else(|| self.err(AsmErrKind::UnexpectedEnd))?;
+        self.line = token.line;
+        self.pos += 1;
+        Ok(token.text.clone())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmErr> {
+        let token = self.next()?;
+        match token == text {
+            true => Ok(()),
+            false => Err(self.err(AsmErrKind::Unexpected(token))),
+        }
+    }
+
+    fn err(&self, kind: AsmErrKind) -> AsmErr {
+        AsmErr { line: self.line, kind }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Chip8Interpreter;
+    use crate::platform_adapter::PlatformAdapter;
+
+    struct MockPlatform;
+
+    impl PlatformAdapter for MockPlatform {
+        fn play_sound(&mut self) {}
+        fn pause_sound(&mut self) {}
+    }
+
+    fn words(rom: &[u8]) -> Vec<u16> {
+        rom.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+    }
+
+    fn err(source: &str) -> AsmErr {
+        assemble(source).unwrap_err()
+    }
+
+    #[test]
+    fn instructions_test() {
+        let source = "
+            : main
+                clear
+                v0 := 0x12  v1 += 3  v2 -= 1  v0 := v1  v3 ^= v4  v5 <<= v6  vA =- vB
+                v0 := random 0b11111111  v7 := key  v8 := delay  delay := v8  buzzer := v9
+                i := 0x300  i += v1  i := hex v2  i := bighex v3  i := long 0x1234
+                sprite v1 v2 5  bcd va  save vf  load v2  save v1 - v3  load v4 - v2
+                hires  scroll-down 4  plane 2  jump0 0x208  ;
+        ";
+
+        assert_eq!(Ok(vec![
+            0x00E0,
+            0x6012, 0x7103, 0x72FF, 0x8010, 0x8343, 0x856E, 0x8AB7,
+            0xC0FF, 0xF70A, 0xF807, 0xF815, 0xF918,
+            0xA300, 0xF11E, 0xF229, 0xF330, 0xF000, 0x1234,
+            0xD125, 0xFA33, 0xFF55, 0xF265, 0x5132, 0x5423,
+            0x00FF, 0x00C4, 0xF201, 0xB208, 0x00EE,
+        ]), assemble(source).map(|rom| words(&rom)));
+    }
+
+    #[test]
+    fn labels_test() {
+        // Code before main puts a jump to it first. Calls, jumps and :unpack can all look ahead.
+        let source = "
+            : data 0xAB 0xCD
+            : main
+                i := data
+                sub
+                :unpack 0xA data
+                :unpack long far
+                jump main
+            : sub
+                v0 := 0
+                return
+            :org 0x300
+            : far
+                :byte { 1 + 2 * 3 }
+        ";
+        let rom = assemble(source).unwrap();
+
+        assert_eq!(0x101, rom.len());
+        assert_eq!(vec![0x1204, 0xABCD, 0xA202, 0x2212, 0x60A2, 0x6102, 0x6003, 0x6100, 0x1204, 0x6000, 0x00EE],
+            words(&rom[..0x16]));
+        assert!(rom[0x16..0x100].iter().all(|&byte| byte == 0));
+        assert_eq!(7, rom[0x100]);
+
+        // :next names the byte after the next instruction's first.
+        assert_eq!(Ok(vec![0x6005, 0xA201]), assemble(": main :next spot v0 := 5 i := spot").map(|rom| words(&rom)));
+    }
+
+    #[test]
+    fn control_flow_test() {
+        let source = "
+            : main
+                loop
+                    if v0 == 5 then v1 := 1
+                    if v0 != v1 begin
+                        v2 := 2
+                    else
+                        v2 := 3
+                    end
+                    while v3 key
+                    v0 += 1
+                again
+        ";
+
+        assert_eq!(Ok(vec![0x4005, 0x6101, 0x9010, 0x120C, 0x6202, 0x120E, 0x6203, 0xE39E, 0x1216, 0x7001, 0x1200]),
+            assemble(source).map(|rom| words(&rom)));
+    }
+
+    #[test]
+    fn comparison_test() {
+        // Each true comparison sets a register, and the loops count.
+        let source = "
+            : main
+                v0 := 5
+                v1 := 7
+                if v0 < v1 then va := 1
+                if v0 > v1 then vb := 1
+                if v0 <= 5 then vc := 1
+                if v0 >= 6 then vd := 1
+                if v1 < 0 then ve := 1
+                if v1 >= 0 begin v2 := 1 end
+                if v1 > 6 begin v3 := 1 else v3 := 2 end
+                loop while v4 != 10 v4 += 1 again
+                loop v5 += 1 while v5 < 20 again
+                loop again
+        ";
+
+        let mut interpreter = Chip8Interpreter::new(MockPlatform, assemble(source).unwrap()).unwrap();
+        for _ in 0..10 {
+            interpreter.run_frame(6000).unwrap();
+        }
+
+        assert_eq!([1, 0, 1, 0, 0], interpreter.v_regs[0xA..0xF]);
+        assert_eq!([1, 1, 10, 20], interpreter.v_regs[2..6]);
+    }
+
+    #[test]
+    fn macro_test() {
+        let source = "
+            :macro add-twice reg n { reg += n reg += n }
+            :macro mark { :byte CALLS }
+            :const STEP 3
+            :calc DOUBLE { STEP * 2 }
+            :alias counter v1
+            : main
+                add-twice counter STEP
+                v2 := DOUBLE
+                mark mark
+        ";
+
+        assert_eq!(Ok(vec![0x71, 0x03, 0x71, 0x03, 0x62, 0x06, 0x00, 0x01]), assemble(source));
+    }
+
+    #[test]
+    fn err_test() {
+        assert_eq!(AsmErr { line: 1, kind: AsmErrKind::Undefined(String::from("nowhere")) }, err(": main jump nowhere"));
+        assert_eq!(AsmErrKind::MissingMain, err(": start return").kind);
+        assert_eq!(AsmErrKind::MissingMain, err("").kind);
+        assert_eq!(AsmErr { line: 2, kind: AsmErrKind::Unbalanced(String::from("end")) }, err(": main\nend"));
+        assert_eq!(AsmErr { line: 2, kind: AsmErrKind::Unbalanced(String::from("loop")) }, err(": main\nloop\nv0 += 1"));
+        assert_eq!(AsmErrKind::OutOfRange(256), err(": main v0 := 256").kind);
+        assert_eq!(AsmErrKind::OutOfRange(16), err(": main sprite v0 v1 16").kind);
+        assert_eq!(AsmErrKind::ExpectedRegister(String::from("5")), err(": main bcd 5").kind);
+        assert_eq!(AsmErrKind::Redefined(String::from("main")), err(": main : main").kind);
+        assert_eq!(AsmErrKind::RecursiveMacro(String::from("forever")), err(":macro forever { forever } : main forever").kind);
+        assert_eq!(AsmErrKind::AssertFailed(String::from("\"too big\"")), err(": main :assert \"too big\" { HERE < 0x200 }").kind);
+        assert_eq!(AsmErrKind::Unsupported(String::from(":stringmode")), err(": main :stringmode").kind);
+        assert_eq!(AsmErrKind::UnexpectedEnd, err(": main v0 :=").kind);
+
+        assert_eq!("line 3: unbalanced 'else'", err(": main\n\nelse").to_string());
+    }
+}
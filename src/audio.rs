@@ -0,0 +1,231 @@
+use crate::interpreter::Chip8Interpreter;
+use crate::observer::Observer;
+use crate::platform_adapter::PlatformAdapter;
+
+pub const DEFAULT_TONE_HZ: f64 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+pub const PATTERN_SZ: usize = 16;
+pub const DEFAULT_PITCH: u8 = 64;
+
+const FRAMES_PER_SECOND: f64 = 60.0;
+
+// Fading in and out over a couple of milliseconds hides the click of a waveform starting or stopping mid-cycle.
+const DECLICK_SECONDS: f64 = 0.002;
+
+pub struct AudioSynth {
+    sample_rate: u32,
+    tone_hz: f64,
+    volume: f32,
+    pattern: Option<[u8; PATTERN_SZ]>, // XO-CHIP audio pattern buffer: 128 1-bit samples played MSB first.
+    pitch: u8,                         // XO-CHIP pitch register.
+    phase: f64,
+    gain: f32,
+    gain_step: f32,
+    sample_debt: f64,
+}
+
+impl AudioSynth {
+    pub fn new(sample_rate: u32) -> Self {
+        let declick_samples = (sample_rate as f64 * DECLICK_SECONDS).max(1.0);
+
+        AudioSynth {
+            sample_rate,
+            tone_hz: DEFAULT_TONE_HZ,
+            volume: DEFAULT_VOLUME,
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+            phase: 0.0,
+            gain: 0.0,
+            gain_step: (1.0 / declick_samples) as f32,
+            sample_debt: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_tone(&mut self, tone_hz: f64) {
+        self.tone_hz = tone_hz;
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume;
+    }
+
+    pub fn set_pattern(&mut self, pattern: Option<[u8; PATTERN_SZ]>) {
+        self.pattern = pattern;
+    }
+
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch;
+    }
+
+    pub fn pattern_rate(&self) -> f64 {
+        // XO-CHIP plays the pattern at 4000 bits/sec at pitch 64, doubling every 48 steps.
+        4000.0 * 2f64.powf((self.pitch as f64 - 64.0) / 48.0)
+    }
+
+    pub fn render_frame(&mut self, sound_on: bool, out: &mut Vec<f32>) {
+        self.render_seconds(sound_on, 1.0 / FRAMES_PER_SECOND, out);
+    }
+
+    pub fn render_step(&mut self, sound_on: bool, tick_rate: u64, out: &mut Vec<f32>) {
+        // Rendering per interpreter step keeps sound starting and stopping on the exact instruction that caused it.
+        self.render_seconds(sound_on, 1.0 / std::cmp::max(1, tick_rate) as f64, out);
+    }
+
+    pub fn render_interpreter_frame<T: PlatformAdapter, O: Observer>(&mut self, interpreter: &Chip8Interpreter<T, O>, out: &mut Vec<f32>) {
+        self.render_frame(interpreter.sound_timer.current_val > 0, out);
+    }
+
+    pub fn render_seconds(&mut self, sound_on: bool, seconds: f64, out: &mut Vec<f32>) {
+        // Carry the fractional sample over, so e.g. 22050Hz alternates 367 and 368 samples per frame.
+        self.sample_debt += seconds * self.sample_rate as f64;
+        let count = self.sample_debt.floor();
+        self.sample_debt -= count;
+
+        self.render_samples(sound_on, count as usize, out);
+    }
+
+    pub fn render_samples(&mut self, sound_on: bool, count: usize, out: &mut Vec<f32>) {
+        let rate = match self.pattern {
+            Some(_) => self.pattern_rate() / (PATTERN_SZ * 8) as f64,
+            None => self.tone_hz,
+        };
+        let phase_step = rate / self.sample_rate as f64;
+
+        out.reserve(count);
+
+        for _ in 0..count {
+            if sound_on {
+                self.gain = (self.gain + self.gain_step).min(1.0);
+            } else {
+                self.gain = (self.gain - self.gain_step).max(0.0);
+            }
+
+            if self.gain == 0.0 {
+                // Restart the waveform from the top next time, so every beep sounds the same.
+                self.phase = 0.0;
+                out.push(0.0);
+                continue;
+            }
+
+            let level = if self.waveform_high() { 1.0 } else { -1.0 };
+            out.push(level * self.gain * self.volume);
+
+            self.phase = (self.phase + phase_step).fract();
+        }
+    }
+
+    fn waveform_high(&self) -> bool {
+        match &self.pattern {
+            Some(pattern) => {
+                let bit_idx = (self.phase * (PATTERN_SZ * 8) as f64) as usize % (PATTERN_SZ * 8);
+                pattern[bit_idx / 8] & (0x80 >> (bit_idx % 8)) != 0
+            }
+            None => self.phase < 0.5,
+        }
+    }
+}
+
+pub fn write_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    // 16-bit signed mono PCM.
+    const CHANNELS: u16 = 1;
+    const BITS_PER_SAMPLE: u16 = 16;
+
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+    let data_len = samples.len() as u32 * block_align as u32;
+
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    out.extend_from_slice(&CHANNELS.to_le_bytes());
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    out.extend_from_slice(&block_align.to_le_bytes());
+    out.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let val = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        out.extend_from_slice(&val.to_le_bytes());
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_sample_count_test() {
+        // A second of frames should produce exactly a second of samples, even when frames don't divide evenly.
+        let mut synth = AudioSynth::new(22050);
+        let mut out = Vec::new();
+
+        synth.render_frame(true, &mut out);
+        assert_eq!(367, out.len());
+
+        for _ in 1..60 {
+            synth.render_frame(true, &mut out);
+        }
+        assert_eq!(22050, out.len());
+    }
+
+    #[test]
+    fn declick_test() {
+        // At 1kHz the declick ramp is 2 samples long, and a 250Hz tone is high for 2 samples then low for 2.
+        let mut synth = AudioSynth::new(1000);
+        synth.set_tone(250.0);
+        synth.set_volume(1.0);
+
+        let mut out = Vec::new();
+        synth.render_samples(true, 4, &mut out);
+        synth.render_samples(false, 3, &mut out);
+
+        assert_eq!(vec![0.5, 1.0, -1.0, -1.0, 0.5, 0.0, 0.0], out);
+    }
+
+    #[test]
+    fn pattern_test() {
+        // At 4000 bits/sec and a 4kHz sample rate, every sample is one bit of the pattern.
+        let mut synth = AudioSynth::new(4000);
+        synth.set_volume(1.0);
+
+        let mut pattern = [0u8; PATTERN_SZ];
+        pattern[0] = 0b_1010_0000;
+        synth.set_pattern(Some(pattern));
+        assert_eq!(4000.0, synth.pattern_rate());
+
+        let mut out = Vec::new();
+        synth.render_samples(true, 16, &mut out);
+        let levels: Vec<bool> = out.iter().map(|sample| *sample > 0.0).collect();
+
+        assert_eq!(vec![true, false, true, false, false], levels[0..5].to_vec());
+        assert!(levels[8..16].iter().all(|high| !high));
+
+        synth.set_pitch(DEFAULT_PITCH + 48);
+        assert_eq!(8000.0, synth.pattern_rate());
+    }
+
+    #[test]
+    fn write_wav_test() {
+        let wav = write_wav(&[0.0, 1.0, -1.0], 8000);
+
+        assert_eq!(44 + 6, wav.len());
+        assert_eq!(b"RIFF", &wav[0..4]);
+        assert_eq!(b"WAVE", &wav[8..12]);
+        assert_eq!(8000u32.to_le_bytes(), wav[24..28]);
+        assert_eq!(6u32.to_le_bytes(), wav[40..44]);
+        assert_eq!([0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80], wav[44..50]);
+    }
+}
@@ -0,0 +1,615 @@
+use std::fs;
+use std::io::{self, Write};
+use std::process;
+
+use chip_8_core::interpreter::Chip8Interpreter;
+use chip_8_core::audio::{self, AudioSynth};
+use chip_8_core::bundle::RomBundle;
+use chip_8_core::gif::GifRecorder;
+use chip_8_core::infer;
+use chip_8_core::fault::{FaultPolicies, FaultPolicy};
+use chip_8_core::keycodes::KeyCodes;
+use chip_8_core::memory_map::MapMode;
+use chip_8_core::movie::Movie;
+use chip_8_core::octo::OctoCartridge;
+use chip_8_core::patch;
+use chip_8_core::opcode::{self, OpCode};
+use chip_8_core::platform_adapter::PlatformAdapter;
+use chip_8_core::profile::Profile;
+use chip_8_core::quirk_flags::QuirkFlags;
+use chip_8_core::rng::{Rng, RngKind};
+use chip_8_core::render::{self, Frame, Palette, RenderOptions};
+use chip_8_core::romdb::{RomDb, RomInfo};
+use chip_8_core::smc::SmcTracker;
+use chip_8_core::timing::VIP_CYCLES_PER_FRAME;
+
+const USAGE: &str = "usage: chip8-run [options] <rom>
+
+options:
+    --profile <name>     vip, vip-hires, chip-8x, schip, megachip or amiga, or auto to guess it and its
+                         quirks from the ROM's code when it isn't known (default: the ROM database's profile
+                         for known ROMs, vip-hires for ROMs starting with 1260, otherwise schip)
+    --patch <file>       apply an IPS or BPS patch to the ROM first; repeat to apply several in order
+    --rom-db <file>      add a ROM database in the community programs.json format to the built-in one, which
+                         only knows the IBM logo (default: the CHIP8_ROM_DB environment variable, if set)
+    --quirks <list>      comma-separated quirks added to the profile: 8xy6,8xye,fx1e,fx55,fx65
+    --tick-rate <n>      instructions per second (default: 600, or the ROM database's speed for known ROMs)
+    --timing <model>     ticks, or vip to time instructions in COSMAC VIP machine cycles, ignoring the tick
+                         rate (default: ticks)
+    --frames <n>         number of 60Hz frames to run (default: 600)
+    --input <file>       keypad script, one \"<frame> <key|->\" entry per line. Prefix the key with 2: for
+                         CHIP-8X's second keypad
+    --seed <n>           seed for the random number generator (default: 1)
+    --rng <kind>         random number generator: xorshift or vip (default: xorshift)
+    --faults <policy>    what to do on memory, stack and opcode faults: trap, ignore or wrap (default: trap)
+    --memory <mode>      permissive, or strict to stop on writes to the font, interpreter area or ROM and on
+                         jumps outside the ROM (default: permissive)
+    --format <fmt>       display dump format: ascii, pbm, ppm or png (default: ascii)
+    --scale <n>          integer scaling for ppm and png dumps (default: 1)
+    --palette <name>     mono, octo, lcd, hotdog, gray, cga0 or cga1 (default: mono)
+    --output <file>      write the display dump to a file instead of stdout
+    --gif <file>         record every frame into an animated GIF
+    --wav <file>         record the sound timer's beeper into a WAV file
+    --record-movie <file>  record the keypad input of the run into a movie
+    --write-bundle <file>  write the ROM and the settings it ran with into a ROM bundle, which can be run in
+                         place of the ROM with those settings as defaults
+    --smc-log <file>     log self-modifying code: writes into instructions that have run, and runs of
+                         instructions that were written
+    --movie <file>       replay a movie instead of running an input script. The movie's quirks, seed and tick
+                         rate are used, and it runs for the movie's length unless --frames is shorter";
+
+const WAV_SAMPLE_RATE: u32 = 44100;
+
+struct HeadlessPlatform;
+
+impl PlatformAdapter for HeadlessPlatform {
+    fn play_sound(&mut self) {}
+
+    fn pause_sound(&mut self) {}
+}
+
+#[derive(PartialEq)]
+enum DumpFormat {
+    Ascii,
+    Pbm,
+    Ppm,
+    Png,
+}
+
+struct Options {
+    rom_path: String,
+    profile: Option<Profile>,
+    quirks: QuirkFlags,
+    tick_rate: u64,
+    vip_timing: bool,
+    frames: u64,
+    input_path: Option<String>,
+    seed: u32,
+    rng_kind: RngKind,
+    fault_policies: FaultPolicies,
+    map_mode: MapMode,
+    format: DumpFormat,
+    render_options: RenderOptions,
+    output_path: Option<String>,
+    gif_path: Option<String>,
+    wav_path: Option<String>,
+    record_movie_path: Option<String>,
+    smc_log_path: Option<String>,
+    movie_path: Option<String>,
+    frames_given: bool,
+    infer_profile: bool,
+    palette_given: bool,
+    write_bundle_path: Option<String>,
+    tick_rate_given: bool,
+    rom_db_path: Option<String>,
+    patch_paths: Vec<String>,
+}
+
+struct InputEvent {
+    frame: u64,
+    keypad: u8,
+    key: Option<KeyCodes>,
+}
+
+enum ExitReason {
+    FramesElapsed,
+    InfiniteLoop(u16),
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("chip8-run: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(msg) = run(&options) {
+        eprintln!("chip8-run: {}", msg);
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: String::new(),
+        profile: None,
+        quirks: QuirkFlags::NONE,
+        tick_rate: 600,
+        vip_timing: false,
+        frames: 600,
+        input_path: None,
+        seed: 1,
+        rng_kind: RngKind::XorShift,
+        fault_policies: FaultPolicies::default(),
+        map_mode: MapMode::Permissive,
+        format: DumpFormat::Ascii,
+        render_options: RenderOptions::new(),
+        output_path: None,
+        gif_path: None,
+        wav_path: None,
+        record_movie_path: None,
+        smc_log_path: None,
+        movie_path: None,
+        frames_given: false,
+        infer_profile: false,
+        palette_given: false,
+        write_bundle_path: None,
+        tick_rate_given: false,
+        rom_db_path: None,
+        patch_paths: Vec::new(),
+    };
+
+    let mut rom_path = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            if rom_path.is_some() {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+
+            rom_path = Some(arg.clone());
+            continue;
+        }
+
+        if arg == "--help" {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+
+        let val = iter.next().ok_or(format!("missing value for {}", arg))?;
+
+        match arg.as_str() {
+            "--profile" if val == "auto" => options.infer_profile = true,
+            "--profile" => {
+                options.profile = Some(Profile::from_name(val).ok_or(format!("unknown profile '{}'", val))?);
+            }
+            "--quirks" => {
+                for name in val.split(',').filter(|name| !name.is_empty()) {
+                    options.quirks |= QuirkFlags::from_name(name).ok_or(format!("unknown quirk '{}'", name))?;
+                }
+            }
+            "--tick-rate" => {
+                options.tick_rate = parse_num(arg, val)?;
+                options.tick_rate_given = true;
+            }
+            "--timing" => {
+                options.vip_timing = match val.as_str() {
+                    "ticks" => false,
+                    "vip" => true,
+                    _ => return Err(format!("unknown timing model '{}'", val)),
+                };
+            }
+            "--frames" => {
+                options.frames = parse_num(arg, val)?;
+                options.frames_given = true;
+            }
+            "--input" => options.input_path = Some(val.clone()),
+            "--rom-db" => options.rom_db_path = Some(val.clone()),
+            "--patch" => options.patch_paths.push(val.clone()),
+            "--write-bundle" => options.write_bundle_path = Some(val.clone()),
+            "--seed" => options.seed = parse_num(arg, val)? as u32,
+            "--faults" => {
+                // Hook needs a host to answer it, which the headless runner doesn't have.
+                let policy = match FaultPolicy::from_name(val) {
+                    Some(FaultPolicy::Hook) | None => return Err(format!("unknown fault policy '{}'", val)),
+                    Some(policy) => policy,
+                };
+                options.fault_policies = FaultPolicies::all(policy);
+            }
+            "--memory" => options.map_mode = MapMode::from_name(val).ok_or(format!("unknown memory mode '{}'", val))?,
+            "--rng" => options.rng_kind = RngKind::from_name(val).ok_or(format!("unknown rng '{}'", val))?,
+            "--format" => {
+                options.format = match val.as_str() {
+                    "ascii" => DumpFormat::Ascii,
+                    "pbm" => DumpFormat::Pbm,
+                    "ppm" => DumpFormat::Ppm,
+                    "png" => DumpFormat::Png,
+                    _ => return Err(format!("unknown format '{}'", val)),
+                }
+            }
+            "--scale" => options.render_options.scale = parse_num(arg, val)? as usize,
+            "--palette" => {
+                options.render_options.palette = Palette::from_name(val).ok_or(format!("unknown palette '{}'", val))?;
+                options.palette_given = true;
+            }
+            "--output" => options.output_path = Some(val.clone()),
+            "--gif" => options.gif_path = Some(val.clone()),
+            "--wav" => options.wav_path = Some(val.clone()),
+            "--record-movie" => options.record_movie_path = Some(val.clone()),
+            "--smc-log" => options.smc_log_path = Some(val.clone()),
+            "--movie" => options.movie_path = Some(val.clone()),
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    // Movies replay frame by frame at the tick rate they were recorded with.
+    if options.vip_timing && (options.movie_path.is_some() || options.record_movie_path.is_some()) {
+        return Err(String::from("--timing vip can't be used with movies"));
+    }
+
+    // Nor do they record the fault policies, so a movie made under anything but Trap wouldn't replay the same.
+    if options.fault_policies != FaultPolicies::default() && (options.movie_path.is_some() || options.record_movie_path.is_some()) {
+        return Err(String::from("--faults can't be used with movies"));
+    }
+
+    // Likewise the memory map mode, which strict recordings depend on to stop where they did.
+    if options.map_mode != MapMode::Permissive && (options.movie_path.is_some() || options.record_movie_path.is_some()) {
+        return Err(String::from("--memory can't be used with movies"));
+    }
+
+    options.rom_path = rom_path.ok_or("no ROM given")?;
+    Ok(options)
+}
+
+fn parse_num(arg: &str, val: &str) -> Result<u64, String> {
+    val.parse::<u64>().map_err(|_| format!("invalid number '{}' for {}", val, arg))
+}
+
+fn parse_input_script(script: &str) -> Result<Vec<InputEvent>, String> {
+    let mut events = Vec::new();
+
+    for (line_idx, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let err = || format!("invalid input script entry on line {}: '{}'", line_idx + 1, line);
+        let mut fields = line.split_whitespace();
+
+        let frame = fields.next().and_then(|field| field.parse::<u64>().ok()).ok_or_else(err)?;
+        let mut key_field = fields.next().ok_or_else(err)?;
+        let mut keypad = 1;
+        if let Some(field) = key_field.strip_prefix("2:") {
+            key_field = field;
+            keypad = 2;
+        }
+
+        let key = match key_field {
+            "-" => None,
+            field => Some(parse_key(field).ok_or_else(err)?),
+        };
+
+        if fields.next().is_some() {
+            return Err(err());
+        }
+
+        events.push(InputEvent { frame, keypad, key });
+    }
+
+    // Entries may be written in any order, but later entries for the same frame win.
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+fn parse_key(field: &str) -> Option<KeyCodes> {
+    if field.len() != 1 {
+        return None;
+    }
+
+    u8::from_str_radix(field, 16).ok().and_then(KeyCodes::from_u8)
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let bytes = fs::read(&options.rom_path).map_err(|err| format!("could not read '{}': {}", options.rom_path, err))?;
+
+    // Bundles carry their own profile, quirks, speed and palette, which the options override. Octo cartridges are
+    // assembled, and their options carry over the same way.
+    let bundle = if OctoCartridge::is_cartridge(&bytes) {
+        let cartridge = OctoCartridge::from_gif(&bytes)
+            .map_err(|err| format!("invalid Octo cartridge '{}': {:?}", options.rom_path, err))?;
+        Some(cartridge.to_bundle().map_err(|err| format!("could not assemble '{}': {}", options.rom_path, err))?)
+    } else if RomBundle::is_bundle(&bytes) {
+        Some(RomBundle::from_bytes(&bytes).map_err(|err| format!("invalid bundle '{}': {:?}", options.rom_path, err))?)
+    } else {
+        None
+    };
+    let mut rom = bundle.as_ref().map(|bundle| bundle.rom.clone()).unwrap_or(bytes);
+
+    // Patches apply in the order given, before anything looks at the ROM.
+    for path in options.patch_paths.iter() {
+        let patch_bytes = fs::read(path).map_err(|err| format!("could not read '{}': {}", path, err))?;
+        rom = patch::apply(&rom, &patch_bytes).map_err(|err| format!("could not apply patch '{}': {:?}", path, err))?;
+    }
+
+    // Known ROMs get their profile, quirks and speed from the database unless they're given.
+    let mut rom_db = RomDb::embedded();
+    let rom_db_path = options.rom_db_path.clone().or_else(|| std::env::var("CHIP8_ROM_DB").ok());
+    if let Some(path) = &rom_db_path {
+        let json = fs::read_to_string(path).map_err(|err| format!("could not read '{}': {}", path, err))?;
+        rom_db.merge(RomDb::from_json(&json).map_err(|err| format!("invalid ROM database '{}': {}", path, err))?);
+    }
+    let known_rom = rom_db.lookup(&rom);
+    if let Some(info) = known_rom.filter(|info| info.profile.is_none()) {
+        eprintln!("'{}' is in the ROM database, but only for platforms that can't be emulated", info.title);
+    }
+
+    let bundle_profile = bundle.as_ref().and_then(|bundle| bundle.profile);
+    let given_profile = options.profile.or(bundle_profile).or_else(|| known_rom.and_then(|info| info.profile));
+    let inferred = if given_profile.is_none() && options.infer_profile { infer::infer(&rom).into_iter().next() } else { None };
+    let profile = given_profile
+        .or_else(|| inferred.as_ref().map(|candidate| candidate.profile))
+        .or_else(|| Profile::detect(&rom))
+        .unwrap_or(Profile::SChip);
+
+    let events = match &options.input_path {
+        Some(path) => {
+            let script = fs::read_to_string(path).map_err(|err| format!("could not read '{}': {}", path, err))?;
+            parse_input_script(&script)?
+        }
+        None => Vec::new(),
+    };
+
+    let playback = match &options.movie_path {
+        Some(path) => {
+            let bytes = fs::read(path).map_err(|err| format!("could not read '{}': {}", path, err))?;
+            Some(Movie::from_bytes(&bytes).map_err(|err| format!("invalid movie '{}': {:?}", path, err))?)
+        }
+        None => None,
+    };
+
+    let (mut interpreter, tick_rate, frame_limit) = match &playback {
+        Some(movie) => {
+            let interpreter = movie.start(HeadlessPlatform, rom.clone())
+                .map_err(|err| format!("could not play movie: {:?}", err))?;
+            let frame_limit = if options.frames_given { options.frames.min(movie.len() as u64) } else { movie.len() as u64 };
+
+            (interpreter, movie.header.tick_rate, frame_limit)
+        }
+        None => {
+            let mut interpreter = Chip8Interpreter::with_memory_size(HeadlessPlatform, rom.clone(), profile.memory_size())
+                .map_err(|err| format!("could not load ROM: {}", err))?;
+            interpreter.apply_profile(profile).map_err(|err| format!("could not load ROM: {}", err))?;
+            match (options.profile, &bundle, known_rom) {
+                (None, Some(bundle), _) if bundle.profile.is_some() || bundle.quirks.is_some() => {
+                    if let Some(quirks) = bundle.quirks {
+                        interpreter.quirks = quirks | (interpreter.quirks & (QuirkFlags::CHIP8X | QuirkFlags::MEGACHIP));
+                    }
+                }
+                (None, _, Some(info)) if info.profile.is_some() => interpreter.quirks = info.quirks,
+                _ => if let Some(candidate) = &inferred {
+                    interpreter.quirks = candidate.quirks;
+                },
+            }
+            interpreter.quirks |= options.quirks;
+            interpreter.rng = Rng::new(options.rng_kind, options.seed);
+            if options.vip_timing {
+                interpreter.enable_vip_timing();
+            }
+            interpreter.fault_policies = options.fault_policies;
+            interpreter.memory_map.mode = options.map_mode;
+
+            let suggested_tick_rate = bundle.as_ref().and_then(|bundle| bundle.tick_rate)
+                .or_else(|| known_rom.and_then(RomInfo::tick_rate));
+            let tick_rate = match suggested_tick_rate {
+                Some(tick_rate) if !options.tick_rate_given => tick_rate,
+                _ => options.tick_rate,
+            };
+
+            (interpreter, tick_rate, options.frames)
+        }
+    };
+
+    let mut render_options = options.render_options;
+    if let (Some(palette), false) = (bundle.as_ref().and_then(|bundle| bundle.palette), options.palette_given) {
+        render_options.palette = palette;
+    }
+
+    if let Some(path) = &options.write_bundle_path {
+        // Everything this run was set up with, so the bundle runs the same way without any options.
+        let mut out = bundle.clone().unwrap_or_else(|| RomBundle::new(rom.clone()));
+        if let (Some(info), None) = (known_rom, &bundle) {
+            out.title = info.title.clone();
+            out.author = info.authors.join(", ");
+            out.keys = info.keys.clone();
+        }
+        out.profile = Some(profile);
+        out.quirks = Some(interpreter.quirks);
+        out.tick_rate = Some(tick_rate);
+        out.palette = Some(render_options.palette);
+
+        fs::write(path, out.to_bytes()).map_err(|err| format!("could not write '{}': {}", path, err))?;
+    }
+
+    if options.smc_log_path.is_some() {
+        interpreter.smc = Some(SmcTracker::new());
+    }
+
+    let mut recording = options.record_movie_path.as_ref().map(|_| {
+        Movie::new(&rom, interpreter.quirks, Some(profile), interpreter.rng, tick_rate)
+    });
+
+    let mut recorder = options.gif_path.as_ref()
+        .map(|_| GifRecorder::new(render_options.palette, render_options.scale));
+
+    let mut synth = options.wav_path.as_ref().map(|_| AudioSynth::new(WAV_SAMPLE_RATE));
+    let mut samples = Vec::new();
+
+    let mut next_event = 0;
+    let mut frame = 0;
+    let mut exit_reason = ExitReason::FramesElapsed;
+    let mut run_err = None;
+
+    while frame < frame_limit {
+        while next_event < events.len() && events[next_event].frame <= frame {
+            let event = &events[next_event];
+            match event.keypad {
+                2 => interpreter.key_press_2 = event.key,
+                _ => interpreter.key_press = event.key,
+            }
+            next_event += 1;
+        }
+
+        if let Some(movie) = &playback {
+            interpreter.key_press = movie.frames[frame as usize];
+            interpreter.key_press_2 = movie.key_2(frame as usize);
+        }
+
+        let (key, key_2) = (interpreter.key_press, interpreter.key_press_2);
+        let result = match recording.as_mut() {
+            Some(movie) => movie.record_frame_keypads(&mut interpreter, key, key_2),
+            None if options.vip_timing => interpreter.run_cycles(VIP_CYCLES_PER_FRAME),
+            None => interpreter.run_frame(tick_rate),
+        };
+
+        if let Err(err) = result {
+            run_err = Some(err);
+            break;
+        }
+
+        frame += 1;
+
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record(&Frame::from_display(interpreter.active_display()));
+        }
+
+        if let Some(synth) = synth.as_mut() {
+            synth.render_interpreter_frame(&interpreter, &mut samples);
+        }
+
+        if let Some(addr) = jump_to_self(&interpreter) {
+            exit_reason = ExitReason::InfiniteLoop(addr);
+            break;
+        }
+    }
+
+    let display = Frame::from_display(interpreter.active_display());
+    let render_image = || {
+        // CHIP-8X and MEGA-CHIP8 programs choose their own colors, so the palette doesn't apply.
+        if let Some(mega) = &interpreter.mega {
+            render::render_mega(mega, render_options.scale)
+        } else if interpreter.quirks.contains(QuirkFlags::CHIP8X) {
+            render::render_chip8x(&display, &interpreter.color_map, interpreter.background_color, render_options.scale)
+        } else {
+            render::render(&display, &render_options)
+        }
+    };
+    let dump = match options.format {
+        DumpFormat::Ascii => display_as_ascii(&display),
+        DumpFormat::Pbm => display.to_pbm(),
+        DumpFormat::Ppm => render_image().to_ppm(),
+        DumpFormat::Png => render_image().to_png(),
+    };
+
+    match &options.output_path {
+        Some(path) => fs::write(path, &dump).map_err(|err| format!("could not write '{}': {}", path, err))?,
+        None => io::stdout().write_all(&dump).map_err(|err| err.to_string())?,
+    }
+
+    if let (Some(recorder), Some(path)) = (&recorder, &options.gif_path) {
+        let gif = recorder.finish().map_err(|err| format!("could not record '{}': {:?}", path, err))?;
+        fs::write(path, gif).map_err(|err| format!("could not write '{}': {}", path, err))?;
+    }
+
+    if let (Some(movie), Some(path)) = (recording.as_mut(), &options.record_movie_path) {
+        movie.seal(&interpreter);
+        fs::write(path, movie.to_bytes()).map_err(|err| format!("could not write '{}': {}", path, err))?;
+    }
+
+    if let (Some(smc), Some(path)) = (&interpreter.smc, &options.smc_log_path) {
+        let log: String = smc.events().iter().map(|event| format!("{}\n", event)).collect();
+        fs::write(path, log).map_err(|err| format!("could not write '{}': {}", path, err))?;
+    }
+
+    if let Some(path) = &options.wav_path {
+        fs::write(path, audio::write_wav(&samples, WAV_SAMPLE_RATE))
+            .map_err(|err| format!("could not write '{}': {}", path, err))?;
+    }
+
+    // Keep stdout clean for the display when it's binary, so the register dump goes to stderr.
+    let state = format_state(&interpreter, frame);
+    if options.format != DumpFormat::Ascii && options.output_path.is_none() {
+        io::stderr().write_all(state.as_bytes()).map_err(|err| err.to_string())?;
+    } else {
+        io::stdout().write_all(state.as_bytes()).map_err(|err| err.to_string())?;
+    }
+
+    if let Some(movie) = &playback {
+        if run_err.is_none() && frame == movie.len() as u64 && movie.verify_final_state(&interpreter).is_err() {
+            return Err(String::from("movie desynced: the final state doesn't match the recording"));
+        }
+    }
+
+    match run_err {
+        Some(err) => Err(format!("{} on frame {}", err, frame)),
+        None => {
+            match exit_reason {
+                ExitReason::FramesElapsed => eprintln!("stopped after {} frames", frame),
+                ExitReason::InfiniteLoop(addr) => eprintln!("infinite loop at {:#05X} after {} frames", addr, frame),
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn jump_to_self<T: PlatformAdapter>(interpreter: &Chip8Interpreter<T>) -> Option<u16> {
+    // Most ROMs finish by jumping to the current instruction forever, since plain CHIP-8 has no exit instruction.
+    let pc = interpreter.pc as usize;
+    if pc + 1 >= interpreter.memory.len() {
+        return None;
+    }
+
+    let instr = ((interpreter.memory[pc] as u16) << 8) | interpreter.memory[pc + 1] as u16;
+    match opcode::decode(instr, interpreter.quirks).opcode {
+        OpCode::OpCode1nnn(addr) if addr == interpreter.pc => Some(addr),
+        _ => None,
+    }
+}
+
+fn display_as_ascii(frame: &Frame) -> Vec<u8> {
+    let mut out = String::with_capacity((frame.width + 1) * frame.height);
+
+    for row in frame.pixels.chunks(frame.width) {
+        for pixel in row.iter() {
+            out.push(if *pixel != 0 { '#' } else { '.' });
+        }
+        out.push('\n');
+    }
+
+    out.into_bytes()
+}
+
+fn format_state<T: PlatformAdapter>(interpreter: &Chip8Interpreter<T>, frame: u64) -> String {
+    let mut out = String::new();
+
+    out.push_str(&format!("frame: {}\n", frame));
+    out.push_str(&format!("pc: {:#05X}  i: {:#05X}  dt: {}  st: {}\n",
+        interpreter.pc, interpreter.i_reg, interpreter.delay_timer.current_val, interpreter.sound_timer.current_val));
+
+    for (idx, val) in interpreter.v_regs.iter().enumerate() {
+        out.push_str(&format!("V{:X}: {:#04X}", idx, val));
+        out.push(if idx % 8 == 7 { '\n' } else { ' ' });
+    }
+
+    let stack = interpreter.stack.snapshot().unwrap_or_default();
+    let stack: Vec<String> = stack.iter().map(|addr| format!("{:#05X}", addr)).collect();
+    out.push_str(&format!("stack: [{}]\n", stack.join(", ")));
+
+    out
+}
@@ -0,0 +1,364 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::{self, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chip_8_core::interpreter::Chip8Interpreter;
+use chip_8_core::keycodes::KeyCodes;
+use chip_8_core::opcode;
+use chip_8_core::platform_adapter::PlatformAdapter;
+use chip_8_core::profile::Profile;
+use chip_8_core::quirk_flags::QuirkFlags;
+use chip_8_core::rng::{Rng, RngKind};
+
+const USAGE: &str = "usage: chip8-term [options] <rom>
+
+options:
+    --profile <name>     vip, vip-hires, chip-8x, schip, megachip or amiga (default: vip-hires for ROMs
+                         starting with 1260, otherwise schip)
+    --quirks <list>      comma-separated quirks added to the profile: 8xy6,8xye,fx1e,fx55,fx65
+    --tick-rate <n>      instructions per second (default: 600)
+    --panel              show registers, stack and disassembly next to the display
+
+keys:
+    1 2 3 4              1 2 3 C
+    q w e r      ->      4 5 6 D
+    a s d f              7 8 9 E
+    z x c v              A 0 B F
+
+    Esc or Ctrl-C quits.";
+
+const FRAME_DURATION: Duration = Duration::from_micros(16_667);
+
+// Terminals only report key presses (and auto-repeats), never releases, so a key is held for this many frames.
+const KEY_HOLD_FRAMES: u32 = 8;
+
+const DISASSEMBLY_BEFORE: u16 = 3;
+const DISASSEMBLY_AFTER: u16 = 6;
+
+const KEY_ESC: u8 = 0x1B;
+const KEY_CTRL_C: u8 = 0x03;
+
+struct TerminalPlatform;
+
+impl PlatformAdapter for TerminalPlatform {
+    fn play_sound(&mut self) {
+        // The terminal bell is the only sound a terminal can make.
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x07");
+        let _ = stdout.flush();
+    }
+
+    fn pause_sound(&mut self) {}
+}
+
+struct Options {
+    rom_path: String,
+    profile: Option<Profile>,
+    quirks: QuirkFlags,
+    tick_rate: u64,
+    show_panel: bool,
+}
+
+struct RawTerminal {
+    saved_settings: String,
+}
+
+impl RawTerminal {
+    fn enable() -> Result<Self, String> {
+        let saved_settings = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+
+        // Switch to the alternate screen and hide the cursor.
+        print!("\x1b[?1049h\x1b[?25l");
+
+        Ok(RawTerminal { saved_settings: saved_settings.trim().to_string() })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        let _ = stty(&[&self.saved_settings]);
+    }
+}
+
+fn stty(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .map_err(|err| format!("could not run stty: {}", err))?;
+
+    if !output.status.success() {
+        return Err("stdin is not a terminal".to_string());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("chip8-term: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(msg) = run(&options) {
+        eprintln!("chip8-term: {}", msg);
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: String::new(),
+        profile: None,
+        quirks: QuirkFlags::NONE,
+        tick_rate: 600,
+        show_panel: false,
+    };
+
+    let mut rom_path = None;
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            if rom_path.is_some() {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+
+            rom_path = Some(arg.clone());
+            continue;
+        }
+
+        match arg.as_str() {
+            "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            "--panel" => {
+                options.show_panel = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        let val = iter.next().ok_or(format!("missing value for {}", arg))?;
+
+        match arg.as_str() {
+            "--profile" => {
+                options.profile = Some(Profile::from_name(val).ok_or(format!("unknown profile '{}'", val))?);
+            }
+            "--quirks" => {
+                for name in val.split(',').filter(|name| !name.is_empty()) {
+                    options.quirks |= QuirkFlags::from_name(name).ok_or(format!("unknown quirk '{}'", name))?;
+                }
+            }
+            "--tick-rate" => {
+                options.tick_rate = val.parse::<u64>().map_err(|_| format!("invalid number '{}' for {}", val, arg))?;
+            }
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    options.rom_path = rom_path.ok_or("no ROM given")?;
+    Ok(options)
+}
+
+fn map_key(byte: u8) -> Option<KeyCodes> {
+    // The left-hand 4x4 block of a QWERTY keyboard mirrors the layout of the COSMAC VIP keypad.
+    match byte.to_ascii_lowercase() {
+        b'1' => Some(KeyCodes::Key1),
+        b'2' => Some(KeyCodes::Key2),
+        b'3' => Some(KeyCodes::Key3),
+        b'4' => Some(KeyCodes::KeyC),
+        b'q' => Some(KeyCodes::Key4),
+        b'w' => Some(KeyCodes::Key5),
+        b'e' => Some(KeyCodes::Key6),
+        b'r' => Some(KeyCodes::KeyD),
+        b'a' => Some(KeyCodes::Key7),
+        b's' => Some(KeyCodes::Key8),
+        b'd' => Some(KeyCodes::Key9),
+        b'f' => Some(KeyCodes::KeyE),
+        b'z' => Some(KeyCodes::KeyA),
+        b'x' => Some(KeyCodes::Key0),
+        b'c' => Some(KeyCodes::KeyB),
+        b'v' => Some(KeyCodes::KeyF),
+        _ => None,
+    }
+}
+
+fn spawn_input_reader() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for byte in stdin.lock().bytes() {
+            match byte {
+                Ok(byte) => {
+                    if sender.send(byte).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+
+    receiver
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let rom = fs::read(&options.rom_path).map_err(|err| format!("could not read '{}': {}", options.rom_path, err))?;
+    let profile = options.profile.or_else(|| Profile::detect(&rom)).unwrap_or(Profile::SChip);
+
+    let mut interpreter = Chip8Interpreter::with_memory_size(TerminalPlatform, rom, profile.memory_size())
+        .map_err(|err| format!("could not load ROM: {}", err))?;
+    interpreter.apply_profile(profile).map_err(|err| format!("could not load ROM: {}", err))?;
+    interpreter.quirks |= options.quirks;
+
+    // Every session should play differently, so seed from the clock.
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.subsec_nanos()).unwrap_or(0);
+    interpreter.rng = Rng::new(RngKind::XorShift, seed);
+
+    let terminal = RawTerminal::enable()?;
+    let input = spawn_input_reader();
+
+    let mut held_frames = 0;
+    let mut drawn_version = None;
+    let mut result = Ok(());
+
+    'frames: loop {
+        let frame_start = Instant::now();
+
+        while let Ok(byte) = input.try_recv() {
+            if byte == KEY_ESC || byte == KEY_CTRL_C {
+                break 'frames;
+            }
+
+            if let Some(keycode) = map_key(byte) {
+                interpreter.key_press = Some(keycode);
+                held_frames = KEY_HOLD_FRAMES;
+            }
+        }
+
+        if held_frames > 0 {
+            held_frames -= 1;
+        } else {
+            interpreter.key_press = None;
+        }
+
+        if let Err(err) = interpreter.run_frame(options.tick_rate) {
+            result = Err(err.to_string());
+            break;
+        }
+
+        // The panel changes every frame, but without it there's only something to draw when the display has.
+        if options.show_panel || drawn_version != Some(interpreter.display_version()) {
+            let screen = render(&interpreter, options.show_panel);
+            let mut stdout = io::stdout();
+            stdout.write_all(screen.as_bytes()).map_err(|err| err.to_string())?;
+            stdout.flush().map_err(|err| err.to_string())?;
+
+            drawn_version = Some(interpreter.display_version());
+        }
+
+        if let Some(remaining) = FRAME_DURATION.checked_sub(frame_start.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+
+    drop(terminal);
+    result
+}
+
+fn render<T: PlatformAdapter>(interpreter: &Chip8Interpreter<T>, show_panel: bool) -> String {
+    let panel = if show_panel { build_panel(interpreter) } else { Vec::new() };
+
+    // Move to the top-left corner and redraw everything in place, which avoids flicker from clearing the screen.
+    let mut out = String::from("\x1b[H");
+
+    // Each character cell covers two display rows: the upper half-block is the top pixel and the lower the bottom.
+    let display = interpreter.active_display();
+    let cell_rows = display.height().div_ceil(2);
+
+    for cell_row in 0..cell_rows {
+        let y = cell_row * 2;
+
+        for x in 0..display.width() {
+            let top = display.get(x, y) != 0;
+            let bottom = y + 1 < display.height() && display.get(x, y + 1) != 0;
+
+            out.push(match (top, bottom) {
+                (true, true) => '\u{2588}',
+                (true, false) => '\u{2580}',
+                (false, true) => '\u{2584}',
+                (false, false) => ' ',
+            });
+        }
+
+        if let Some(line) = panel.get(cell_row) {
+            out.push_str(" \u{2502} ");
+            out.push_str(line);
+        }
+
+        // Raw mode disables the implicit carriage return, and stale panel text needs clearing.
+        out.push_str("\x1b[K\r\n");
+    }
+
+    // Panel lines that don't fit next to the display go below it.
+    for line in panel.iter().skip(cell_rows) {
+        out.push_str(&" ".repeat(display.width()));
+        out.push_str(" \u{2502} ");
+        out.push_str(line);
+        out.push_str("\x1b[K\r\n");
+    }
+
+    out
+}
+
+fn build_panel<T: PlatformAdapter>(interpreter: &Chip8Interpreter<T>) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    lines.push(format!("PC {:#05X}  I {:#05X}  DT {:3}  ST {:3}",
+        interpreter.pc, interpreter.i_reg, interpreter.delay_timer.current_val, interpreter.sound_timer.current_val));
+
+    for (row_idx, regs) in interpreter.v_regs.chunks(4).enumerate() {
+        let regs: Vec<String> = regs.iter().enumerate()
+            .map(|(idx, val)| format!("V{:X} {:02X}", row_idx * 4 + idx, val))
+            .collect();
+        lines.push(regs.join("  "));
+    }
+
+    let stack = interpreter.stack.snapshot().unwrap_or_default();
+    let stack: Vec<String> = stack.iter().map(|addr| format!("{:03X}", addr)).collect();
+    lines.push(format!("stack [{}]", stack.join(" ")));
+    lines.push(String::new());
+
+    let start = interpreter.pc.saturating_sub(DISASSEMBLY_BEFORE * 2);
+    let end = interpreter.pc.saturating_add(DISASSEMBLY_AFTER * 2);
+
+    for addr in (start..=end).step_by(2) {
+        let idx = addr as usize;
+        if idx + 1 >= interpreter.memory.len() {
+            break;
+        }
+
+        let instr = ((interpreter.memory[idx] as u16) << 8) | interpreter.memory[idx + 1] as u16;
+        let decoded = opcode::decode(instr, interpreter.quirks);
+        let marker = if addr == interpreter.pc { '>' } else { ' ' };
+        let mnemonic = if decoded.mnemonic.is_empty() { "???" } else { decoded.mnemonic.as_str() };
+
+        lines.push(format!("{} {:03X}  {:04X}  {}", marker, addr, instr, mnemonic));
+    }
+
+    lines
+}
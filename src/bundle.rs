@@ -0,0 +1,287 @@
+use crate::bytes::{ByteReader, Truncated};
+use crate::interpreter::{Chip8Interpreter, InterpreterErr, MEM_SZ};
+use crate::keycodes::KeyCodes;
+use crate::platform_adapter::PlatformAdapter;
+use crate::profile::Profile;
+use crate::quirk_flags::QuirkFlags;
+use crate::render::{Palette, PALETTE_SZ};
+
+pub const MAGIC: &[u8; 4] = b"C8RB";
+const VERSION: u8 = 1;
+
+// Chunk tags. Readers skip chunks they don't know, so new ones can be added without a version bump.
+const ROM_TAG: &[u8; 4] = b"ROM ";
+const TITLE_TAG: &[u8; 4] = b"TITL";
+const AUTHOR_TAG: &[u8; 4] = b"AUTH";
+const PROFILE_TAG: &[u8; 4] = b"PROF";
+const QUIRKS_TAG: &[u8; 4] = b"QURK";
+const TICK_RATE_TAG: &[u8; 4] = b"TICK";
+const KEYS_TAG: &[u8; 4] = b"KEYS";
+const PALETTE_TAG: &[u8; 4] = b"PALT";
+const OCTO_TAG: &[u8; 4] = b"OCTO";
+
+#[derive(Debug, PartialEq)]
+pub enum BundleErr {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    MissingRom,
+    InvalidText,
+    InvalidQuirks(u8),
+    InvalidKey(u8),
+    UnknownProfile(String),
+    Interpreter(InterpreterErr),
+}
+
+impl From<Truncated> for BundleErr {
+    fn from(_: Truncated) -> BundleErr {
+        BundleErr::Truncated
+    }
+}
+
+// A ROM together with everything needed to run it as intended.
+//
+// The file is the magic and version followed by chunks, each a four-byte tag, a little-endian u32 length and the
+// payload. Only the ROM chunk is required. Octo's cartridge options are kept as they are, for tools that understand
+// them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomBundle {
+    pub rom: Vec<u8>,
+    pub title: String,
+    pub author: String,
+    pub profile: Option<Profile>,
+    pub quirks: Option<QuirkFlags>, // In place of the profile's, though the profile still picks the instruction set.
+    pub tick_rate: Option<u64>,
+    pub keys: Vec<(String, KeyCodes)>, // What each key does, such as "up" or "a".
+    pub palette: Option<Palette>,
+    pub octo_options: Vec<(String, String)>,
+}
+
+impl RomBundle {
+    pub fn new(rom: Vec<u8>) -> Self {
+        RomBundle {
+            rom,
+            title: String::new(),
+            author: String::new(),
+            profile: None,
+            quirks: None,
+            tick_rate: None,
+            keys: Vec::new(),
+            palette: None,
+            octo_options: Vec::new(),
+        }
+    }
+
+    pub fn is_bundle(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    pub fn load<T: PlatformAdapter>(&self, platform_adapter: T) -> Result<Chip8Interpreter<T>, BundleErr> {
+        // Set up an interpreter with the bundle's profile and quirks. The tick rate, keys and palette are up to the
+        // frontend.
+        let mem_sz = self.profile.map(|profile| profile.memory_size()).unwrap_or(MEM_SZ);
+        let mut interpreter = Chip8Interpreter::with_memory_size(platform_adapter, self.rom.clone(), mem_sz)
+            .map_err(BundleErr::Interpreter)?;
+
+        if let Some(profile) = self.profile {
+            interpreter.apply_profile(profile).map_err(BundleErr::Interpreter)?;
+        }
+
+        if let Some(quirks) = self.quirks {
+            interpreter.quirks = quirks | (interpreter.quirks & (QuirkFlags::CHIP8X | QuirkFlags::MEGACHIP));
+        }
+
+        Ok(interpreter)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+
+        write_chunk(&mut out, ROM_TAG, &self.rom);
+
+        if !self.title.is_empty() {
+            write_chunk(&mut out, TITLE_TAG, self.title.as_bytes());
+        }
+
+        if !self.author.is_empty() {
+            write_chunk(&mut out, AUTHOR_TAG, self.author.as_bytes());
+        }
+
+        if let Some(profile) = self.profile {
+            write_chunk(&mut out, PROFILE_TAG, profile.name().as_bytes());
+        }
+
+        if let Some(quirks) = self.quirks {
+            write_chunk(&mut out, QUIRKS_TAG, &[quirks.bits()]);
+        }
+
+        if let Some(tick_rate) = self.tick_rate {
+            write_chunk(&mut out, TICK_RATE_TAG, &tick_rate.to_le_bytes());
+        }
+
+        if !self.keys.is_empty() {
+            // The key, then its length-prefixed name.
+            let mut payload = Vec::new();
+            for (name, key) in self.keys.iter() {
+                payload.push(*key as u8);
+                write_text(&mut payload, name);
+            }
+            write_chunk(&mut out, KEYS_TAG, &payload);
+        }
+
+        if let Some(palette) = &self.palette {
+            write_chunk(&mut out, PALETTE_TAG, &palette.colors.concat());
+        }
+
+        if !self.octo_options.is_empty() {
+            let mut payload = Vec::new();
+            for (name, val) in self.octo_options.iter() {
+                write_text(&mut payload, name);
+                write_text(&mut payload, val);
+            }
+            write_chunk(&mut out, OCTO_TAG, &payload);
+        }
+
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<RomBundle, BundleErr> {
+        let mut reader = ByteReader::new(bytes);
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(BundleErr::BadMagic);
+        }
+
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(BundleErr::UnsupportedVersion(version));
+        }
+
+        let mut rom = None;
+        let mut bundle = RomBundle::new(Vec::new());
+
+        while !reader.is_empty() {
+            let tag: [u8; 4] = reader.array()?;
+            let len = u32::from_le_bytes(reader.array()?) as usize;
+            let mut payload = ByteReader::new(reader.take(len)?);
+
+            match &tag {
+                ROM_TAG => rom = Some(payload.bytes.to_vec()),
+                TITLE_TAG => bundle.title = to_text(payload.bytes)?,
+                AUTHOR_TAG => bundle.author = to_text(payload.bytes)?,
+                PROFILE_TAG => {
+                    let name = to_text(payload.bytes)?;
+                    bundle.profile = Some(Profile::from_name(&name).ok_or(BundleErr::UnknownProfile(name))?);
+                }
+                QUIRKS_TAG => {
+                    let bits = payload.u8()?;
+                    bundle.quirks = Some(QuirkFlags::from_bits(bits).ok_or(BundleErr::InvalidQuirks(bits))?);
+                }
+                TICK_RATE_TAG => bundle.tick_rate = Some(u64::from_le_bytes(payload.array()?)),
+                KEYS_TAG => {
+                    while !payload.is_empty() {
+                        let val = payload.u8()?;
+                        let key = KeyCodes::from_u8(val).ok_or(BundleErr::InvalidKey(val))?;
+                        bundle.keys.push((read_text(&mut payload)?, key));
+                    }
+                }
+                PALETTE_TAG => {
+                    let mut colors = [[0u8; 4]; PALETTE_SZ];
+                    for color in colors.iter_mut() {
+                        *color = payload.array()?;
+                    }
+                    bundle.palette = Some(Palette::new(colors));
+                }
+                OCTO_TAG => {
+                    while !payload.is_empty() {
+                        let name = read_text(&mut payload)?;
+                        bundle.octo_options.push((name, read_text(&mut payload)?));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        bundle.rom = rom.ok_or(BundleErr::MissingRom)?;
+        Ok(bundle)
+    }
+}
+
+fn write_chunk(out: &mut Vec<u8>, tag: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(tag);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+fn write_text(out: &mut Vec<u8>, text: &str) {
+    out.extend_from_slice(&(text.len() as u16).to_le_bytes());
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn to_text(bytes: &[u8]) -> Result<String, BundleErr> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| BundleErr::InvalidText)
+}
+
+fn read_text(reader: &mut ByteReader) -> Result<String, BundleErr> {
+    let len = u16::from_le_bytes(reader.array()?) as usize;
+    to_text(reader.take(len)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockPlatform;
+
+    impl PlatformAdapter for MockPlatform {
+        fn play_sound(&mut self) {}
+        fn pause_sound(&mut self) {}
+    }
+
+    fn example() -> RomBundle {
+        RomBundle {
+            rom: vec![0x12, 0x00],
+            title: String::from("Loop"),
+            author: String::from("Someone"),
+            profile: Some(Profile::Chip8X),
+            quirks: Some(QuirkFlags::QUIRK_FX1E),
+            tick_rate: Some(900),
+            keys: vec![(String::from("up"), KeyCodes::Key5), (String::from("a"), KeyCodes::KeyA)],
+            palette: Palette::from_name("lcd"),
+            octo_options: vec![(String::from("tickrate"), String::from("15"))],
+        }
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let bundle = example();
+        assert_eq!(Ok(bundle.clone()), RomBundle::from_bytes(&bundle.to_bytes()));
+
+        let bare = RomBundle::new(vec![0x00, 0xE0]);
+        assert_eq!(MAGIC.len() + 1 + 4 + 4 + 2, bare.to_bytes().len());
+        assert_eq!(Ok(bare.clone()), RomBundle::from_bytes(&bare.to_bytes()));
+    }
+
+    #[test]
+    fn from_bytes_err_test() {
+        let bytes = example().to_bytes();
+
+        assert_eq!(Err(BundleErr::BadMagic), RomBundle::from_bytes(b"C8MV\x01"));
+        assert_eq!(Err(BundleErr::UnsupportedVersion(9)), RomBundle::from_bytes(b"C8RB\x09"));
+        assert_eq!(Err(BundleErr::Truncated), RomBundle::from_bytes(&bytes[..bytes.len() - 1]));
+        assert_eq!(Err(BundleErr::MissingRom), RomBundle::from_bytes(b"C8RB\x01"));
+
+        // Unknown chunks are skipped.
+        let mut bytes = bytes;
+        write_chunk(&mut bytes, b"NEW!", &[1, 2, 3]);
+        assert_eq!(Ok(example()), RomBundle::from_bytes(&bytes));
+    }
+
+    #[test]
+    fn load_test() {
+        let interpreter = example().load(MockPlatform).unwrap();
+        assert_eq!(QuirkFlags::CHIP8X | QuirkFlags::QUIRK_FX1E, interpreter.quirks);
+        assert_eq!(0x300, interpreter.pc);
+    }
+}
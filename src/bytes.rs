@@ -0,0 +1,55 @@
+// Reading the binary formats: movies, bundles, patches, GIFs and the cartridges in them. Running out of bytes is
+// the only thing that can go wrong here, and each format turns it into its own error with From.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct Truncated;
+
+pub(crate) struct ByteReader<'a> {
+    pub bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { bytes, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], Truncated> {
+        // Lengths come from the data being read, so they can be anything.
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.bytes.len()).ok_or(Truncated)?;
+
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Truncated> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], Truncated> {
+        let mut arr = [0u8; N];
+        arr.copy_from_slice(self.take(N)?);
+        Ok(arr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_reader_test() {
+        let mut reader = ByteReader::new(&[1, 2, 3, 4, 5]);
+        assert_eq!(Ok(1), reader.u8());
+        assert_eq!(Ok([2, 3]), reader.array());
+        assert_eq!(Err(Truncated), reader.take(3));
+        assert_eq!(Err(Truncated), reader.take(usize::MAX));
+        assert_eq!(Ok(&[4, 5][..]), reader.take(2));
+        assert!(reader.is_empty());
+        assert_eq!(Err(Truncated), reader.u8());
+    }
+}
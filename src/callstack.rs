@@ -4,6 +4,7 @@ pub enum CallStackErr {
     StackEmpty
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct CallStack {
     arr: Vec<u16>,
     top: i16,
@@ -36,6 +37,18 @@ impl CallStack {
         Ok(())
     }    
 
+    pub fn push_wrapping(&mut self, addr: u16) {
+        // Like push, but a full stack drops its oldest return address to make room.
+        if self.is_full() {
+            self.arr.remove(0);
+            self.arr.push(0);
+            self.top -= 1;
+        }
+
+        self.top += 1;
+        self.arr[self.top as usize] = addr;
+    }
+
     pub fn pop(&mut self) -> Result<u16, CallStackErr> {
         if self.is_empty() {
             return Err(CallStackErr::StackEmpty);
@@ -0,0 +1,400 @@
+// Passed as the planes argument to act on every plane a display has.
+pub const ALL_PLANES: u8 = 0xFF;
+
+const WORD_BITS: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Rect { x, y, width, height }
+    }
+
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = std::cmp::min(self.x, other.x);
+        let y = std::cmp::min(self.y, other.y);
+        let right = std::cmp::max(self.x + self.width, other.x + other.width);
+        let bottom = std::cmp::max(self.y + self.height, other.y + other.height);
+
+        Rect { x, y, width: right - x, height: bottom - y }
+    }
+}
+
+// The bounding box of everything changed on a display since it was last taken.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DirtyRegion {
+    bounds: Option<Rect>,
+}
+
+impl DirtyRegion {
+    pub fn add(&mut self, rect: Rect) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+
+        self.bounds = Some(match self.bounds {
+            Some(bounds) => bounds.union(&rect),
+            None => rect,
+        });
+    }
+
+    pub fn take(&mut self) -> Option<Rect> {
+        self.bounds.take()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.bounds.is_some()
+    }
+
+    fn add_wrapped(&mut self, x: usize, y: usize, width: usize, height: usize, display_width: usize, display_height: usize) {
+        // A sprite that wraps around an edge touches both sides of the display, so mark the full extent on that axis.
+        let (x, width) = if x + width > display_width { (0, display_width) } else { (x, width) };
+        let (y, height) = if y + height > display_height { (0, display_height) } else { (y, height) };
+
+        self.add(Rect::new(x, y, width, height));
+    }
+}
+
+// A display the interpreter draws to.
+//
+// Pixels can be lit on several planes, which XO-CHIP uses for its four colors, and get returns a bitmask of the
+// planes lit at a position. Each plane is 1-bit, and a single-plane display only ever returns 0 or 1. Operations
+// that take a planes mask leave the planes outside it alone.
+pub trait Display {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    fn plane_count(&self) -> usize;
+
+    fn get(&self, x: usize, y: usize) -> u8;
+
+    fn clear(&mut self, planes: u8);
+
+    // XOR a sprite onto a plane with its top-left corner at (x, y). Rows are width / 8 bytes, leftmost pixel in the
+    // most significant bit. Returns whether any lit pixel was turned off.
+    fn draw_sprite(&mut self, plane: usize, x: usize, y: usize, sprite: &[u8], width: usize) -> bool;
+
+    fn scroll_up(&mut self, planes: u8, rows: usize);
+    fn scroll_down(&mut self, planes: u8, rows: usize);
+    fn scroll_left(&mut self, planes: u8, cols: usize);
+    fn scroll_right(&mut self, planes: u8, cols: usize);
+
+    // The bounding box of what has changed since the last call, or None if nothing has.
+    fn take_dirty(&mut self) -> Option<Rect>;
+}
+
+// The default display, with every row of a plane packed into 64-bit words so sprites XOR in a word at a time.
+// Sprites wrap around the edges.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BitDisplay {
+    width: usize,
+    height: usize,
+    words_per_row: usize,
+    planes: Vec<Vec<u64>>, // Rows top to bottom, with the leftmost pixel of each word in its most significant bit.
+    dirty: DirtyRegion,
+}
+
+impl BitDisplay {
+    pub fn new(width: usize, height: usize, plane_count: usize) -> Self {
+        // Every CHIP-8 variant's display is 64 or 128 pixels wide, which lets a row wrap on a word boundary.
+        assert!(width > 0 && width.is_multiple_of(WORD_BITS), "display width must be a multiple of {}", WORD_BITS);
+
+        let words_per_row = width / WORD_BITS;
+
+        BitDisplay {
+            width,
+            height,
+            words_per_row,
+            planes: vec![vec![0; words_per_row * height]; plane_count],
+            dirty: DirtyRegion::default(),
+        }
+    }
+
+    pub fn set(&mut self, plane: usize, x: usize, y: usize, lit: bool) {
+        let (idx, bit) = self.locate(x, y);
+
+        if lit {
+            self.planes[plane][idx] |= bit;
+        } else {
+            self.planes[plane][idx] &= !bit;
+        }
+
+        self.dirty.add(Rect::new(x, y, 1, 1));
+    }
+
+    pub fn plane_words(&self, plane: usize) -> &[u64] {
+        &self.planes[plane]
+    }
+
+    fn locate(&self, x: usize, y: usize) -> (usize, u64) {
+        let idx = y * self.words_per_row + x / WORD_BITS;
+        let bit = 1 << (WORD_BITS - 1 - x % WORD_BITS);
+
+        (idx, bit)
+    }
+
+    fn selected_planes(&mut self, planes: u8) -> impl Iterator<Item = &mut Vec<u64>> {
+        self.planes.iter_mut().enumerate().filter(move |(idx, _)| planes & (1 << idx) != 0).map(|(_, plane)| plane)
+    }
+
+    fn mark_all_dirty(&mut self) {
+        self.dirty.add(Rect::new(0, 0, self.width, self.height));
+    }
+}
+
+impl Display for BitDisplay {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn plane_count(&self) -> usize {
+        self.planes.len()
+    }
+
+    fn get(&self, x: usize, y: usize) -> u8 {
+        let (idx, bit) = self.locate(x, y);
+
+        self.planes.iter().enumerate()
+            .filter(|(_, plane)| plane[idx] & bit != 0)
+            .fold(0, |mask, (plane_idx, _)| mask | 1 << plane_idx)
+    }
+
+    fn clear(&mut self, planes: u8) {
+        for plane in self.selected_planes(planes) {
+            plane.iter_mut().for_each(|word| *word = 0);
+        }
+
+        self.mark_all_dirty();
+    }
+
+    fn draw_sprite(&mut self, plane: usize, x: usize, y: usize, sprite: &[u8], width: usize) -> bool {
+        if plane >= self.planes.len() {
+            return false;
+        }
+
+        let bytes_per_row = std::cmp::max(1, width / 8);
+        let x = x % self.width;
+        let y = y % self.height;
+
+        // Where the sprite's left edge lands. Anything that spills past the end of that word goes into the next,
+        // which for the last word in a row is the first, so the sprite wraps. Rows wider than a word are drawn a
+        // word's worth at a time.
+        let word = x / WORD_BITS;
+        let shift = x % WORD_BITS;
+        let mut collided = false;
+
+        let rows = sprite.chunks(bytes_per_row);
+        let row_count = rows.len();
+
+        for (row_idx, row) in rows.enumerate() {
+            let start = ((y + row_idx) % self.height) * self.words_per_row;
+            let row_words = &mut self.planes[plane][start..start + self.words_per_row];
+
+            for (chunk_idx, chunk) in row.chunks(WORD_BITS / 8).enumerate() {
+                let bits = chunk.iter().enumerate().fold(0u64, |bits, (idx, byte)| bits | (*byte as u64) << (56 - 8 * idx));
+                let chunk_word = (word + chunk_idx) % self.words_per_row;
+
+                collided |= xor_word(&mut row_words[chunk_word], bits >> shift);
+                if shift > 0 {
+                    collided |= xor_word(&mut row_words[(chunk_word + 1) % self.words_per_row], bits << (WORD_BITS - shift));
+                }
+            }
+        }
+
+        self.dirty.add_wrapped(x, y, bytes_per_row * 8, row_count, self.width, self.height);
+
+        collided
+    }
+
+    fn scroll_up(&mut self, planes: u8, rows: usize) {
+        let shift = std::cmp::min(rows, self.height) * self.words_per_row;
+
+        for plane in self.selected_planes(planes) {
+            let len = plane.len();
+            plane.copy_within(shift.., 0);
+            plane[len - shift..].iter_mut().for_each(|word| *word = 0);
+        }
+
+        self.mark_all_dirty();
+    }
+
+    fn scroll_down(&mut self, planes: u8, rows: usize) {
+        let shift = std::cmp::min(rows, self.height) * self.words_per_row;
+
+        for plane in self.selected_planes(planes) {
+            let len = plane.len();
+            plane.copy_within(..len - shift, shift);
+            plane[..shift].iter_mut().for_each(|word| *word = 0);
+        }
+
+        self.mark_all_dirty();
+    }
+
+    fn scroll_left(&mut self, planes: u8, cols: usize) {
+        let words_per_row = self.words_per_row;
+        let cols = std::cmp::min(cols, self.width);
+
+        for plane in self.selected_planes(planes) {
+            for row in plane.chunks_mut(words_per_row) {
+                shift_row_left(row, cols);
+            }
+        }
+
+        self.mark_all_dirty();
+    }
+
+    fn scroll_right(&mut self, planes: u8, cols: usize) {
+        let words_per_row = self.words_per_row;
+        let cols = std::cmp::min(cols, self.width);
+
+        for plane in self.selected_planes(planes) {
+            for row in plane.chunks_mut(words_per_row) {
+                shift_row_right(row, cols);
+            }
+        }
+
+        self.mark_all_dirty();
+    }
+
+    fn take_dirty(&mut self) -> Option<Rect> {
+        self.dirty.take()
+    }
+}
+
+fn xor_word(word: &mut u64, bits: u64) -> bool {
+    let collided = *word & bits != 0;
+    *word ^= bits;
+
+    collided
+}
+
+fn shift_row_left(row: &mut [u64], cols: usize) {
+    // Move every pixel cols to the left, treating the row's words as one long bit string.
+    let word_shift = cols / WORD_BITS;
+    let bit_shift = cols % WORD_BITS;
+
+    for idx in 0..row.len() {
+        let hi = row.get(idx + word_shift).copied().unwrap_or(0);
+        let lo = row.get(idx + word_shift + 1).copied().unwrap_or(0);
+
+        row[idx] = if bit_shift == 0 { hi } else { hi << bit_shift | lo >> (WORD_BITS - bit_shift) };
+    }
+}
+
+fn shift_row_right(row: &mut [u64], cols: usize) {
+    let word_shift = cols / WORD_BITS;
+    let bit_shift = cols % WORD_BITS;
+
+    for idx in (0..row.len()).rev() {
+        let lo = if idx >= word_shift { row[idx - word_shift] } else { 0 };
+        let hi = if idx > word_shift { row[idx - word_shift - 1] } else { 0 };
+
+        row[idx] = if bit_shift == 0 { lo } else { lo >> bit_shift | hi << (WORD_BITS - bit_shift) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_sprite_test() {
+        let mut display = BitDisplay::new(64, 32, 1);
+
+        assert!(!display.draw_sprite(0, 4, 5, &[0b1010_1010, 0b0101_0101], 8));
+        assert_eq!(1, display.get(4, 5));
+        assert_eq!(0, display.get(5, 5));
+        assert_eq!(1, display.get(5, 6));
+        assert_eq!(Some(Rect::new(4, 5, 8, 2)), display.take_dirty());
+        assert_eq!(None, display.take_dirty());
+
+        // Drawing the same sprite again erases it and collides.
+        assert!(display.draw_sprite(0, 4, 5, &[0b1010_1010, 0b0101_0101], 8));
+        assert_eq!(0, display.get(4, 5));
+
+        // Sprites wrap around the right and bottom edges.
+        assert!(!display.draw_sprite(0, 60, 31, &[0xFF, 0xFF], 8));
+        assert_eq!(1, display.get(63, 31));
+        assert_eq!(1, display.get(0, 31));
+        assert_eq!(1, display.get(3, 0));
+        assert_eq!(0, display.get(4, 0));
+        assert_eq!(Some(Rect::new(0, 0, 64, 32)), display.take_dirty());
+    }
+
+    #[test]
+    fn wide_sprite_test() {
+        // 128 pixels across, starting partway into a word and wrapping back round.
+        let mut display = BitDisplay::new(128, 64, 1);
+        let mut sprite = [0u8; 16];
+        sprite[0] = 0x80;
+        sprite[8] = 0x80;
+        sprite[15] = 0x01;
+
+        assert!(!display.draw_sprite(0, 4, 0, &sprite, 128));
+        assert_eq!(1, display.get(4, 0));
+        assert_eq!(1, display.get(68, 0));
+        assert_eq!(1, display.get(3, 0));
+        assert_eq!(3, (0..128).map(|x| display.get(x, 0)).sum::<u8>());
+    }
+
+    #[test]
+    fn wide_display_test() {
+        // A 16-pixel sprite straddling the words of a 128-pixel row, and one wrapping back to the start.
+        let mut display = BitDisplay::new(128, 64, 1);
+
+        display.draw_sprite(0, 60, 0, &[0xFF, 0x01], 16);
+        assert_eq!(1, display.get(60, 0));
+        assert_eq!(1, display.get(67, 0));
+        assert_eq!(0, display.get(68, 0));
+        assert_eq!(1, display.get(75, 0));
+
+        display.draw_sprite(0, 124, 1, &[0xFF], 8);
+        assert_eq!(1, display.get(127, 1));
+        assert_eq!(1, display.get(3, 1));
+    }
+
+    #[test]
+    fn planes_test() {
+        let mut display = BitDisplay::new(64, 32, 2);
+
+        display.draw_sprite(0, 0, 0, &[0xC0], 8);
+        display.draw_sprite(1, 1, 0, &[0xC0], 8);
+        assert_eq!([1, 3, 2, 0], [display.get(0, 0), display.get(1, 0), display.get(2, 0), display.get(3, 0)]);
+
+        // Clearing one plane leaves the other.
+        display.clear(0b10);
+        assert_eq!([1, 1, 0], [display.get(0, 0), display.get(1, 0), display.get(2, 0)]);
+    }
+
+    #[test]
+    fn scroll_test() {
+        let mut display = BitDisplay::new(128, 64, 1);
+        display.set(0, 62, 10, true);
+
+        display.scroll_right(ALL_PLANES, 4);
+        assert_eq!(1, display.get(66, 10));
+
+        display.scroll_left(ALL_PLANES, 66);
+        assert_eq!(1, display.get(0, 10));
+
+        display.scroll_down(ALL_PLANES, 4);
+        assert_eq!(1, display.get(0, 14));
+        assert_eq!(0, display.get(0, 10));
+
+        display.scroll_up(ALL_PLANES, 14);
+        assert_eq!(1, display.get(0, 0));
+
+        // Pixels scrolled off the edge are gone.
+        display.scroll_up(ALL_PLANES, 1);
+        assert!(display.plane_words(0).iter().all(|word| *word == 0));
+    }
+}
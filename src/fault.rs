@@ -0,0 +1,74 @@
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FaultClass {
+    Memory,         // A read or write outside of memory, usually through I.
+    StackOverflow,  // 2NNN with a full call stack.
+    StackUnderflow, // 00EE with an empty call stack.
+    InvalidOpcode,
+}
+
+// What to do when a fault happens.
+//
+// Wrap only has a meaning for memory, where the address wraps around within memory, and for stack overflow, where
+// the oldest return address is dropped to make room. For the other classes it behaves like Ignore.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FaultPolicy {
+    Trap,   // Stop with an InterpreterErr.
+    Ignore, // Treat the instruction as a no-op. Out of range reads return 0.
+    Wrap,
+    Hook,   // Ask the platform adapter's on_fault, which returns the policy to apply.
+}
+
+impl FaultPolicy {
+    pub fn from_name(name: &str) -> Option<FaultPolicy> {
+        match name.to_ascii_lowercase().as_str() {
+            "trap" => Some(FaultPolicy::Trap),
+            "ignore" => Some(FaultPolicy::Ignore),
+            "wrap" => Some(FaultPolicy::Wrap),
+            "hook" => Some(FaultPolicy::Hook),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FaultPolicies {
+    pub memory: FaultPolicy,
+    pub stack_overflow: FaultPolicy,
+    pub stack_underflow: FaultPolicy,
+    pub invalid_opcode: FaultPolicy,
+}
+
+impl FaultPolicies {
+    pub fn all(policy: FaultPolicy) -> Self {
+        FaultPolicies {
+            memory: policy,
+            stack_overflow: policy,
+            stack_underflow: policy,
+            invalid_opcode: policy,
+        }
+    }
+
+    pub fn get(&self, class: FaultClass) -> FaultPolicy {
+        match class {
+            FaultClass::Memory => self.memory,
+            FaultClass::StackOverflow => self.stack_overflow,
+            FaultClass::StackUnderflow => self.stack_underflow,
+            FaultClass::InvalidOpcode => self.invalid_opcode,
+        }
+    }
+
+    pub fn set(&mut self, class: FaultClass, policy: FaultPolicy) {
+        match class {
+            FaultClass::Memory => self.memory = policy,
+            FaultClass::StackOverflow => self.stack_overflow = policy,
+            FaultClass::StackUnderflow => self.stack_underflow = policy,
+            FaultClass::InvalidOpcode => self.invalid_opcode = policy,
+        }
+    }
+}
+
+impl Default for FaultPolicies {
+    fn default() -> Self {
+        FaultPolicies::all(FaultPolicy::Trap)
+    }
+}
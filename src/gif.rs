@@ -0,0 +1,603 @@
+use std::convert::TryFrom;
+
+use crate::bytes::{ByteReader, Truncated};
+use crate::render::{Frame, Palette, PALETTE_SZ};
+
+const MAX_CODE_SIZE: u8 = 12;
+const MAX_CODES: u16 = 1 << MAX_CODE_SIZE;
+const MAX_SUB_BLOCK_SZ: usize = 255;
+
+const FRAMES_PER_SECOND: u64 = 60;
+const CENTISECONDS_PER_SECOND: u64 = 100;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GifFrame {
+    pub pixels: Vec<u8>,
+    pub delay_cs: u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GifErr {
+    BadMagic,
+    Truncated,
+    InvalidBlock(u8),
+    InvalidCodeSize(u8),
+    InvalidCode(u16),
+    TooLarge, // Wider or taller than the 65535 pixels a GIF can hold.
+}
+
+impl From<Truncated> for GifErr {
+    fn from(_: Truncated) -> GifErr {
+        GifErr::Truncated
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gif {
+    pub width: u16,
+    pub height: u16,
+    pub palette: Vec<[u8; 3]>, // The global color table, or the first frame's own if there isn't one.
+    pub frames: Vec<GifFrame>, // Each the whole canvas, with the frame drawn over the one before.
+}
+
+pub fn encode(width: u16, height: u16, palette: &[[u8; 3]], frames: &[GifFrame], looping: bool) -> Vec<u8> {
+    // The global color table must have a power-of-two size of at least 2 entries.
+    let mut table_bits = 1;
+    while (1usize << table_bits) < palette.len() {
+        table_bits += 1;
+    }
+
+    let mut out = b"GIF89a".to_vec();
+    out.extend_from_slice(&width.to_le_bytes());
+    out.extend_from_slice(&height.to_le_bytes());
+    out.push(0x80 | ((table_bits - 1) << 4) | (table_bits - 1)); // Global color table present.
+    out.push(0); // Background color index.
+    out.push(0); // No aspect ratio.
+
+    for idx in 0..(1usize << table_bits) {
+        out.extend_from_slice(palette.get(idx).unwrap_or(&[0, 0, 0]));
+    }
+
+    if looping && frames.len() > 1 {
+        // NETSCAPE2.0 application extension, looping forever.
+        out.extend_from_slice(&[0x21, 0xFF, 0x0B]);
+        out.extend_from_slice(b"NETSCAPE2.0");
+        out.extend_from_slice(&[0x03, 0x01, 0x00, 0x00, 0x00]);
+    }
+
+    // LZW needs a minimum code size of at least 2, even for 1-bit images.
+    let min_code_size = std::cmp::max(2, table_bits);
+
+    for frame in frames {
+        // Graphic control extension carrying the frame delay.
+        out.extend_from_slice(&[0x21, 0xF9, 0x04, 0x00]);
+        out.extend_from_slice(&frame.delay_cs.to_le_bytes());
+        out.extend_from_slice(&[0x00, 0x00]);
+
+        // Image descriptor covering the whole canvas, with no local color table.
+        out.push(0x2C);
+        out.extend_from_slice(&[0, 0, 0, 0]);
+        out.extend_from_slice(&width.to_le_bytes());
+        out.extend_from_slice(&height.to_le_bytes());
+        out.push(0);
+
+        out.push(min_code_size);
+        write_sub_blocks(&mut out, &lzw_encode(min_code_size, &frame.pixels));
+    }
+
+    out.push(0x3B); // Trailer.
+    out
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Gif, GifErr> {
+    let mut reader = ByteReader::new(bytes);
+
+    let magic = reader.take(6)?;
+    if magic != b"GIF87a" && magic != b"GIF89a" {
+        return Err(GifErr::BadMagic);
+    }
+
+    let width = read_u16(&mut reader)?;
+    let height = read_u16(&mut reader)?;
+    let flags = reader.u8()?;
+    reader.take(2)?; // Background color index and aspect ratio.
+
+    let mut palette = match flags & 0x80 {
+        0 => Vec::new(),
+        _ => read_color_table(&mut reader, flags)?,
+    };
+
+    let mut canvas = vec![0u8; width as usize * height as usize];
+    let mut frames = Vec::new();
+    let mut delay_cs = 0;
+
+    loop {
+        match reader.u8()? {
+            0x21 => {
+                let label = reader.u8()?;
+                let data = read_sub_blocks(&mut reader)?;
+
+                // Graphic control extension. Everything else, like comments and looping, doesn't matter here.
+                if label == 0xF9 && data.len() >= 3 {
+                    delay_cs = u16::from_le_bytes([data[1], data[2]]);
+                }
+            }
+            0x2C => {
+                let left = read_u16(&mut reader)? as usize;
+                let top = read_u16(&mut reader)? as usize;
+                let frame_width = read_u16(&mut reader)? as usize;
+                let frame_height = read_u16(&mut reader)? as usize;
+                let frame_flags = reader.u8()?;
+
+                if frame_flags & 0x80 != 0 {
+                    let local = read_color_table(&mut reader, frame_flags)?;
+                    if palette.is_empty() {
+                        palette = local;
+                    }
+                }
+
+                let min_code_size = reader.u8()?;
+                let mut indices = lzw_decode(min_code_size, &read_sub_blocks(&mut reader)?)?;
+                indices.resize(frame_width * frame_height, 0);
+
+                let rows = match frame_flags & 0x40 {
+                    0 => (0..frame_height).collect(),
+                    _ => interlaced_rows(frame_height),
+                };
+
+                for (src_row, dest_row) in rows.into_iter().enumerate() {
+                    for col in 0..frame_width {
+                        let (x, y) = (left + col, top + dest_row);
+                        if x < width as usize && y < height as usize {
+                            canvas[y * width as usize + x] = indices[src_row * frame_width + col];
+                        }
+                    }
+                }
+
+                frames.push(GifFrame { pixels: canvas.clone(), delay_cs });
+                delay_cs = 0;
+            }
+            0x3B => break,
+            block => return Err(GifErr::InvalidBlock(block)),
+        }
+    }
+
+    Ok(Gif { width, height, palette, frames })
+}
+
+fn interlaced_rows(height: usize) -> Vec<usize> {
+    // Interlaced images store every 8th row from 0, then every 8th from 4, every 4th from 2 and every 2nd from 1.
+    [(0, 8), (4, 8), (2, 4), (1, 2)].iter()
+        .flat_map(|&(start, step)| (start..height).step_by(step))
+        .collect()
+}
+
+fn read_u16(reader: &mut ByteReader) -> Result<u16, GifErr> {
+    Ok(u16::from_le_bytes(reader.array()?))
+}
+
+fn read_color_table(reader: &mut ByteReader, flags: u8) -> Result<Vec<[u8; 3]>, GifErr> {
+    let len = 1usize << ((flags & 0x07) + 1);
+    Ok(reader.take(len * 3)?.chunks(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect())
+}
+
+fn read_sub_blocks(reader: &mut ByteReader) -> Result<Vec<u8>, GifErr> {
+    let mut data = Vec::new();
+    loop {
+        let len = reader.u8()? as usize;
+        if len == 0 {
+            return Ok(data);
+        }
+        data.extend_from_slice(reader.take(len)?);
+    }
+}
+
+pub(crate) fn lzw_decode(min_code_size: u8, data: &[u8]) -> Result<Vec<u8>, GifErr> {
+    if !(2..MAX_CODE_SIZE).contains(&min_code_size) {
+        return Err(GifErr::InvalidCodeSize(min_code_size));
+    }
+
+    let clear_code = 1u16 << min_code_size;
+    let end_code = clear_code + 1;
+
+    // Each code's string is its prefix code's string plus one index. The first index and length of every string are
+    // kept too, so strings can be written out back to front without recursion.
+    let mut prefixes = vec![0u16; MAX_CODES as usize];
+    let mut suffixes = vec![0u8; MAX_CODES as usize];
+    let mut firsts = vec![0u8; MAX_CODES as usize];
+    let mut lens = vec![0usize; MAX_CODES as usize];
+    for code in 0..clear_code {
+        suffixes[code as usize] = code as u8;
+        firsts[code as usize] = code as u8;
+        lens[code as usize] = 1;
+    }
+
+    let mut next_code = end_code + 1;
+    let mut code_size = min_code_size + 1;
+    let mut prev: Option<u16> = None;
+
+    let mut out = Vec::new();
+    let mut bit_buf = 0u32;
+    let mut bit_count = 0u8;
+    let mut bytes = data.iter();
+
+    loop {
+        // GIF packs codes LSB first. Running out of data ends the image, as if the end code had come.
+        while bit_count < code_size {
+            match bytes.next() {
+                Some(byte) => {
+                    bit_buf |= (*byte as u32) << bit_count;
+                    bit_count += 8;
+                }
+                None => return Ok(out),
+            }
+        }
+
+        let code = (bit_buf & ((1 << code_size) - 1)) as u16;
+        bit_buf >>= code_size;
+        bit_count -= code_size;
+
+        if code == clear_code {
+            next_code = end_code + 1;
+            code_size = min_code_size + 1;
+            prev = None;
+            continue;
+        }
+
+        if code == end_code {
+            return Ok(out);
+        }
+
+        let known = code < clear_code || (code > end_code && code < next_code);
+        let first = match (known, prev) {
+            (true, _) => firsts[code as usize],
+            (false, Some(prev)) if code == next_code => firsts[prev as usize], // The string being defined.
+            _ => return Err(GifErr::InvalidCode(code)),
+        };
+
+        if let Some(prev) = prev {
+            if next_code < MAX_CODES {
+                let new = next_code as usize;
+                prefixes[new] = prev;
+                suffixes[new] = first;
+                firsts[new] = firsts[prev as usize];
+                lens[new] = lens[prev as usize] + 1;
+                next_code += 1;
+
+                if next_code == 1 << code_size && code_size < MAX_CODE_SIZE {
+                    code_size += 1;
+                }
+            }
+        }
+
+        let start = out.len();
+        out.resize(start + lens[code as usize], 0);
+        let mut string_code = code;
+        for idx in (start..out.len()).rev() {
+            out[idx] = suffixes[string_code as usize];
+            string_code = prefixes[string_code as usize];
+        }
+
+        prev = Some(code);
+    }
+}
+
+fn write_sub_blocks(out: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(MAX_SUB_BLOCK_SZ) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+
+    out.push(0); // Block terminator.
+}
+
+struct CodeWriter {
+    out: Vec<u8>,
+    bit_buf: u32,
+    bit_count: u8,
+}
+
+impl CodeWriter {
+    // GIF packs codes LSB first.
+    fn write(&mut self, code: u16, code_size: u8) {
+        self.bit_buf |= (code as u32) << self.bit_count;
+        self.bit_count += code_size;
+
+        while self.bit_count >= 8 {
+            self.out.push(self.bit_buf as u8);
+            self.bit_buf >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.out.push(self.bit_buf as u8);
+        }
+
+        self.out
+    }
+}
+
+pub(crate) fn lzw_encode(min_code_size: u8, indices: &[u8]) -> Vec<u8> {
+    let clear_code = 1u16 << min_code_size;
+    let end_code = clear_code + 1;
+
+    // The string table maps (prefix code, next index) to a code. Strings are only ever extended one index at a
+    // time, so a dense table indexed by prefix code is enough.
+    let mut table = vec![0u16; MAX_CODES as usize * 256];
+    let mut next_code = end_code + 1;
+    let mut code_size = min_code_size + 1;
+
+    let mut writer = CodeWriter { out: Vec::new(), bit_buf: 0, bit_count: 0 };
+    writer.write(clear_code, code_size);
+
+    let mut iter = indices.iter();
+    let mut prefix = match iter.next() {
+        Some(idx) => *idx as u16,
+        None => {
+            writer.write(end_code, code_size);
+            return writer.finish();
+        }
+    };
+
+    for idx in iter {
+        let entry = prefix as usize * 256 + *idx as usize;
+
+        if table[entry] != 0 {
+            prefix = table[entry];
+            continue;
+        }
+
+        writer.write(prefix, code_size);
+
+        if next_code < MAX_CODES {
+            table[entry] = next_code;
+
+            // The decoder lags one code behind, so grow once the new code no longer fits.
+            if next_code == 1 << code_size && code_size < MAX_CODE_SIZE {
+                code_size += 1;
+            }
+            next_code += 1;
+        } else {
+            // The table is full, so start over.
+            writer.write(clear_code, code_size);
+            table.iter_mut().for_each(|code| *code = 0);
+            next_code = end_code + 1;
+            code_size = min_code_size + 1;
+        }
+
+        prefix = *idx as u16;
+    }
+
+    writer.write(prefix, code_size);
+    writer.write(end_code, code_size);
+    writer.finish()
+}
+
+// Megamode switches between the 64x32 and 256x192 displays mid-run, so frames can differ in size. The GIF is as big
+// as the largest, and smaller frames are scaled up by as much as fits and centred in it.
+pub struct GifRecorder {
+    palette: Palette,
+    scale: usize,
+    frames: Vec<(Frame, u64)>, // Distinct consecutive frames and how many 60Hz frames each was shown for.
+    frame_count: u64,
+}
+
+impl GifRecorder {
+    pub fn new(palette: Palette, scale: usize) -> Self {
+        GifRecorder {
+            palette,
+            scale: std::cmp::max(1, scale),
+            frames: Vec::new(),
+            frame_count: 0,
+        }
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn record(&mut self, frame: &Frame) {
+        // Identical consecutive frames are collapsed into one with a longer delay.
+        self.frame_count += 1;
+
+        if let Some((last, duration)) = self.frames.last_mut() {
+            if last == frame {
+                *duration += 1;
+                return;
+            }
+        }
+
+        self.frames.push((frame.clone(), 1));
+    }
+
+    pub fn finish(&self) -> Result<Vec<u8>, GifErr> {
+        let palette: Vec<[u8; 3]> = self.palette.colors.iter()
+            .map(|color| [color[0], color[1], color[2]])
+            .collect();
+
+        let width = self.frames.iter().map(|(frame, _)| frame.width).max().unwrap_or(0);
+        let height = self.frames.iter().map(|(frame, _)| frame.height).max().unwrap_or(0);
+        let gif_width = width.checked_mul(self.scale).and_then(|width| u16::try_from(width).ok()).ok_or(GifErr::TooLarge)?;
+        let gif_height = height.checked_mul(self.scale).and_then(|height| u16::try_from(height).ok()).ok_or(GifErr::TooLarge)?;
+
+        let mut gif_frames = Vec::with_capacity(self.frames.len());
+        let mut start_frame = 0;
+
+        for (frame, duration) in self.frames.iter() {
+            // GIF delays are in centiseconds, which don't divide 1/60th of a second, so each delay is measured
+            // between rounded timestamps. This keeps the total length exact instead of drifting.
+            let end_frame = start_frame + duration;
+            let mut delay_cs = frames_to_cs(end_frame) - frames_to_cs(start_frame);
+            start_frame = end_frame;
+
+            // A delay longer than a GIF frame can hold, about 11 minutes, is shown over several copies of it.
+            let pixels = self.scale_pixels(frame, width, height);
+            while delay_cs > u16::MAX as u64 {
+                gif_frames.push(GifFrame { pixels: pixels.clone(), delay_cs: u16::MAX });
+                delay_cs -= u16::MAX as u64;
+            }
+
+            gif_frames.push(GifFrame {
+                pixels,
+                delay_cs: delay_cs as u16,
+            });
+        }
+
+        Ok(encode(gif_width, gif_height, &palette[0..PALETTE_SZ], &gif_frames, true))
+    }
+
+    fn scale_pixels(&self, frame: &Frame, width: usize, height: usize) -> Vec<u8> {
+        // Scale the frame into a canvas of width x height, then the whole canvas by the recorder's scale.
+        let fit = std::cmp::max(1, std::cmp::min(width / frame.width.max(1), height / frame.height.max(1)));
+        let left = (width - frame.width * fit) / 2;
+        let top = (height - frame.height * fit) / 2;
+        let scale = fit * self.scale;
+
+        let canvas_width = width * self.scale;
+        let mut scaled = vec![0u8; canvas_width * height * self.scale];
+
+        for (y, row) in frame.pixels.chunks(frame.width.max(1)).enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                let (dest_x, dest_y) = (left * self.scale + x * scale, top * self.scale + y * scale);
+                for line in scaled[dest_y * canvas_width..].chunks_mut(canvas_width).take(scale) {
+                    line[dest_x..dest_x + scale].fill(*pixel % PALETTE_SZ as u8);
+                }
+            }
+        }
+
+        scaled
+    }
+}
+
+fn frames_to_cs(frames: u64) -> u64 {
+    (frames * CENTISECONDS_PER_SECOND + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lzw_encode_test() {
+        // The second and third pixels are emitted together as the newly added string #6.
+        let encoded = lzw_encode(2, &[1, 1, 1, 1]);
+
+        // Codes: 4 (clear), 1, 6 (1,1), 1, 5 (end), all 3 bits wide.
+        let codes: u32 = 4 | 1 << 3 | 6 << 6 | 1 << 9 | 5 << 12;
+        assert_eq!(vec![codes as u8, (codes >> 8) as u8], encoded);
+    }
+
+    #[test]
+    fn lzw_round_trip_test() {
+        // Enough varied data to fill the string table and force a clear code.
+        let mut seed = 1u32;
+        let indices: Vec<u8> = (0..20_000).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) as u8 & 0x0F
+        }).collect();
+
+        assert_eq!(indices, lzw_decode(4, &lzw_encode(4, &indices)).unwrap());
+        assert_eq!(vec![1, 1, 1, 1], lzw_decode(2, &lzw_encode(2, &[1, 1, 1, 1])).unwrap());
+        assert_eq!(Vec::<u8>::new(), lzw_decode(2, &lzw_encode(2, &[])).unwrap());
+        assert_eq!(Err(GifErr::InvalidCode(7)), lzw_decode(2, &[0x3C]));
+    }
+
+    #[test]
+    fn decode_test() {
+        let palette = [[0, 0, 0], [255, 0, 0], [0, 255, 0]];
+        let frames = vec![
+            GifFrame { pixels: vec![0, 1, 2, 0, 1, 2], delay_cs: 5 },
+            GifFrame { pixels: vec![2, 2, 2, 1, 1, 1], delay_cs: 10 },
+        ];
+
+        let gif = decode(&encode(3, 2, &palette, &frames, true)).unwrap();
+        assert_eq!((3, 2), (gif.width, gif.height));
+        assert_eq!(vec![[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 0]], gif.palette);
+        assert_eq!(frames, gif.frames);
+
+        assert_eq!(Err(GifErr::BadMagic), decode(b"PNG...."));
+        assert_eq!(Err(GifErr::Truncated), decode(b"GIF89a\x03\x00"));
+    }
+
+    #[test]
+    fn interlaced_rows_test() {
+        assert_eq!(vec![0, 8, 4, 2, 6, 10, 1, 3, 5, 7, 9], interlaced_rows(11));
+    }
+
+    #[test]
+    fn recorder_mixed_sizes_test() {
+        // A lores frame after a larger one is scaled up to fit and centred.
+        let mut recorder = GifRecorder::new(Palette::monochrome(), 2);
+        recorder.record(&Frame::new(8, 6));
+        let mut lores = Frame::new(4, 2);
+        lores.pixels[0] = 1;
+        recorder.record(&lores);
+
+        let gif = decode(&recorder.finish().unwrap()).unwrap();
+        assert_eq!((16, 12), (gif.width, gif.height));
+        assert_eq!(vec![0; 16 * 12], gif.frames[0].pixels);
+
+        // Scaled by 2 to fit 8x6, then by the recorder's 2, one row down.
+        let lit: Vec<usize> = (0..16 * 12).filter(|idx| gif.frames[1].pixels[*idx] == 1).collect();
+        assert_eq!(vec![32, 33, 34, 35, 48, 49, 50, 51, 64, 65, 66, 67, 80, 81, 82, 83], lit);
+    }
+
+    #[test]
+    fn recorder_collapses_frames_test() {
+        let mut recorder = GifRecorder::new(Palette::monochrome(), 1);
+        let blank = Frame::new(8, 4);
+        let mut lit = Frame::new(8, 4);
+        lit.pixels[0] = 1;
+
+        for _ in 0..3 {
+            recorder.record(&blank);
+        }
+        recorder.record(&lit);
+        recorder.record(&blank);
+
+        assert_eq!(5, recorder.frame_count());
+        assert_eq!(vec![3, 1, 1], recorder.frames.iter().map(|(_, duration)| *duration).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn frame_delay_test() {
+        // 60 frames must add up to exactly one second, however they're split.
+        let mut total = 0;
+        let mut start = 0;
+        for duration in [1, 1, 1, 7, 20, 30].iter() {
+            total += frames_to_cs(start + duration) - frames_to_cs(start);
+            start += duration;
+        }
+
+        assert_eq!(100, total);
+        assert_eq!(2, frames_to_cs(1));
+    }
+
+    #[test]
+    fn encode_structure_test() {
+        let mut recorder = GifRecorder::new(Palette::monochrome(), 2);
+        recorder.record(&Frame::new(4, 2));
+
+        let gif = recorder.finish().unwrap();
+        assert_eq!(b"GIF89a", &gif[0..6]);
+        assert_eq!([8, 0, 4, 0], gif[6..10]);
+        assert_eq!(0x3B, *gif.last().unwrap());
+
+        let mut too_wide = GifRecorder::new(Palette::monochrome(), 1024);
+        too_wide.record(&Frame::new(128, 64));
+        assert_eq!(Err(GifErr::TooLarge), too_wide.finish());
+    }
+
+    #[test]
+    fn long_delay_test() {
+        // Twenty minutes without a change is more than one frame's delay can hold.
+        let mut recorder = GifRecorder::new(Palette::monochrome(), 1);
+        recorder.frames.push((Frame::new(4, 2), 20 * 60 * FRAMES_PER_SECOND));
+
+        let gif = decode(&recorder.finish().unwrap()).unwrap();
+        let delays: Vec<u16> = gif.frames.iter().map(|frame| frame.delay_cs).collect();
+        assert_eq!(vec![u16::MAX, 54_465], delays);
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::display::Display;
+use crate::interpreter::{Chip8Interpreter, HIRES_START_ADDR, START_ADDR};
+use crate::observer::Observer;
+use crate::platform_adapter::PlatformAdapter;
+use crate::profile::Profile;
+use crate::quirk_flags::QuirkFlags;
+
+// How long each candidate runs for, and how fast.
+pub const INFER_FRAMES: u64 = 120;
+pub const INFER_TICK_RATE: u64 = 600;
+
+// How far past an FX55 or FX65 to look for the next use of I.
+const I_LOOKAHEAD: usize = 32;
+
+const PROFILES: [Profile; 6] = [
+    Profile::CosmacVip, Profile::VipHires, Profile::Chip8X, Profile::SChip, Profile::MegaChip, Profile::Amiga
+];
+
+// The quirks code gives itself away on, which every profile is also tried with the other way around. Each group
+// goes together, since programs written for one behaviour expect it from both instructions.
+const QUIRK_GROUPS: [QuirkFlags; 2] = [
+    QuirkFlags::from_bits_truncate(QuirkFlags::QUIRK_8XY6.bits() | QuirkFlags::QUIRK_8XYE.bits()),
+    QuirkFlags::from_bits_truncate(QuirkFlags::QUIRK_FX55.bits() | QuirkFlags::QUIRK_FX65.bits()),
+];
+
+const QUIRK_NAMES: [(QuirkFlags, &str); 5] = [
+    (QuirkFlags::QUIRK_8XY6, "8xy6"),
+    (QuirkFlags::QUIRK_8XYE, "8xye"),
+    (QuirkFlags::QUIRK_FX1E, "fx1e"),
+    (QuirkFlags::QUIRK_FX55, "fx55"),
+    (QuirkFlags::QUIRK_FX65, "fx65"),
+];
+
+// Evidence weights. Instructions only one platform has are close to proof, quirk-sensitive code is a hint, and a
+// crash under a profile all but rules it out.
+const PLATFORM_SCORE: i32 = 10;
+const QUIRK_SCORE: i32 = 2;
+const EDGE_SCORE: i32 = 1;
+const FAULT_SCORE: i32 = -20;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub profile: Profile,
+    pub quirks: QuirkFlags,
+    pub score: i32,
+    pub reasons: Vec<String>, // Why the score is what it is, one piece of evidence each.
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Quirks are shown as changes to the profile's own, which is what --quirks and the ROM database list.
+        write!(f, "{}", self.profile.name())?;
+        let defaults = self.profile.quirks();
+        for (flag, name) in QUIRK_NAMES.iter() {
+            if self.quirks.contains(*flag) != defaults.contains(*flag) {
+                write!(f, " {}{}", if self.quirks.contains(*flag) { '+' } else { '-' }, name)?;
+            }
+        }
+        write!(f, " ({:+})", self.score)?;
+        for reason in self.reasons.iter() {
+            write!(f, "\n    {}", reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Extension {
+    SChip,
+    XoChip,
+    MegaChip,
+    Chip8X,
+}
+
+impl Extension {
+    fn of(instr: u16) -> Option<Extension> {
+        let x = (instr >> 8) & 0xF;
+        let low = instr & 0xFF;
+
+        match instr >> 12 {
+            0x0 => match instr {
+                0x00FB..=0x00FF => Some(Extension::SChip),
+                0x00C1..=0x00CF => Some(Extension::SChip),
+                0x00D0..=0x00DF => Some(Extension::XoChip),
+                0x0010 | 0x0011 => Some(Extension::MegaChip),
+                0x02A0 => Some(Extension::Chip8X),
+                _ => None,
+            },
+            0x5 if instr & 0xF == 0x2 || instr & 0xF == 0x3 => Some(Extension::XoChip),
+            0xD if instr & 0xF == 0 => Some(Extension::SChip),
+            0xE if low == 0xF2 || low == 0xF5 => Some(Extension::Chip8X),
+            0xF => match low {
+                0x30 | 0x75 | 0x85 => Some(Extension::SChip),
+                0x00 if x == 0 => Some(Extension::XoChip),
+                0x01 | 0x3A => Some(Extension::XoChip),
+                0x02 if x == 0 => Some(Extension::XoChip),
+                0xF8 | 0xFB => Some(Extension::Chip8X),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Extension::SChip => "S-CHIP",
+            Extension::XoChip => "XO-CHIP",
+            Extension::MegaChip => "MEGA-CHIP8",
+            Extension::Chip8X => "CHIP-8X",
+        }
+    }
+
+    fn profiles(&self) -> &'static [Profile] {
+        // MEGA-CHIP8 builds on S-CHIP, and there's no XO-CHIP profile, but S-CHIP has its instructions' quirks.
+        match self {
+            Extension::SChip => &[Profile::SChip, Profile::MegaChip],
+            Extension::XoChip => &[Profile::SChip],
+            Extension::MegaChip => &[Profile::MegaChip],
+            Extension::Chip8X => &[Profile::Chip8X],
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Findings {
+    extensions: Vec<(Extension, u16, u16)>, // The first use found of each, with its address and instruction.
+    hires_trampoline: bool,
+    vy_shift: Option<(u16, u16)>,           // An 8XY6 or 8XYE with X != Y.
+    i_increment: Option<(u16, u16)>,        // An FX55 or FX65 whose I is used again without being set.
+}
+
+// Guesses which profile and quirks a ROM was written for, best first.
+//
+// Instructions in reachable code that only one platform has point straight at it, and some code only makes sense
+// under particular quirks. Then every profile, with its own quirks and with each of QUIRK_GROUPS flipped, gets a
+// short headless run, where faulting counts heavily against it.
+pub fn infer(rom: &[u8]) -> Vec<Candidate> {
+    let findings = analyse(rom);
+
+    let mut candidates: Vec<Candidate> = PROFILES.iter().flat_map(|&profile| {
+        quirk_variants(profile).into_iter().map(move |quirks| Candidate { profile, quirks, score: 0, reasons: Vec::new() })
+    }).map(|mut candidate| {
+        score_findings(&mut candidate, &findings);
+        score_run(&mut candidate, rom);
+        candidate
+    }).collect();
+
+    // The sort is stable, so ties keep PROFILES' order, which puts the plainer platforms first, and each profile's
+    // own quirks ahead of its variants.
+    candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.score));
+    candidates
+}
+
+fn quirk_variants(profile: Profile) -> Vec<QuirkFlags> {
+    // The profile's quirks first, then every combination of QUIRK_GROUPS flipped.
+    (0..1 << QUIRK_GROUPS.len()).map(|flips: usize| {
+        QUIRK_GROUPS.iter().enumerate()
+            .filter(|(index, _)| flips & (1 << index) != 0)
+            .fold(profile.quirks(), |quirks, (_, &group)| {
+                if quirks.intersects(group) { quirks - group } else { quirks | group }
+            })
+    }).collect()
+}
+
+fn read_instr(rom: &[u8], addr: u16) -> Option<u16> {
+    let offset = (addr as usize).checked_sub(START_ADDR)?;
+    Some(u16::from_be_bytes([*rom.get(offset)?, *rom.get(offset + 1)?]))
+}
+
+fn successors(addr: u16, instr: u16) -> Vec<u16> {
+    // Where execution can go after an instruction, as far as can be told without running it.
+    let next = addr.wrapping_add(2);
+    let nnn = instr & 0xFFF;
+    let low = instr & 0xFF;
+
+    match instr >> 12 {
+        0x0 if instr == 0x00EE || instr == 0x00FD => vec![],
+        0x0 if instr >> 8 == 0x01 => vec![next.wrapping_add(2)], // MEGA-CHIP8's LDHI is two words long.
+        0x1 if nnn == addr => vec![],
+        0x1 => vec![nnn],
+        0x2 => vec![nnn, next],
+        0x3 | 0x4 | 0x5 | 0x9 => vec![next, next.wrapping_add(2)],
+        0xB => vec![], // Computed.
+        0xE if low == 0x9E || low == 0xA1 || low == 0xF2 || low == 0xF5 => vec![next, next.wrapping_add(2)],
+        0xF if instr == 0xF000 => vec![next.wrapping_add(2)], // XO-CHIP's long I, also two words.
+        _ => vec![next],
+    }
+}
+
+fn analyse(rom: &[u8]) -> Findings {
+    let mut findings = Findings::default();
+
+    // VIP hires programs start with a jump over the hires interpreter, which isn't CHIP-8 code.
+    let entry = if rom.starts_with(&[0x12, 0x60]) {
+        findings.hires_trampoline = true;
+        HIRES_START_ADDR as u16
+    } else {
+        START_ADDR as u16
+    };
+
+    let mut visited = HashSet::new();
+    let mut pending = vec![entry];
+
+    while let Some(addr) = pending.pop() {
+        if !visited.insert(addr) {
+            continue;
+        }
+
+        let instr = match read_instr(rom, addr) {
+            Some(instr) => instr,
+            None => continue,
+        };
+
+        if let Some(extension) = Extension::of(instr) {
+            if !findings.extensions.iter().any(|(seen, _, _)| *seen == extension) {
+                findings.extensions.push((extension, addr, instr));
+            }
+        }
+
+        let x = (instr >> 8) & 0xF;
+        let y = (instr >> 4) & 0xF;
+        let is_shift = instr >> 12 == 0x8 && (instr & 0xF == 0x6 || instr & 0xF == 0xE);
+        if is_shift && x != y && findings.vy_shift.is_none() {
+            findings.vy_shift = Some((addr, instr));
+        }
+
+        let is_load_store = instr >> 12 == 0xF && (instr & 0xFF == 0x55 || instr & 0xFF == 0x65);
+        if is_load_store && findings.i_increment.is_none() && uses_i_before_setting(rom, addr) {
+            findings.i_increment = Some((addr, instr));
+        }
+
+        pending.extend(successors(addr, instr));
+    }
+
+    findings.extensions.sort_by_key(|(_, addr, _)| *addr);
+    findings
+}
+
+fn uses_i_before_setting(rom: &[u8], addr: u16) -> bool {
+    // Follow the code after an FX55 or FX65, through plain jumps, for an instruction that uses I before anything
+    // sets it. Such a program depends on whether the load or store moved I on.
+    let mut addr = addr;
+
+    for _ in 0..I_LOOKAHEAD {
+        addr = match read_instr(rom, addr) {
+            Some(instr) if instr >> 12 == 0x1 => instr & 0xFFF,
+            Some(_) => addr.wrapping_add(2),
+            None => return false,
+        };
+
+        let instr = match read_instr(rom, addr) {
+            Some(instr) => instr,
+            None => return false,
+        };
+
+        match (instr >> 12, instr & 0xFF) {
+            (0xA, _) | (0xF, 0x1E) | (0xF, 0x29) | (0xF, 0x30) => return false,
+            (0xF, 0x55) | (0xF, 0x65) | (0xF, 0x33) | (0xD, _) => return true,
+            (0x0, _) if instr == 0x00EE => return false,
+            (0x2, _) | (0xB, _) => return false, // Whatever gets called or jumped to might set I.
+            _ => (),
+        }
+    }
+
+    false
+}
+
+fn score_findings(candidate: &mut Candidate, findings: &Findings) {
+    for (extension, addr, instr) in findings.extensions.iter() {
+        if extension.profiles().contains(&candidate.profile) {
+            candidate.score += PLATFORM_SCORE;
+            candidate.reasons.push(format!("uses {} instructions, like {:04X} at {:#05X}", extension.name(), instr, addr));
+        }
+    }
+
+    if findings.hires_trampoline && candidate.profile == Profile::VipHires {
+        candidate.score += PLATFORM_SCORE;
+        candidate.reasons.push(String::from("starts with the VIP hires interpreter's 1260 jump"));
+    }
+
+    if let Some((addr, instr)) = findings.vy_shift {
+        if candidate.quirks.contains(QuirkFlags::QUIRK_8XY6) {
+            candidate.score += QUIRK_SCORE;
+            candidate.reasons.push(format!("shifts from VY, not VX, with {:04X} at {:#05X}", instr, addr));
+        }
+    }
+
+    if let Some((addr, instr)) = findings.i_increment {
+        if candidate.quirks.intersects(QuirkFlags::QUIRK_FX55 | QuirkFlags::QUIRK_FX65) {
+            candidate.score += QUIRK_SCORE;
+            candidate.reasons.push(format!("uses I after {:04X} at {:#05X} without setting it, expecting it to have moved on", instr, addr));
+        }
+    }
+}
+
+struct Headless;
+
+impl PlatformAdapter for Headless {
+    fn play_sound(&mut self) {}
+
+    fn pause_sound(&mut self) {}
+}
+
+#[derive(Default)]
+struct EdgeSprites {
+    width: usize,
+    height: usize,
+    count: usize,
+}
+
+impl Observer for EdgeSprites {
+    fn on_sprite(&mut self, x: u8, y: u8, rows: usize, _collided: bool) {
+        if x as usize % self.width + 8 > self.width || y as usize % self.height + rows > self.height {
+            self.count += 1;
+        }
+    }
+}
+
+fn score_run(candidate: &mut Candidate, rom: &[u8]) {
+    let profile = candidate.profile;
+    let mut interpreter = match Chip8Interpreter::with_observer(Headless, rom.to_vec(), profile.memory_size(), EdgeSprites::default()) {
+        Ok(interpreter) => interpreter,
+        Err(err) => {
+            candidate.score += FAULT_SCORE;
+            candidate.reasons.push(format!("doesn't load: {}", err));
+            return;
+        }
+    };
+
+    if let Err(err) = interpreter.apply_profile(profile) {
+        candidate.score += FAULT_SCORE;
+        candidate.reasons.push(format!("doesn't load: {}", err));
+        return;
+    }
+    interpreter.quirks = candidate.quirks;
+    interpreter.observer.width = interpreter.display.width();
+    interpreter.observer.height = interpreter.display.height();
+
+    for frame in 0..INFER_FRAMES {
+        if let Err(err) = interpreter.run_frame(INFER_TICK_RATE) {
+            candidate.score += FAULT_SCORE;
+            candidate.reasons.push(format!("{} on frame {}", err, frame));
+            break;
+        }
+    }
+
+    if interpreter.observer.count > 0 && profile == Profile::SChip {
+        candidate.score += EDGE_SCORE;
+        candidate.reasons.push(format!("draws {} sprites across the screen edge, which S-CHIP clips", interpreter.observer.count));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_test() {
+        assert_eq!(Some(Extension::SChip), Extension::of(0x00FF));
+        assert_eq!(Some(Extension::SChip), Extension::of(0xD120));
+        assert_eq!(Some(Extension::XoChip), Extension::of(0xF000));
+        assert_eq!(Some(Extension::MegaChip), Extension::of(0x0011));
+        assert_eq!(Some(Extension::Chip8X), Extension::of(0xE1F2));
+        assert_eq!(None, Extension::of(0x00E0));
+        assert_eq!(None, Extension::of(0xD125));
+    }
+
+    #[test]
+    fn analyse_test() {
+        // LD I, 0x300; LD [I], V1; LD [I], V1; SHR V1, V2; JP 0x208; HIGH (unreachable)
+        let rom = [0xA3, 0x00, 0xF1, 0x55, 0xF1, 0x55, 0x81, 0x26, 0x12, 0x08, 0x00, 0xFF];
+        let findings = analyse(&rom);
+
+        assert_eq!(Some((0x202, 0xF155)), findings.i_increment);
+        assert_eq!(Some((0x206, 0x8126)), findings.vy_shift);
+        assert!(findings.extensions.is_empty());
+        assert!(!findings.hires_trampoline);
+    }
+
+    #[test]
+    fn infer_test() {
+        // HIGH; JP 0x202
+        let candidates = infer(&[0x00, 0xFF, 0x12, 0x02]);
+        assert_eq!(Profile::SChip, candidates[0].profile);
+        assert_eq!("uses S-CHIP instructions, like 00FF at 0x200", candidates[0].reasons[0]);
+
+        // The VIP has no 00FF, so it faults.
+        let vip = candidates.iter().find(|candidate| candidate.profile == Profile::CosmacVip).unwrap();
+        assert!(vip.score < 0);
+        assert_eq!("vip (-20)\n    InvalidOpcode 0x00FF at 0x200 (SYS 0x0FF) on frame 0", vip.to_string());
+
+        // LD I, 0x300; LD [I], V1; LD V0, [I]; JP 0x206
+        let candidates = infer(&[0xA3, 0x00, 0xF1, 0x55, 0xF0, 0x65, 0x12, 0x06]);
+        assert_eq!(Profile::CosmacVip, candidates[0].profile);
+        assert_eq!(Profile::CosmacVip.quirks(), candidates[0].quirks);
+        assert_eq!(QUIRK_SCORE, candidates[0].score);
+    }
+
+    #[test]
+    fn quirk_variants_test() {
+        let variants = quirk_variants(Profile::SChip);
+        assert_eq!(4, variants.len());
+        assert_eq!(QuirkFlags::NONE, variants[0]);
+        assert_eq!(QuirkFlags::QUIRK_8XY6 | QuirkFlags::QUIRK_8XYE, variants[1]);
+        assert_eq!(QuirkFlags::QUIRK_FX55 | QuirkFlags::QUIRK_FX65, variants[2]);
+        assert_eq!(QUIRK_GROUPS[0] | QUIRK_GROUPS[1], variants[3]);
+
+        let variants = quirk_variants(Profile::Chip8X);
+        assert_eq!(Profile::Chip8X.quirks(), variants[0]);
+        assert_eq!(QuirkFlags::CHIP8X, variants[3]);
+    }
+
+    #[test]
+    fn infer_quirks_test() {
+        // HIGH; LD I, 0x300; LD [I], V1; LD V0, [I]; JP 0x208
+        let candidates = infer(&[0x00, 0xFF, 0xA3, 0x00, 0xF1, 0x55, 0xF0, 0x65, 0x12, 0x08]);
+        assert_eq!(Profile::SChip, candidates[0].profile);
+        assert_eq!(QuirkFlags::QUIRK_FX55 | QuirkFlags::QUIRK_FX65, candidates[0].quirks);
+        assert!(candidates[0].to_string().starts_with("schip +fx55 +fx65 ("));
+
+        // Without the quirk, S-CHIP only has the extension to go on.
+        let schip = candidates.iter().find(|candidate| candidate.profile == Profile::SChip && candidate.quirks.is_empty()).unwrap();
+        assert_eq!(schip.score + QUIRK_SCORE, candidates[0].score);
+    }
+}
@@ -1,21 +1,49 @@
-use crate::{callstack, opcode, timer, platform_adapter, keycodes, quirk_flags};
+use std::error::Error;
+use std::fmt;
+
+use crate::{callstack, display, fault, memory_map, opcode, observer, timer, platform_adapter, keycodes, mega, profile, quirk_flags, rng, romdb, savestate, smc, sys, timing};
 
 use callstack::*;
+use display::*;
+use fault::*;
+use observer::*;
 use opcode::*;
 use timer::*;
 use platform_adapter::*;
 use keycodes::*;
+use mega::*;
+use memory_map::*;
+use profile::*;
 use quirk_flags::*;
+use rng::*;
+use romdb::*;
+use savestate::*;
+use smc::*;
+use sys::*;
+use timing::*;
 
 pub const RES_Y: usize = 32;
+pub const HIRES_RES_Y: usize = 64;
 pub const RES_X: usize = 64;
 
-const START_ADDR: usize = 0x200;
-const STACK_SZ: usize = 16;
-const MEM_SZ: usize = 4096;
-const REG_COUNT: usize = 16;
+pub const START_ADDR: usize = 0x200;
+pub const HIRES_START_ADDR: usize = 0x2C0; // Where the program proper starts, after the hires interpreter's own code.
+pub const CHIP8X_START_ADDR: usize = 0x300; // CHIP-8X's interpreter is bigger, so its programs load a page later.
+
+// CHIP-8X colors the display in zones 8 pixels wide and 1 pixel tall.
+pub const COLOR_ZONE_WIDTH: usize = 8;
+pub const COLOR_ZONES_X: usize = RES_X / COLOR_ZONE_WIDTH;
+pub const COLOR_COUNT: u8 = 8;
+pub const BACKGROUND_COUNT: u8 = 4;
+pub const DEFAULT_FOREGROUND: u8 = 1; // Red.
+pub const STACK_SZ: usize = 16;
+pub const MEM_SZ: usize = 4096;
+pub const REG_COUNT: usize = 16;
 
-const CHAR_TABLE_LEN: usize = 5 * 16; // 16 characters (0-F), 5 bytes each.
+// Past this many, dirty rects waiting to be taken are merged into one covering them all.
+pub const MAX_DIRTY_RECTS: usize = 64;
+
+pub const CHAR_TABLE_LEN: usize = 5 * 16; // 16 characters (0-F), 5 bytes each.
 const CHAR_TABLE: [u8; CHAR_TABLE_LEN] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // "0"
     0x20, 0x60, 0x20, 0x20, 0x70, // "1"
@@ -35,18 +63,82 @@ const CHAR_TABLE: [u8; CHAR_TABLE_LEN] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // "F"
 ];
 
-#[derive(Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum InterpreterErr {
     CallStackEmpty,
     CallStackOverflow,
     InvalidOpcode(u16),
-    InvalidRegister,
-    MemFault,
+    InvalidRegister(u8),
+    MemFault(MemAccess, u32),
+    PermissionFault(MemAccess, u32, RegionKind), // An access the memory map doesn't allow, in strict mode.
     DisplayFault,
     NonMonotonicClockValue,
+    TimingModeMismatch, // run_cycles without VIP timing, or step and run_frame with it.
     RomTooLarge,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MemAccess {
+    Read,
+    Write,
+    Execute,
+}
+
+impl MemAccess {
+    fn verb(&self) -> &'static str {
+        match self {
+            MemAccess::Read => "reading",
+            MemAccess::Write => "writing",
+            MemAccess::Execute => "executing",
+        }
+    }
+}
+
+impl fmt::Display for InterpreterErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterpreterErr::InvalidOpcode(instr) => write!(f, "InvalidOpcode {:#06X}", instr),
+            InterpreterErr::InvalidRegister(reg_idx) => write!(f, "InvalidRegister V{}", reg_idx),
+            InterpreterErr::MemFault(access, addr) => write!(f, "MemFault {} {:#06X}", access.verb(), addr),
+            InterpreterErr::PermissionFault(access, addr, region) => {
+                write!(f, "PermissionFault {} {:#06X} in {}", access.verb(), addr, region.name())
+            }
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl Error for InterpreterErr {}
+
+// An InterpreterErr raised by step, along with where the machine was when it happened.
+#[derive(Debug, PartialEq)]
+pub struct ExecutionError {
+    pub kind: InterpreterErr,
+    pub pc: u16,                              // Address of the faulting instruction.
+    pub instr: Option<DecodedInstruction>,    // None if the fault happened before an instruction was decoded.
+    pub call_stack: Vec<u16>,
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {:#05X}", self.kind, self.pc)?;
+
+        // Invalid opcodes decode without a mnemonic.
+        match &self.instr {
+            Some(instr) if !instr.mnemonic.is_empty() => write!(f, " ({})", instr.mnemonic)?,
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+impl Error for ExecutionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.kind)
+    }
+}
+
 fn from_stack_err(stack_err: CallStackErr) -> InterpreterErr { 
     match stack_err {
         CallStackErr::StackOverflow => InterpreterErr::CallStackOverflow,
@@ -59,22 +151,37 @@ pub struct KeyAwaitOp {
     pub dest_v_reg: u8,
 }
 
-pub struct Chip8Interpreter<T>
+pub struct Chip8Interpreter<T, O = NoopObserver>
 where
     T: PlatformAdapter,
+    O: Observer,
 {
     pub quirks: QuirkFlags,
     pub key_press: Option<KeyCodes>,
-    pub display_buffer: [[u8; RES_X]; RES_Y],
-    pub memory: [u8; MEM_SZ],
+    pub key_press_2: Option<KeyCodes>, // CHIP-8X's second keypad.
+    pub display: BitDisplay, // RES_X x RES_Y, or RES_X x HIRES_RES_Y in hires mode.
+    pub color_map: [[u8; COLOR_ZONES_X]; RES_Y], // CHIP-8X foreground color of each zone.
+    pub background_color: u8,                    // CHIP-8X background color.
+    pub memory: Vec<u8>, // MEM_SZ bytes, or more for MEGA-CHIP8.
     pub pc: u16,
     pub v_regs: [u8; REG_COUNT],
-    pub i_reg: u16,
+    pub i_reg: u32, // 16 bits, or 24 bits in MEGA-CHIP8's megamode.
     pub stack: CallStack,
     pub key_await_dest_reg: Option<KeyAwaitOp>,
     pub delay_timer: Timer,
     pub sound_timer: Timer,
     pub is_sound_playing: bool,
+    pub rng: Rng,
+    pub fault_policies: FaultPolicies,
+    pub memory_map: MemoryMap,
+    pub mega: Option<MegaDisplay>, // The MEGA-CHIP8 display, present while in megamode.
+    pub smc: Option<SmcTracker>,   // Self-modifying code tracking, off unless set.
+    pub timing: Option<VipTiming>, // Cycle-accurate VIP timing, on from enable_vip_timing.
+    dirty_rects: Vec<Rect>,
+    display_version: u64, // Bumped whenever the display changes, so frontends can tell when to redraw.
+    rom_start: usize,
+    rom_len: usize,
+    pub observer: O,
     platform_adapter: T,
 }
 
@@ -83,18 +190,57 @@ where
     T: PlatformAdapter,
 {
     pub fn new(platform_adapter: T, rom: Vec<u8>) -> Result<Self, InterpreterErr> {
+        Self::with_memory_size(platform_adapter, rom, MEM_SZ)
+    }
+
+    pub fn with_memory_size(platform_adapter: T, rom: Vec<u8>, mem_sz: usize) -> Result<Self, InterpreterErr> {
+        Self::with_observer(platform_adapter, rom, mem_sz, NoopObserver)
+    }
+
+    pub fn from_known_rom(platform_adapter: T, rom: Vec<u8>, db: &RomDb) -> Result<(Self, Option<RomInfo>), InterpreterErr> {
+        // Set up the profile and quirks the database has for the ROM, or the profile the ROM itself suggests if it
+        // isn't known or only runs on platforms we don't emulate. The database entry comes back too, with the speed
+        // to pass to run_frame (RomInfo::tick_rate) and the key bindings.
+        let info = db.lookup(&rom).cloned();
+        let known_profile = info.as_ref().and_then(|info| info.profile);
+        let profile = known_profile.or_else(|| Profile::detect(&rom));
+
+        let mem_sz = profile.map(|profile| profile.memory_size()).unwrap_or(MEM_SZ);
+        let mut interpreter = Self::with_memory_size(platform_adapter, rom, mem_sz)?;
+
+        if let Some(profile) = profile {
+            interpreter.apply_profile(profile)?;
+        }
+
+        if let (Some(info), Some(_)) = (&info, known_profile) {
+            interpreter.quirks = info.quirks;
+        }
+
+        Ok((interpreter, info))
+    }
+}
+
+impl<T, O> Chip8Interpreter<T, O>
+where
+    T: PlatformAdapter,
+    O: Observer,
+{
+    pub fn with_observer(platform_adapter: T, rom: Vec<u8>, mem_sz: usize, observer: O) -> Result<Self, InterpreterErr> {
         let rom_len  = rom.len();
         
-        if START_ADDR + rom_len >= MEM_SZ {
+        if START_ADDR + rom_len >= mem_sz {
             return Err(InterpreterErr::RomTooLarge)
         }
 
         let mut interpreter = Chip8Interpreter {
             quirks: QuirkFlags::NONE,
             key_press: Option::None,
+            key_press_2: Option::None,
             platform_adapter,
-            memory: [0; MEM_SZ],
-            display_buffer: [[0; RES_X]; RES_Y],
+            memory: vec![0; mem_sz],
+            display: BitDisplay::new(RES_X, RES_Y, 1),
+            color_map: [[DEFAULT_FOREGROUND; COLOR_ZONES_X]; RES_Y],
+            background_color: 0,
             pc: START_ADDR as u16,
             v_regs: [0; 16],
             i_reg: 0,
@@ -103,6 +249,17 @@ where
             delay_timer: Timer::new(),
             sound_timer: Timer::new(),
             is_sound_playing: false,
+            rng: Rng::default(),
+            fault_policies: FaultPolicies::default(),
+            memory_map: MemoryMap::standard(START_ADDR, rom_len, mem_sz),
+            mega: None,
+            smc: None,
+            timing: None,
+            dirty_rects: Vec::new(),
+            display_version: 0,
+            rom_start: START_ADDR,
+            rom_len,
+            observer,
         };
 
         // Copy the character table into memory.
@@ -118,21 +275,298 @@ where
         Ok(interpreter)
     }
 
-    pub fn step(&mut self, tick_rate: u64) -> Result<DecodedInstruction, InterpreterErr> {
+    pub fn apply_profile(&mut self, profile: Profile) -> Result<(), InterpreterErr> {
+        self.quirks = profile.quirks();
+
+        if profile.is_hires() {
+            self.enable_hires();
+        }
+
+        if profile == Profile::Chip8X {
+            self.enable_chip8x()?;
+        }
+
+        if profile == Profile::MegaChip {
+            self.enable_megachip();
+        }
+
+        Ok(())
+    }
+
+    pub fn enable_hires(&mut self) {
+        // Switch to the VIP's two-page hires CHIP-8: a 64x64 display, with the program starting past the 1260
+        // trampoline and the space the hires interpreter occupied.
+        self.display = BitDisplay::new(RES_X, HIRES_RES_Y, 1);
+        self.pc = HIRES_START_ADDR as u16;
+        self.mark_display_dirty();
+    }
+
+    pub fn enable_chip8x(&mut self) -> Result<(), InterpreterErr> {
+        // Move the program up to where CHIP-8X programs expect to be, unless it's there already. A ROM too big to
+        // fit from there is rejected, the same as one too big to load at all.
+        if self.rom_start != CHIP8X_START_ADDR {
+            if CHIP8X_START_ADDR + self.rom_len >= self.memory.len() {
+                return Err(InterpreterErr::RomTooLarge);
+            }
+
+            self.memory.copy_within(self.rom_start..self.rom_start + self.rom_len, CHIP8X_START_ADDR);
+            self.memory[self.rom_start..CHIP8X_START_ADDR].iter_mut().for_each(|byte| *byte = 0);
+            self.memory_map.relocate_rom(CHIP8X_START_ADDR);
+            self.rom_start = CHIP8X_START_ADDR;
+        }
 
-        self.check_sound_timer(tick_rate)?;
+        self.quirks |= QuirkFlags::CHIP8X;
+        self.pc = CHIP8X_START_ADDR as u16;
+
+        Ok(())
+    }
+
+    pub fn enable_megachip(&mut self) {
+        // Decode the MEGA-CHIP8 instructions and grow memory to what a 24-bit I can address. Megamode itself waits
+        // for the program to run 0011.
+        if self.memory.len() < MEGA_MEM_SZ {
+            self.memory.resize(MEGA_MEM_SZ, 0);
+            self.memory_map.resize(MEGA_MEM_SZ);
+        }
+
+        self.quirks |= QuirkFlags::MEGACHIP;
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.display.height() == HIRES_RES_Y
+    }
+
+    pub fn active_display(&self) -> &dyn Display {
+        // What frontends should show: the MEGA-CHIP8 display in megamode, and otherwise the ordinary one.
+        match &self.mega {
+            Some(mega) => mega,
+            None => &self.display,
+        }
+    }
+
+    pub fn display_version(&self) -> u64 {
+        self.display_version
+    }
+
+    pub fn take_dirty_rects(&mut self) -> Vec<Rect> {
+        // The regions of the active display changed since the last call, oldest first. They may overlap.
+        self.collect_dirty_rects();
+        std::mem::take(&mut self.dirty_rects)
+    }
+
+    fn collect_dirty_rects(&mut self) {
+        let rect = match self.mega.as_mut() {
+            Some(mega) => mega.take_dirty(),
+            None => self.display.take_dirty(),
+        };
+
+        if let Some(rect) = rect {
+            self.add_dirty_rect(rect);
+        }
+    }
+
+    fn mark_display_dirty(&mut self) {
+        // For changes that affect the whole display, like switching modes or colors.
+        let display = self.active_display();
+        let rect = Rect::new(0, 0, display.width(), display.height());
+
+        self.add_dirty_rect(rect);
+    }
+
+    fn add_dirty_rect(&mut self, rect: Rect) {
+        self.display_version += 1;
+        self.platform_adapter.on_display_changed(&rect, self.display_version);
+
+        self.dirty_rects.push(rect);
+        if self.dirty_rects.len() > MAX_DIRTY_RECTS {
+            let bounds = self.dirty_rects.iter().skip(1).fold(self.dirty_rects[0], |bounds, rect| bounds.union(rect));
+            self.dirty_rects = vec![bounds];
+        }
+    }
+
+    pub fn step(&mut self, tick_rate: u64) -> Result<DecodedInstruction, ExecutionError> {
+        let result = self.execute_step(tick_rate);
+        if let Err(err) = &result {
+            self.observer.on_error(err);
+        }
+
+        result
+    }
+
+    fn execute_step(&mut self, tick_rate: u64) -> Result<DecodedInstruction, ExecutionError> {
+        let pc = self.pc;
+
+        if self.timing.is_some() {
+            return Err(self.execution_error(InterpreterErr::TimingModeMismatch, pc, None));
+        }
+
+        self.check_sound_timer(tick_rate).map_err(|err| self.execution_error(err, pc, None))?;
+
+        Ok(self.execute_next()?.unwrap_or_else(DecodedInstruction::new))
+    }
+
+    fn execute_next(&mut self) -> Result<Option<DecodedInstruction>, ExecutionError> {
+        // Execute the next instruction, or nothing while waiting for a key press.
+        let pc = self.pc;
 
         // Execution should halt if FX0A was executed, which waits until a key has been pressed.
-        if !self.is_awaiting_key_press()? {
-            let opcode = self.fetch_next_instruction()?;
+        if self.is_awaiting_key_press().map_err(|err| self.execution_error(err, pc, None))? {
+            return Ok(None);
+        }
+
+        let opcode = self.fetch_next_instruction().map_err(|err| self.execution_error(err, pc, None))?;
+        self.rng.tick();
+
+        // The VIP only drew sprites once the display interrupt had come round.
+        if let (Some(timing), OpCode::OpCodeDxyn(..)) = (self.timing.as_mut(), &opcode.opcode) {
+            timing.wait_for_interrupt();
+            self.vip_interrupt();
+        }
+        let vx = self.v_regs[((opcode.instr >> 8) & 0xF) as usize];
+
+        let result = self.execute_instruction(&opcode);
+        self.collect_dirty_rects();
+
+        match result {
+            Ok(()) => {
+                if let Some(timing) = self.timing.as_mut() {
+                    timing.cycles += timing::cycle_cost(&opcode.opcode, vx, self.pc == pc.wrapping_add(4));
+                }
 
-            return match self.execute_instruction(&opcode) {
-                Ok(()) => Ok(opcode),
-                Err(err) => Err(err)
+                self.observer.on_instruction(pc, &opcode);
+                Ok(Some(opcode))
             }
+            Err(err) => Err(self.execution_error(err, pc, Some(opcode)))
         }
+    }
 
-        Ok(DecodedInstruction::new())
+    pub fn enable_vip_timing(&mut self) {
+        // Switch from the tick rate to VIP timing, after which the interpreter is run with run_cycles rather than
+        // step or run_frame.
+        if self.timing.is_none() {
+            self.timing = Some(VipTiming::new());
+        }
+    }
+
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<(), ExecutionError> {
+        // Run for a number of VIP machine cycles, with each instruction taking as long as it did on the VIP and the
+        // timers counting down in the display interrupt.
+        let end = match self.timing.as_ref() {
+            Some(timing) => timing.cycles + cycles,
+            None => {
+                let err = self.execution_error(InterpreterErr::TimingModeMismatch, self.pc, None);
+                self.observer.on_error(&err);
+                return Err(err);
+            }
+        };
+
+        while let Some(timing) = self.timing.as_mut() {
+            if timing.cycles >= end {
+                break;
+            }
+
+            if timing.is_interrupt_due() {
+                self.vip_interrupt();
+                continue;
+            }
+
+            match self.execute_next() {
+                Ok(Some(_)) => (),
+                Ok(None) => self.timing.as_mut().unwrap().wait_for_interrupt(),
+                Err(err) => {
+                    self.observer.on_error(&err);
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn vip_interrupt(&mut self) {
+        if let Some(timing) = self.timing.as_mut() {
+            timing.interrupt();
+        }
+
+        self.delay_timer.decrement();
+        let timer_val = self.sound_timer.decrement();
+        self.update_sound(timer_val);
+    }
+
+    fn execution_error(&self, kind: InterpreterErr, pc: u16, instr: Option<DecodedInstruction>) -> ExecutionError {
+        ExecutionError {
+            kind,
+            pc,
+            instr,
+            call_stack: self.stack.snapshot().unwrap_or_default(),
+        }
+    }
+
+    pub fn run_frame(&mut self, tick_rate: u64) -> Result<(), ExecutionError> {
+        // A frame lasts 1/60th of a second, so execute as many instructions as the tick rate allows in that time.
+        let steps_per_frame = std::cmp::max(1, tick_rate / 60);
+
+        for _ in 0..steps_per_frame {
+            self.step(tick_rate)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            quirks: self.quirks,
+            key_press: self.key_press,
+            key_press_2: self.key_press_2,
+            display: self.display.clone(),
+            color_map: self.color_map,
+            background_color: self.background_color,
+            memory: self.memory.clone(),
+            pc: self.pc,
+            v_regs: self.v_regs,
+            i_reg: self.i_reg,
+            stack: self.stack.clone(),
+            key_await_dest_reg: self.key_await_dest_reg,
+            delay_timer: self.delay_timer.clone(),
+            sound_timer: self.sound_timer.clone(),
+            is_sound_playing: self.is_sound_playing,
+            rng: self.rng,
+            mega: self.mega.clone(),
+            timing: self.timing.clone(),
+        }
+    }
+
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.quirks = state.quirks;
+        self.key_press = state.key_press;
+        self.key_press_2 = state.key_press_2;
+        self.display = state.display.clone();
+        self.color_map = state.color_map;
+        self.background_color = state.background_color;
+        self.memory = state.memory.clone();
+        self.pc = state.pc;
+        self.v_regs = state.v_regs;
+        self.i_reg = state.i_reg;
+        self.stack = state.stack.clone();
+        self.key_await_dest_reg = state.key_await_dest_reg;
+        self.delay_timer = state.delay_timer.clone();
+        self.sound_timer = state.sound_timer.clone();
+        self.rng = state.rng;
+        self.mega = state.mega.clone();
+        self.timing = state.timing.clone();
+        self.mark_display_dirty();
+
+        // Keep the platform's sound output in step with the restored sound timer.
+        if state.is_sound_playing != self.is_sound_playing {
+            if state.is_sound_playing {
+                self.platform_adapter.play_sound();
+                self.observer.on_sound_start();
+            } else {
+                self.platform_adapter.pause_sound();
+                self.observer.on_sound_stop();
+            }
+            self.is_sound_playing = state.is_sound_playing;
+        }
     }
 
     fn is_awaiting_key_press(&mut self) -> Result<bool, InterpreterErr> {
@@ -145,6 +579,7 @@ where
                 Some(keycode) => {
                     self.write_v_reg(key_await_op.dest_v_reg, keycode as u8)?;
                     self.key_await_dest_reg = Option::None;
+                    self.observer.on_key_wait_end(key_await_op.dest_v_reg, keycode);
                     Ok(false)
                 }
             },
@@ -152,11 +587,26 @@ where
     }
 
     fn fetch_next_instruction(&mut self) -> Result<DecodedInstruction, InterpreterErr> {
+        // Running off the end of memory can't be skipped like a bad read, so Ignore stops here as Trap does. Wrap
+        // carries on from the start of memory.
+        if self.pc as usize >= self.memory.len() {
+            let err = InterpreterErr::MemFault(MemAccess::Execute, self.pc as u32);
+            match self.resolve_fault(FaultClass::Memory, err)? {
+                FaultPolicy::Wrap => self.pc = (self.pc as usize % self.memory.len()) as u16,
+                _ => return Err(err),
+            }
+        }
+
         // Opcodes are 16 bits, so read two bytes.
-        let hi = self.read_mem(self.pc)? as u16;
-        let lo = self.read_mem(self.pc + 1)? as u16;
+        self.memory_map.check(self.pc as u32, MemAccess::Execute)?;
+        let hi = self.read_mem(self.pc as u32)? as u16;
+        let lo = self.read_mem(self.pc as u32 + 1)? as u16;
 
-        self.pc += 2;
+        if let Some(smc) = self.smc.as_mut() {
+            smc.on_fetch(self.pc, &self.memory);
+        }
+
+        self.pc = self.pc.wrapping_add(2);
 
         let instr = (hi << 8) | lo;
         Ok(opcode::decode(instr, self.quirks))
@@ -165,6 +615,7 @@ where
     fn execute_instruction(&mut self, decoded_instr: &DecodedInstruction) -> Result<(), InterpreterErr> {
         
         match decoded_instr.opcode {
+            OpCode::OpCode0nnn(addr) => self.execute_0nnn(decoded_instr.instr, addr),
             
             OpCode::OpCode00e0() => self.execute_00e0(),
 
@@ -268,82 +719,191 @@ where
                     self.execute_fx65(vx_idx)
                 }
             }
+
+            OpCode::OpCode02a0() => self.execute_02a0(),
+            OpCode::OpCodeBxy0(vx_idx, vy_idx) => self.execute_bxy0(vx_idx, vy_idx),
+            OpCode::OpCodeBxyn(vx_idx, vy_idx, count) => self.execute_bxyn(vx_idx, vy_idx, count),
+            OpCode::OpCodeExf2(vx_idx) => self.execute_exf2(vx_idx),
+            OpCode::OpCodeExf5(vx_idx) => self.execute_exf5(vx_idx),
+            OpCode::OpCodeFxf8(vx_idx) => self.execute_fxf8(vx_idx),
+            OpCode::OpCodeFxfb(vx_idx) => self.execute_fxfb(vx_idx),
+
+            OpCode::OpCode0010() => self.execute_0010(),
+            OpCode::OpCode0011() => self.execute_0011(),
+            OpCode::OpCode01nn(val) => self.execute_01nn(val),
+            OpCode::OpCode02nn(count) => self.execute_02nn(count),
+            OpCode::OpCode03nn(width) => self.execute_03nn(width),
+            OpCode::OpCode04nn(height) => self.execute_04nn(height),
+            OpCode::OpCode05nn(alpha) => self.execute_05nn(alpha),
+            OpCode::OpCode060n(mode) => self.execute_060n(mode),
+            OpCode::OpCode0700() => self.execute_0700(),
+            OpCode::OpCode080n(mode) => self.execute_080n(decoded_instr.instr, mode),
+            OpCode::OpCode00bn(rows) => self.execute_00bn(rows),
             
-            OpCode::OpCodeInvalid() => Err(InterpreterErr::InvalidOpcode(decoded_instr.instr))
+            OpCode::OpCodeInvalid() => {
+                // Anything but Trap skips over the unknown instruction.
+                self.resolve_fault(FaultClass::InvalidOpcode, InterpreterErr::InvalidOpcode(decoded_instr.instr))?;
+                Ok(())
+            }
         }
     }
 
-    fn read_mem(&self, addr: u16) -> Result<u8, InterpreterErr> {
+    fn i_reg_mask(&self) -> u32 {
+        // Megamode's long I is 24 bits.
+        if self.mega.is_some() {
+            0xFF_FFFF
+        } else {
+            0xFFFF
+        }
+    }
+
+    fn resolve_fault(&mut self, class: FaultClass, err: InterpreterErr) -> Result<FaultPolicy, InterpreterErr> {
+        // Returns the policy to carry on with, or the error if the fault should trap.
+        let policy = match self.fault_policies.get(class) {
+            FaultPolicy::Hook => self.platform_adapter.on_fault(&err),
+            policy => policy,
+        };
+
+        match policy {
+            FaultPolicy::Trap | FaultPolicy::Hook => Err(err),
+            policy => Ok(policy),
+        }
+    }
+
+    fn read_mem(&mut self, addr: u32) -> Result<u8, InterpreterErr> {
         let idx = addr as usize;
-        if idx >= MEM_SZ {
-            return Err(InterpreterErr::MemFault);
+        if idx >= self.memory.len() {
+            return match self.resolve_fault(FaultClass::Memory, InterpreterErr::MemFault(MemAccess::Read, addr))? {
+                FaultPolicy::Wrap => {
+                    let idx = idx % self.memory.len();
+                    self.memory_map.check(idx as u32, MemAccess::Read)?;
+                    self.observer.on_mem_read(idx as u32, self.memory[idx]);
+                    Ok(self.memory[idx])
+                }
+                _ => Ok(0),
+            };
         }
 
+        self.memory_map.check(addr, MemAccess::Read)?;
+        self.observer.on_mem_read(addr, self.memory[idx]);
         Ok(self.memory[idx])
     }
 
-    fn write_mem(&mut self, addr: u16, val: u8) -> Result<(), InterpreterErr> {
+    fn write_mem(&mut self, addr: u32, val: u8) -> Result<(), InterpreterErr> {
         let idx = addr as usize;
-        if idx >= MEM_SZ {
-            return Err(InterpreterErr::MemFault);
+        if idx >= self.memory.len() {
+            if self.resolve_fault(FaultClass::Memory, InterpreterErr::MemFault(MemAccess::Write, addr))? == FaultPolicy::Wrap {
+                let idx = idx % self.memory.len();
+                self.memory_map.check(idx as u32, MemAccess::Write)?;
+                self.store_mem(idx, val);
+            }
+
+            return Ok(());
         }
 
-        self.memory[idx] = val;
+        self.memory_map.check(addr, MemAccess::Write)?;
+        self.store_mem(idx, val);
         Ok(())
     }
 
+    fn store_mem(&mut self, idx: usize, val: u8) {
+        let old_val = self.memory[idx];
+        self.memory[idx] = val;
+
+        if let Some(smc) = self.smc.as_mut() {
+            smc.on_write(idx as u32, old_val, &self.memory);
+        }
+
+        self.observer.on_mem_write(idx as u32, val);
+    }
+
     fn write_v_reg(&mut self, reg_idx: u8, val: u8) -> Result<(), InterpreterErr> {
         let idx = reg_idx as usize;
         if idx >= REG_COUNT {
-            return Err(InterpreterErr::InvalidRegister);
+            return Err(InterpreterErr::InvalidRegister(reg_idx));
         }
 
         self.v_regs[idx] = val;
+        self.observer.on_reg_write(reg_idx, val);
         Ok(())
     }
 
     fn read_v_reg(&self, reg_idx: u8) -> Result<u8, InterpreterErr> {
         let idx = reg_idx as usize;
         if idx >= REG_COUNT {
-            return Err(InterpreterErr::InvalidRegister);
+            return Err(InterpreterErr::InvalidRegister(reg_idx));
         }
 
         Ok(self.v_regs[idx])
     }
 
-    fn draw(&mut self, x: u8, y: u8, val: u8) -> bool {
-        let x_idx = x as usize % RES_X;
-        let y_idx = y as usize % RES_Y;
-
-        let original_val = self.display_buffer[y_idx][x_idx];
-        let new_val = original_val ^ val; // The CHIP-8 sets pixels by XOR'ing the new value with the existing value.
-        self.display_buffer[y_idx][x_idx] = new_val;
-
-        if val == original_val && val == 1 {
-            return true // The position has been toggled off.
-        }
-
-        false
-    }
-
     fn start_delay_timer(&mut self, start_val: u8) {
         self.delay_timer.set(start_val);
+        self.observer.on_timer_set(TimerKind::Delay, start_val);
     }
 
     fn start_sound_timer(&mut self, start_val: u8) {
         self.sound_timer.set(start_val);
+        self.observer.on_timer_set(TimerKind::Sound, start_val);
         
         self.platform_adapter.play_sound();
+        if !self.is_sound_playing {
+            self.observer.on_sound_start();
+        }
         self.is_sound_playing = true;
     }
 
     fn check_sound_timer(&mut self, tick_rate: u64) -> Result<(), InterpreterErr> {
         let timer_val = self.sound_timer.tick(tick_rate);
-        
+        self.update_sound(timer_val);
+
+        Ok(())
+    }
+
+    fn update_sound(&mut self, timer_val: u8) {
         if timer_val == 0 && self.is_sound_playing {
             self.platform_adapter.pause_sound();
+            self.observer.on_sound_stop();
             self.is_sound_playing = false;
         }
+    }
+
+    fn execute_0nnn(&mut self, instr: u16, addr: u16) -> Result<(), InterpreterErr> {
+        // Execute 0NNN. Run the machine-code routine at NNN, which only the host or the built-in table can do.
+        // The routine gets a copy of memory, and whatever it changes is written back afterwards.
+        let mut memory = self.memory.clone();
+        let mut machine = Machine {
+            memory: &mut memory,
+            display: &mut self.display,
+            v_regs: &mut self.v_regs,
+            i_reg: &mut self.i_reg,
+            pc: &mut self.pc,
+        };
+
+        if self.platform_adapter.sys_call(addr, &mut machine) != SysCallResult::Handled {
+            match SysRoutine::lookup(addr) {
+                Some(routine) => routine.run(&mut machine),
+                None => {
+                    self.resolve_fault(FaultClass::InvalidOpcode, InterpreterErr::InvalidOpcode(instr))?;
+                }
+            }
+        }
+
+        self.write_back(&memory)
+    }
+
+    fn write_back(&mut self, memory: &[u8]) -> Result<(), InterpreterErr> {
+        // Store what a routine changed like any other write, through the memory map, self-modifying code tracking and
+        // the observer. A single write the map forbids fails the routine, and none of its writes land.
+        let changed: Vec<usize> = (0..memory.len()).filter(|&idx| memory[idx] != self.memory[idx]).collect();
+
+        for &idx in changed.iter() {
+            self.memory_map.check(idx as u32, MemAccess::Write)?;
+        }
+
+        for idx in changed {
+            self.store_mem(idx, memory[idx]);
+        }
 
         Ok(())
     }
@@ -351,17 +911,25 @@ where
     fn execute_00ee(&mut self) -> Result<(), InterpreterErr> {
         // Execute 00EE. Return from the current subroutine.
         // i.e. return;
-        self.pc = self.stack.pop().map_err(from_stack_err)?;
+        match self.stack.pop() {
+            Ok(addr) => {
+                self.observer.on_return(self.pc.wrapping_sub(2), addr);
+                self.pc = addr;
+            }
+            Err(err) => {
+                self.resolve_fault(FaultClass::StackUnderflow, from_stack_err(err))?;
+            }
+        }
 
         Ok(())
     }
 
     fn execute_00e0(&mut self) -> Result<(), InterpreterErr> {
         // Execute 00E0. Clear the display.
-        for y in 0..RES_Y {
-            for x in 0..RES_X {
-                self.display_buffer[y][x] = 0;
-            }
+        // In megamode this also presents the frame drawn since the last 00E0.
+        match self.mega.as_mut() {
+            Some(mega) => mega.clear(ALL_PLANES),
+            None => self.display.clear(ALL_PLANES),
         }
 
         Ok(())
@@ -378,7 +946,14 @@ where
     fn execute_2nnn(&mut self, addr: u16) -> Result<(), InterpreterErr> {
         // Execute 2NNN. Call the subroutine in memory at NNN.
         // i.e. *(NNN)();
-        self.stack.push(self.pc).map_err(from_stack_err)?;
+        if let Err(err) = self.stack.push(self.pc) {
+            match self.resolve_fault(FaultClass::StackOverflow, from_stack_err(err))? {
+                FaultPolicy::Wrap => self.stack.push_wrapping(self.pc),
+                _ => return Ok(()),
+            }
+        }
+
+        self.observer.on_call(self.pc.wrapping_sub(2), addr);
         self.pc = addr;
 
         Ok(())
@@ -390,7 +965,7 @@ where
         let vx_val = self.read_v_reg(vx_idx)?;
 
         if vx_val == val {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
 
         Ok(())
@@ -402,7 +977,7 @@ where
         let vx_val = self.read_v_reg(vx_idx)?;
 
         if vx_val != val {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
 
         Ok(())
@@ -415,7 +990,7 @@ where
         let vy_val = self.read_v_reg(vy_idx)?;
 
         if vx_val == vy_val {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
 
         Ok(())
@@ -603,7 +1178,7 @@ where
         let vy_val = self.read_v_reg(vy_idx)?;
 
         if vx_val != vy_val {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
 
         Ok(())
@@ -612,7 +1187,7 @@ where
     fn execute_annn(&mut self, addr: u16) -> Result<(), InterpreterErr> {
         // Execute ANNN. Set I to NNN.
         // i.e. I = NNN
-        self.i_reg = addr;
+        self.i_reg = addr as u32;
 
         Ok(())
     }
@@ -621,7 +1196,7 @@ where
         // Execute BNNN. Set I to NNN + V0.
         // i.e. I = V0 + NNN
         let v0_val = self.read_v_reg(0x00)? as u16;
-        self.i_reg = addr + v0_val;
+        self.i_reg = (addr + v0_val) as u32;
 
         Ok(())
     }
@@ -629,7 +1204,7 @@ where
     fn execute_cxnn(&mut self, vx_idx: u8, mask: u8) -> Result<(), InterpreterErr> {
         // Execute CXNN. Set VX to a random number masked by NN.
         // i.e VX = rand() & NN
-        let rand_val = self.platform_adapter.get_random_val();
+        let rand_val = self.rng.next(&self.memory);
 
         let result = rand_val & mask;
         self.write_v_reg(vx_idx, result)?;
@@ -645,36 +1220,19 @@ where
         let x_start = self.read_v_reg(vx_idx)?;
         let y_start = self.read_v_reg(vy_idx)?;
 
-        let addr = self.i_reg;
-        let mut did_toggle_pixel_off = false;
-
-        for line_num in 0..count {
-            let sprite_data = self.read_mem(addr + line_num as u16)?;
-
-            did_toggle_pixel_off |=
-                self.draw(x_start, y_start + line_num, (sprite_data >> 7) & 1);
-            did_toggle_pixel_off |=
-                self.draw(x_start + 1, y_start + line_num, (sprite_data >> 6) & 1);
-            did_toggle_pixel_off |=
-                self.draw(x_start + 2, y_start + line_num, (sprite_data >> 5) & 1);
-            did_toggle_pixel_off |=
-                self.draw(x_start + 3, y_start + line_num, (sprite_data >> 4) & 1);
-            did_toggle_pixel_off |=
-                self.draw(x_start + 4, y_start + line_num, (sprite_data >> 3) & 1);
-            did_toggle_pixel_off |=
-                self.draw(x_start + 5, y_start + line_num, (sprite_data >> 2) & 1);
-            did_toggle_pixel_off |=
-                self.draw(x_start + 6, y_start + line_num, (sprite_data >> 1) & 1);
-            did_toggle_pixel_off |=
-                self.draw(x_start + 7, y_start + line_num, sprite_data & 1);
-        }
-
-        if did_toggle_pixel_off {
-            self.write_v_reg(0x0F, 0x01)?;
-        } else {
-            self.write_v_reg(0x0F, 0x00)?;
+        if self.mega.is_some() {
+            return self.draw_mega_sprite(x_start, y_start);
+        }
+
+        let mut sprite = Vec::with_capacity(count as usize);
+        for line_num in 0..count as u32 {
+            sprite.push(self.read_mem(self.i_reg.wrapping_add(line_num))?);
         }
 
+        let did_toggle_pixel_off = self.display.draw_sprite(0, x_start as usize, y_start as usize, &sprite, 8);
+        self.observer.on_sprite(x_start, y_start, count as usize, did_toggle_pixel_off);
+        self.write_v_reg(0x0F, did_toggle_pixel_off as u8)?;
+
         Ok(())
     }
 
@@ -687,7 +1245,7 @@ where
             
             Some(keycode) => {
                 if keycode as u8 == vx_val {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
             }
 
@@ -706,7 +1264,7 @@ where
             
             Some(keycode) => {
                 if keycode as u8 != vx_val {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
                 }
             }
 
@@ -719,7 +1277,11 @@ where
     fn execute_fx07(&mut self, vx_idx: u8) -> Result<(), InterpreterErr> {
         // Execute FX07. Set VX to the value of the delay timer.
         // i.e VX = get_delay_value();
-        let delay_value = self.delay_timer.tick(100);
+        // With VIP timing the interrupt counts the timer down instead.
+        let delay_value = match self.timing {
+            Some(_) => self.delay_timer.current_val,
+            None => self.delay_timer.tick(100),
+        };
 
         self.write_v_reg(vx_idx, delay_value)?;
 
@@ -730,6 +1292,7 @@ where
         // Execute FX07. Halt execution until a key is pressed. Store the key-press in VX.
         // i.e. VX = await get_key_press();
         self.key_await_dest_reg = Option::Some(KeyAwaitOp { dest_v_reg: vx_idx });
+        self.observer.on_key_wait_begin(vx_idx);
 
         Ok(())
     }
@@ -756,8 +1319,7 @@ where
         // Execute FX1E. Add VX to I. Do not modify VF.
         // i.e. I += VX;
         let val = self.read_v_reg(vx_idx)?;
-        let (sum, _) = self.i_reg.overflowing_add(val as u16);
-        self.i_reg = sum;
+        self.i_reg = (self.i_reg + val as u32) & self.i_reg_mask();
 
         Ok(())
     }
@@ -768,8 +1330,9 @@ where
         // i.e. I += VX;
         let val = self.read_v_reg(vx_idx)?;
         
-        let (sum, did_overflow) = self.i_reg.overflowing_add(val as u16);
-        self.i_reg = sum;
+        let sum = self.i_reg + val as u32;
+        let did_overflow = sum > self.i_reg_mask();
+        self.i_reg = sum & self.i_reg_mask();
 
         if did_overflow {
             self.write_v_reg(0x0F, 0x01)?;
@@ -785,7 +1348,7 @@ where
         // i.e. I = get_char_addr(VX);
         let val = self.read_v_reg(vx_idx)?;
 
-        self.i_reg = (val * 5) as u16; // Characters are 5 bytes long and are stored in sequential order (0-F) starting at address 0x000.
+        self.i_reg = (val * 5) as u32; // Characters are 5 bytes long and are stored in sequential order (0-F) starting at address 0x000.
         
         Ok(())
     }
@@ -800,8 +1363,8 @@ where
         let ones_place = val % 10;
 
         self.write_mem(self.i_reg, hundreds_place)?;
-        self.write_mem(self.i_reg + 1, tens_place)?;
-        self.write_mem(self.i_reg + 2, ones_place)?;
+        self.write_mem(self.i_reg.wrapping_add(1), tens_place)?;
+        self.write_mem(self.i_reg.wrapping_add(2), ones_place)?;
         
         Ok(())
     }
@@ -811,7 +1374,7 @@ where
         // i.e for x in [0,X] { mem[I + x] = Vx; }
         for x in 0x0..=vx_idx {
             let v_reg_val = self.read_v_reg(x)?;
-            self.write_mem(self.i_reg + x as u16, v_reg_val)?
+            self.write_mem(self.i_reg.wrapping_add(x as u32), v_reg_val)?
         }
 
         Ok(())
@@ -822,10 +1385,10 @@ where
         // i.e for x in [0,X] { mem[I + x] = Vx; } I += X + 1;
         for x in 0x0..=vx_idx {
             let v_reg_val = self.read_v_reg(x)?;
-            self.write_mem(self.i_reg + x as u16, v_reg_val)?
+            self.write_mem(self.i_reg.wrapping_add(x as u32), v_reg_val)?
         }
 
-        self.i_reg += vx_idx as u16 + 1;
+        self.i_reg += vx_idx as u32 + 1;
 
         Ok(())
     }
@@ -834,7 +1397,7 @@ where
         // Execute FX55. Load I..I+X into V0..VX and do not modify I.
         // i.e for x in [0,X] { Vx = I + x; }
         for x in 0x0..=vx_idx {
-            let mem_val = self.read_mem(self.i_reg + x as u16)?;
+            let mem_val = self.read_mem(self.i_reg.wrapping_add(x as u32))?;
             self.write_v_reg(x, mem_val)?
         }
 
@@ -845,11 +1408,249 @@ where
         // Execute FX55. Load I..I+X into V0..VX and set I to I + X + 1.
         // i.e for x in [0,X] { Vx = I + x; } I += X + 1;
         for x in 0x0..=vx_idx {
-            let mem_val = self.read_mem(self.i_reg + x as u16)?;
+            let mem_val = self.read_mem(self.i_reg.wrapping_add(x as u32))?;
             self.write_v_reg(x, mem_val)?
         }
 
-        self.i_reg += vx_idx as u16 + 1;
+        self.i_reg += vx_idx as u32 + 1;
+
+        Ok(())
+    }
+
+    fn execute_02a0(&mut self) -> Result<(), InterpreterErr> {
+        // Execute 02A0 (CHIP-8X). Step the background to the next of its four colors.
+        self.background_color = (self.background_color + 1) % BACKGROUND_COUNT;
+        self.mark_display_dirty();
+
+        Ok(())
+    }
+
+    fn execute_bxy0(&mut self, vx_idx: u8, vy_idx: u8) -> Result<(), InterpreterErr> {
+        // Execute BXY0 (CHIP-8X). Set the foreground color of a block of 8x4 pixel areas to VY.
+        // VX holds the left area in its low nibble and the width less one in its high nibble, and VX+1 the same for
+        // the top area and height.
+        let horizontal = self.read_v_reg(vx_idx)?;
+        let vertical = self.read_v_reg(vx_idx + 1)?;
+        let color = self.read_v_reg(vy_idx)? % COLOR_COUNT;
+
+        const AREA_HEIGHT: usize = 4;
+
+        let left = (horizontal & 0x0F) as usize;
+        let top = (vertical & 0x0F) as usize * AREA_HEIGHT;
+        let right = std::cmp::min(COLOR_ZONES_X, left + (horizontal >> 4) as usize + 1);
+        let bottom = std::cmp::min(RES_Y, top + ((vertical >> 4) as usize + 1) * AREA_HEIGHT);
+
+        // Areas starting off the screen color nothing.
+        if left >= COLOR_ZONES_X || top >= RES_Y {
+            return Ok(());
+        }
+
+        for row in self.color_map.iter_mut().take(bottom).skip(top) {
+            for zone in row.iter_mut().take(right).skip(left) {
+                *zone = color;
+            }
+        }
+        self.add_dirty_rect(Rect::new(left * COLOR_ZONE_WIDTH, top, (right - left) * COLOR_ZONE_WIDTH, bottom - top));
+
+        Ok(())
+    }
+
+    fn execute_bxyn(&mut self, vx_idx: u8, vy_idx: u8, count: u8) -> Result<(), InterpreterErr> {
+        // Execute BXYN (CHIP-8X). Set the foreground color of the zones covering N rows from (VX, VY) to VX+1,
+        // wrapping like sprites do.
+        let x = self.read_v_reg(vx_idx)? as usize % RES_X;
+        let y = self.read_v_reg(vy_idx)? as usize;
+        let color = self.read_v_reg(vx_idx + 1)? % COLOR_COUNT;
+
+        for row in 0..count as usize {
+            self.color_map[(y + row) % RES_Y][x / COLOR_ZONE_WIDTH] = color;
+        }
+        self.mark_display_dirty();
+
+        Ok(())
+    }
+
+    fn execute_exf2(&mut self, vx_idx: u8) -> Result<(), InterpreterErr> {
+        // Execute EXF2 (CHIP-8X). Skip the next instruction if VX equals the key pressed on the second keypad.
+        let vx_val = self.read_v_reg(vx_idx)?;
+
+        if self.key_press_2.map(|keycode| keycode as u8) == Some(vx_val) {
+            self.pc = self.pc.wrapping_add(2);
+        }
+
+        Ok(())
+    }
+
+    fn execute_exf5(&mut self, vx_idx: u8) -> Result<(), InterpreterErr> {
+        // Execute EXF5 (CHIP-8X). Skip the next instruction if VX doesn't equal the key pressed on the second keypad.
+        let vx_val = self.read_v_reg(vx_idx)?;
+
+        if self.key_press_2.map(|keycode| keycode as u8) != Some(vx_val) {
+            self.pc = self.pc.wrapping_add(2);
+        }
+
+        Ok(())
+    }
+
+    fn execute_fxf8(&mut self, vx_idx: u8) -> Result<(), InterpreterErr> {
+        // Execute FXF8 (CHIP-8X). Output VX to the I/O port.
+        let vx_val = self.read_v_reg(vx_idx)?;
+        self.platform_adapter.port_out(vx_val);
+
+        Ok(())
+    }
+
+    fn execute_fxfb(&mut self, vx_idx: u8) -> Result<(), InterpreterErr> {
+        // Execute FXFB (CHIP-8X). Wait for a byte from the I/O port and store it in VX.
+        match self.platform_adapter.port_in() {
+            Some(val) => self.write_v_reg(vx_idx, val)?,
+
+            // Nothing yet, so run this instruction again next step.
+            None => self.pc = self.pc.wrapping_sub(2),
+        }
+
+        Ok(())
+    }
+
+    fn draw_mega_sprite(&mut self, x: u8, y: u8) -> Result<(), InterpreterErr> {
+        // DXYN in megamode. Draw a sprite of palette indices, one byte per pixel, of the size set by 03NN and 04NN.
+        // Set VF to 1 if it overlapped anything already drawn.
+        let (width, height) = match self.mega.as_ref() {
+            Some(mega) => (mega.sprite_width, mega.sprite_height),
+            None => return Ok(()),
+        };
+
+        let mut sprite = Vec::with_capacity(width * height);
+        for offset in 0..(width * height) as u32 {
+            sprite.push(self.read_mem(self.i_reg.wrapping_add(offset))?);
+        }
+
+        let collided = match self.mega.as_mut() {
+            Some(mega) => mega.draw_indexed(x as usize, y as usize, &sprite),
+            None => false,
+        };
+        self.observer.on_sprite(x, y, height, collided);
+        self.write_v_reg(0x0F, collided as u8)?;
+
+        Ok(())
+    }
+
+    fn execute_0010(&mut self) -> Result<(), InterpreterErr> {
+        // Execute 0010 (MEGA-CHIP8). Leave megamode for the ordinary display.
+        self.mega = None;
+        self.i_reg &= self.i_reg_mask();
+        self.platform_adapter.stop_digitized();
+        self.mark_display_dirty();
+
+        Ok(())
+    }
+
+    fn execute_0011(&mut self) -> Result<(), InterpreterErr> {
+        // Execute 0011 (MEGA-CHIP8). Enter megamode with a cleared 256x192 display.
+        self.mega = Some(MegaDisplay::new());
+        self.mark_display_dirty();
+
+        Ok(())
+    }
+
+    fn execute_01nn(&mut self, val: u8) -> Result<(), InterpreterErr> {
+        // Execute 01NN (MEGA-CHIP8). Set I to NN, followed by the 16 bits of the next word.
+        // i.e. I = (NN << 16) | *(PC)
+        let hi = self.read_mem(self.pc as u32)? as u32;
+        let lo = self.read_mem(self.pc as u32 + 1)? as u32;
+        self.pc = self.pc.wrapping_add(2);
+
+        self.i_reg = ((val as u32) << 16 | hi << 8 | lo) & self.i_reg_mask();
+
+        Ok(())
+    }
+
+    fn execute_02nn(&mut self, count: u8) -> Result<(), InterpreterErr> {
+        // Execute 02NN (MEGA-CHIP8). Load NN ARGB colors from I into the palette, starting at index 1.
+        let mut colors = Vec::with_capacity(count as usize * 4);
+        for offset in 0..count as u32 * 4 {
+            colors.push(self.read_mem(self.i_reg.wrapping_add(offset))?);
+        }
+
+        if let Some(mega) = self.mega.as_mut() {
+            mega.load_palette(&colors);
+        }
+
+        Ok(())
+    }
+
+    fn execute_03nn(&mut self, width: u8) -> Result<(), InterpreterErr> {
+        // Execute 03NN (MEGA-CHIP8). Set the sprite width to NN, where 0 means 256.
+        if let Some(mega) = self.mega.as_mut() {
+            mega.sprite_width = if width == 0 { 256 } else { width as usize };
+        }
+
+        Ok(())
+    }
+
+    fn execute_04nn(&mut self, height: u8) -> Result<(), InterpreterErr> {
+        // Execute 04NN (MEGA-CHIP8). Set the sprite height to NN, where 0 means 256.
+        if let Some(mega) = self.mega.as_mut() {
+            mega.sprite_height = if height == 0 { 256 } else { height as usize };
+        }
+
+        Ok(())
+    }
+
+    fn execute_05nn(&mut self, alpha: u8) -> Result<(), InterpreterErr> {
+        // Execute 05NN (MEGA-CHIP8). Set the opacity sprites are drawn with.
+        if let Some(mega) = self.mega.as_mut() {
+            mega.alpha = alpha;
+        }
+
+        Ok(())
+    }
+
+    fn execute_060n(&mut self, mode: u8) -> Result<(), InterpreterErr> {
+        // Execute 060N (MEGA-CHIP8). Play the digitized sound at I, looping it if N is 0.
+        let addr = self.i_reg;
+
+        match self.memory.get(addr as usize..).and_then(parse_sound) {
+            Some((sample_rate, samples)) => self.platform_adapter.play_digitized(samples, sample_rate, mode == 0),
+
+            // The header runs off the end of memory, or promises more samples than there are.
+            None => {
+                self.resolve_fault(FaultClass::Memory, InterpreterErr::MemFault(MemAccess::Read, addr))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute_0700(&mut self) -> Result<(), InterpreterErr> {
+        // Execute 0700 (MEGA-CHIP8). Stop the digitized sound.
+        self.platform_adapter.stop_digitized();
+
+        Ok(())
+    }
+
+    fn execute_080n(&mut self, instr: u16, mode: u8) -> Result<(), InterpreterErr> {
+        // Execute 080N (MEGA-CHIP8). Set the blend mode sprites are drawn with.
+        match BlendMode::from_u8(mode) {
+            Some(blend_mode) => {
+                if let Some(mega) = self.mega.as_mut() {
+                    mega.blend_mode = blend_mode;
+                }
+            }
+
+            None => {
+                self.resolve_fault(FaultClass::InvalidOpcode, InterpreterErr::InvalidOpcode(instr))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute_00bn(&mut self, rows: u8) -> Result<(), InterpreterErr> {
+        // Execute 00BN (MEGA-CHIP8). Scroll the display up N rows.
+        if let Some(mega) = self.mega.as_mut() {
+            mega.scroll_up(ALL_PLANES, rows as usize);
+        }
 
         Ok(())
     }
@@ -858,19 +1659,33 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     struct MockPlatform {
-        random_val: u8,
         play_count: u8,
         pause_count: u8,
+        faults: Vec<InterpreterErr>,
+        fault_policy: FaultPolicy,
+        sys_calls: Vec<u16>,
+        port_out: Vec<u8>,
+        port_in: Option<u8>,
+        digitized: Option<(Vec<u8>, u16, bool)>,
+        display_changes: Vec<(Rect, u64)>,
     }
 
     impl MockPlatform {
         fn new() -> Self {
             MockPlatform {
-                random_val: 0,
                 play_count: 0,
                 pause_count: 0,
+                faults: Vec::new(),
+                fault_policy: FaultPolicy::Trap,
+                sys_calls: Vec::new(),
+                port_out: Vec::new(),
+                port_in: None,
+                digitized: None,
+                display_changes: Vec::new(),
             }
         }
     }
@@ -884,8 +1699,47 @@ mod tests {
             self.pause_count += 1;
         }
 
-        fn get_random_val(&self) -> u8 {
-            self.random_val
+        fn on_fault(&mut self, fault: &InterpreterErr) -> FaultPolicy {
+            self.faults.push(*fault);
+            self.fault_policy
+        }
+
+        fn sys_call(&mut self, addr: u16, machine: &mut Machine) -> SysCallResult {
+            // Pretend there's a routine at 0x300 that sets VF.
+            self.sys_calls.push(addr);
+
+            if addr == 0x300 {
+                machine.v_regs[0xF] = 0x01;
+                return SysCallResult::Handled;
+            }
+
+            // And one at 0x310 that overwrites the font.
+            if addr == 0x310 {
+                machine.memory[0x000] = 0xFF;
+                return SysCallResult::Handled;
+            }
+
+            SysCallResult::Unhandled
+        }
+
+        fn port_out(&mut self, val: u8) {
+            self.port_out.push(val);
+        }
+
+        fn port_in(&mut self) -> Option<u8> {
+            self.port_in
+        }
+
+        fn play_digitized(&mut self, samples: &[u8], sample_rate: u16, looping: bool) {
+            self.digitized = Some((samples.to_vec(), sample_rate, looping));
+        }
+
+        fn stop_digitized(&mut self) {
+            self.digitized = None;
+        }
+
+        fn on_display_changed(&mut self, rect: &Rect, version: u64) {
+            self.display_changes.push((*rect, version));
         }
     }
 
@@ -897,8 +1751,8 @@ mod tests {
     fn fetch_instruction() {
         let mut interpreter = get_new_interpreter();
         let start_addr = START_ADDR as u16;
-        interpreter.write_mem(start_addr, 0xD1).unwrap();
-        interpreter.write_mem(start_addr + 1, 0xCD).unwrap();
+        interpreter.write_mem(start_addr as u32, 0xD1).unwrap();
+        interpreter.write_mem(start_addr as u32 + 1, 0xCD).unwrap();
 
         let decoded_instr = interpreter.fetch_next_instruction().unwrap();
         assert_eq!(start_addr + 2, interpreter.pc);
@@ -906,13 +1760,615 @@ mod tests {
         assert_eq!(OpCode::OpCodeDxyn(0x1, 0xC, 0xD), decoded_instr.opcode);
     }
 
+    #[test]
+    fn run_frame_test() {
+        // A frame at 600 ticks/sec should execute 10 instructions.
+        let rom = [0x70, 0x01].repeat(20);
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+
+        interpreter.run_frame(600).unwrap();
+
+        assert_eq!(10, interpreter.read_v_reg(0x00).unwrap());
+        assert_eq!(START_ADDR as u16 + 20, interpreter.pc);
+    }
+
+    #[test]
+    fn execution_error_test() {
+        // CALL 0x204; LD I, 0xFFF; LD [I], V1
+        let rom = vec![0x22, 0x02, 0xAF, 0xFF, 0xF1, 0x55];
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+
+        interpreter.step(600).unwrap();
+        interpreter.step(600).unwrap();
+        let err = interpreter.step(600).unwrap_err();
+
+        assert_eq!(InterpreterErr::MemFault(MemAccess::Write, 0x1000), err.kind);
+        assert_eq!(0x204, err.pc);
+        assert_eq!(vec![0x202], err.call_stack);
+        assert_eq!("MemFault writing 0x1000 at 0x204 (LD [I], V1)", err.to_string());
+
+        let rom = vec![0x51, 0x21]; // Invalid, as 5XYN requires N = 0.
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+        assert_eq!("InvalidOpcode 0x5121 at 0x200", interpreter.step(600).unwrap_err().to_string());
+    }
+
+    #[test]
+    fn memory_fault_policy_test() {
+        let mut interpreter = get_new_interpreter();
+        interpreter.i_reg = 0xFFF;
+        interpreter.v_regs[0x00] = 0xAA;
+        interpreter.v_regs[0x01] = 0xBB;
+        let fx55 = opcode::decode(0xF155, QuirkFlags::NONE);
+        let fx65 = opcode::decode(0xF165, QuirkFlags::NONE);
+
+        assert!(interpreter.execute_instruction(&fx55).is_err());
+
+        // Ignore drops out of range writes and reads them back as 0.
+        interpreter.fault_policies.memory = FaultPolicy::Ignore;
+        interpreter.execute_instruction(&fx55).unwrap();
+        assert_eq!(0xAA, interpreter.memory[0xFFF]);
+        assert_eq!(CHAR_TABLE[0], interpreter.memory[0x000]);
+
+        interpreter.execute_instruction(&fx65).unwrap();
+        assert_eq!(0x00, interpreter.v_regs[0x01]);
+
+        // Wrap carries on from the start of memory.
+        interpreter.v_regs[0x01] = 0xBB;
+        interpreter.fault_policies.memory = FaultPolicy::Wrap;
+        interpreter.execute_instruction(&fx55).unwrap();
+        assert_eq!(0xBB, interpreter.memory[0x000]);
+    }
+
+    #[test]
+    fn pc_leaves_memory_test() {
+        // LD V0, 0x00, then zeroes to the end of memory, which the Ignore policy runs through as no-ops.
+        let rom = vec![0x60, 0x00];
+
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom.clone()).unwrap();
+        interpreter.fault_policies = FaultPolicies::all(FaultPolicy::Ignore);
+        let err = (0..0x1000).find_map(|_| interpreter.step(600).err()).unwrap();
+        assert_eq!(InterpreterErr::MemFault(MemAccess::Execute, MEM_SZ as u32), err.kind);
+
+        // Wrap goes back to the start of memory and keeps running.
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom.clone()).unwrap();
+        interpreter.fault_policies = FaultPolicies::all(FaultPolicy::Wrap);
+        interpreter.pc = 0xFFE;
+        interpreter.step(600).unwrap();
+        assert_eq!(0x1000, interpreter.pc);
+        interpreter.step(600).unwrap();
+        assert_eq!(0x002, interpreter.pc);
+
+        // A skip at the top of the address space wraps rather than overflowing.
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+        interpreter.pc = 0xFFFE;
+        interpreter.execute_instruction(&opcode::decode(0x3000, QuirkFlags::NONE)).unwrap();
+        assert_eq!(0x0000, interpreter.pc);
+
+        // Trap stops as before.
+        let mut interpreter = get_new_interpreter();
+        interpreter.pc = MEM_SZ as u16;
+        assert!(interpreter.step(600).is_err());
+    }
+
+    #[test]
+    fn memory_map_test() {
+        // LD I, 0x010; LD [I], V0; JP 0x050
+        let rom = vec![0xA0, 0x10, 0xF0, 0x55, 0x10, 0x50];
+
+        // Permissive mode is the default, and lets the program scribble on the font and run the interpreter area.
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom.clone()).unwrap();
+        for _ in 0..3 {
+            interpreter.step(600).unwrap();
+        }
+        assert_eq!(0x00, interpreter.memory[0x010]);
+        assert_eq!(0x050, interpreter.pc);
+
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+        interpreter.memory_map.mode = MapMode::Strict;
+        interpreter.step(600).unwrap();
+
+        let err = interpreter.step(600).unwrap_err();
+        assert_eq!(InterpreterErr::PermissionFault(MemAccess::Write, 0x010, RegionKind::Font), err.kind);
+        assert_eq!("PermissionFault writing 0x0010 in font at 0x202 (LD [I], V0)", err.to_string());
+
+        interpreter.pc = 0x204;
+        interpreter.step(600).unwrap();
+        let err = interpreter.step(600).unwrap_err();
+        assert_eq!(InterpreterErr::PermissionFault(MemAccess::Execute, 0x050, RegionKind::Reserved), err.kind);
+    }
+
+    #[test]
+    fn smc_test() {
+        // LD V0, 0x40; LD I, 0x200; LD [I], V0; JP 0x200, which turns the first instruction into SNE V0, 0x40.
+        let rom = vec![0x60, 0x40, 0xA2, 0x00, 0xF0, 0x55, 0x12, 0x00];
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+        interpreter.smc = Some(SmcTracker::new());
+
+        for _ in 0..5 {
+            interpreter.step(600).unwrap();
+        }
+
+        let events = interpreter.smc.as_mut().unwrap().take_events();
+        assert_eq!(vec![
+            SmcEvent { kind: SmcKind::WriteToExecuted, writer_pc: 0x204, addr: 0x200, old_instr: 0x6040, new_instr: 0x4040 },
+            SmcEvent { kind: SmcKind::ExecuteWritten, writer_pc: 0x204, addr: 0x200, old_instr: 0x6040, new_instr: 0x4040 },
+        ], events);
+        assert_eq!("0x204 wrote executed code at 0x200: 6040 -> 4040", events[0].to_string());
+
+        // Writing the same value again isn't a modification.
+        for _ in 0..4 {
+            interpreter.step(600).unwrap();
+        }
+        assert!(interpreter.smc.as_ref().unwrap().events().is_empty());
+    }
+
+    #[test]
+    fn vip_timing_test() {
+        // LD V0, 120; LD DT, V0; JP 0x204
+        let rom = vec![0x60, 0x78, 0xF0, 0x15, 0x12, 0x04];
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+        interpreter.enable_vip_timing();
+
+        // The first interrupt comes before the program sets the timer, and 59 more count it down.
+        interpreter.run_cycles(VIP_CYCLES_PER_FRAME * 60).unwrap();
+        assert_eq!(61, interpreter.delay_timer.current_val);
+        assert_eq!(60, interpreter.timing.as_ref().unwrap().frames());
+
+        // DRW V0, V0, 1; DRW V0, V0, 1
+        let rom = vec![0xD0, 0x01, 0xD0, 0x01];
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+        interpreter.enable_vip_timing();
+
+        // Each sprite waits for the next interrupt, so only one is drawn a frame.
+        interpreter.run_cycles(VIP_CYCLES_PER_FRAME).unwrap();
+        assert_eq!(0x202, interpreter.pc);
+        assert_eq!(2, interpreter.timing.as_ref().unwrap().frames());
+
+        interpreter.run_cycles(1).unwrap();
+        assert_eq!(0x204, interpreter.pc);
+        assert_eq!(3, interpreter.timing.as_ref().unwrap().frames());
+
+        // The two kinds of timing don't mix.
+        assert_eq!(InterpreterErr::TimingModeMismatch, interpreter.run_frame(600).unwrap_err().kind);
+        let mut interpreter = get_new_interpreter();
+        assert_eq!(InterpreterErr::TimingModeMismatch, interpreter.run_cycles(1).unwrap_err().kind);
+    }
+
+    #[test]
+    fn vip_timing_save_state_test() {
+        // ADD V0, 1; DRW V0, V0, 1; JP 0x200
+        let rom = vec![0x70, 0x01, 0xD0, 0x01, 0x12, 0x00];
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+        interpreter.enable_vip_timing();
+
+        // Save partway through a frame, so the restored state has to pick up the interrupt phase.
+        interpreter.run_cycles(VIP_CYCLES_PER_FRAME * 3 + 1000).unwrap();
+        let saved = interpreter.save_state();
+        assert_eq!(interpreter.timing, saved.timing);
+
+        interpreter.run_cycles(VIP_CYCLES_PER_FRAME * 5).unwrap();
+        let expected = interpreter.save_state();
+
+        interpreter.load_state(&saved);
+        assert_eq!(saved, interpreter.save_state());
+        interpreter.run_cycles(VIP_CYCLES_PER_FRAME * 5).unwrap();
+        assert_eq!(expected, interpreter.save_state());
+        assert_eq!(expected.hash(), interpreter.save_state().hash());
+        assert_ne!(saved.hash(), expected.hash());
+    }
+
+    #[test]
+    fn from_known_rom_test() {
+        let rom = vec![0x12, 0x00];
+        let json = format!(
+            r#"[{{ "title": "Loop", "roms": {{ "{}": {{ "platforms": ["originalChip8"], "quirkyPlatforms": {{ "originalChip8": {{ "memoryLeaveIUnchanged": true }} }} }} }} }}]"#,
+            crate::sha1::to_hex(&crate::sha1::sha1(&rom)));
+        let db = RomDb::from_json(&json).unwrap();
+
+        let (interpreter, info) = Chip8Interpreter::from_known_rom(MockPlatform::new(), rom, &db).unwrap();
+        assert_eq!(QuirkFlags::QUIRK_8XY6 | QuirkFlags::QUIRK_8XYE, interpreter.quirks);
+        assert_eq!(Some("Loop"), info.as_ref().map(|info| info.title.as_str()));
+
+        // Unknown ROMs fall back to detection.
+        let (interpreter, info) = Chip8Interpreter::from_known_rom(MockPlatform::new(), vec![0x12, 0x60], &db).unwrap();
+        assert!(interpreter.is_hires());
+        assert_eq!(Profile::VipHires.quirks(), interpreter.quirks);
+        assert_eq!(None, info);
+
+        // So do ROMs only known on platforms we don't emulate, though their entry still comes back.
+        let rom = vec![0x12, 0x02];
+        let json = format!(r#"[{{ "title": "XO", "roms": {{ "{}": {{ "platforms": ["xochip"], "tickrate": 100 }} }} }}]"#,
+            crate::sha1::to_hex(&crate::sha1::sha1(&rom)));
+        let db = RomDb::from_json(&json).unwrap();
+
+        let (interpreter, info) = Chip8Interpreter::from_known_rom(MockPlatform::new(), rom, &db).unwrap();
+        assert_eq!(QuirkFlags::NONE, interpreter.quirks);
+        assert_eq!(Some(6000), info.and_then(|info| info.tick_rate()));
+    }
+
+    #[test]
+    fn stack_fault_policy_test() {
+        let mut interpreter = get_new_interpreter();
+        let call = opcode::decode(0x2300, QuirkFlags::NONE);
+        let ret = opcode::decode(0x00EE, QuirkFlags::NONE);
+
+        for _ in 0..STACK_SZ {
+            interpreter.execute_instruction(&call).unwrap();
+        }
+        assert_eq!(Err(InterpreterErr::CallStackOverflow), interpreter.execute_instruction(&call));
+
+        // Ignore skips the call altogether.
+        interpreter.pc = 0x400;
+        interpreter.fault_policies.stack_overflow = FaultPolicy::Ignore;
+        interpreter.execute_instruction(&call).unwrap();
+        assert_eq!(0x400, interpreter.pc);
+
+        // Wrap drops the oldest return address.
+        interpreter.fault_policies.stack_overflow = FaultPolicy::Wrap;
+        interpreter.execute_instruction(&call).unwrap();
+        assert_eq!(0x300, interpreter.pc);
+        assert_eq!(STACK_SZ, interpreter.stack.snapshot().unwrap().len());
+        assert_eq!(Some(&0x400), interpreter.stack.snapshot().unwrap().last());
+
+        for _ in 0..STACK_SZ {
+            interpreter.execute_instruction(&ret).unwrap();
+        }
+        assert_eq!(Err(InterpreterErr::CallStackEmpty), interpreter.execute_instruction(&ret));
+
+        interpreter.fault_policies.stack_underflow = FaultPolicy::Ignore;
+        interpreter.execute_instruction(&ret).unwrap();
+    }
+
+    #[test]
+    fn fault_hook_test() {
+        // 0123 isn't a routine anyone knows, so it faults as an invalid opcode.
+        let rom = vec![0x01, 0x23, 0x60, 0x05];
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+        interpreter.fault_policies = FaultPolicies::all(FaultPolicy::Hook);
+        interpreter.platform_adapter.fault_policy = FaultPolicy::Ignore;
+
+        interpreter.step(600).unwrap();
+        interpreter.step(600).unwrap();
+
+        assert_eq!(vec![InterpreterErr::InvalidOpcode(0x0123)], interpreter.platform_adapter.faults);
+        assert_eq!(0x05, interpreter.v_regs[0x00]);
+
+        // A hook that doesn't resolve the fault traps.
+        interpreter.platform_adapter.fault_policy = FaultPolicy::Hook;
+        interpreter.pc = START_ADDR as u16;
+        assert!(interpreter.step(600).is_err());
+    }
+
+    #[test]
+    fn execute_0nnn_test() {
+        let mut interpreter = get_new_interpreter();
+
+        interpreter.execute_instruction(&opcode::decode(0x0300, QuirkFlags::NONE)).unwrap();
+        assert_eq!(0x01, interpreter.v_regs[0xF]);
+
+        // The host passes on 0230, so the built-in hires clear runs.
+        interpreter.display.set(0, 5, 3, true);
+        interpreter.execute_instruction(&opcode::decode(0x0230, QuirkFlags::NONE)).unwrap();
+        assert_eq!(0, interpreter.display.get(5, 3));
+
+        assert_eq!(Err(InterpreterErr::InvalidOpcode(0x0456)), interpreter.execute_instruction(&opcode::decode(0x0456, QuirkFlags::NONE)));
+        assert_eq!(vec![0x300, 0x230, 0x456], interpreter.platform_adapter.sys_calls);
+    }
+
+    #[test]
+    fn execute_0nnn_memory_map_test() {
+        // Routines' writes go through the memory map like the program's own.
+        let mut interpreter = get_new_interpreter();
+        interpreter.memory_map.mode = MapMode::Strict;
+        assert_eq!(Err(InterpreterErr::PermissionFault(MemAccess::Write, 0x000, RegionKind::Font)), interpreter.execute_instruction(&opcode::decode(0x0310, QuirkFlags::NONE)));
+        assert_eq!(CHAR_TABLE[0], interpreter.memory[0x000]);
+
+        interpreter.memory_map.mode = MapMode::Permissive;
+        interpreter.execute_instruction(&opcode::decode(0x0310, QuirkFlags::NONE)).unwrap();
+        assert_eq!(0xFF, interpreter.memory[0x000]);
+    }
+
+    #[test]
+    fn hires_test() {
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), vec![0x12, 0x60]).unwrap();
+        interpreter.apply_profile(Profile::VipHires).unwrap();
+
+        assert!(interpreter.is_hires());
+        assert_eq!(HIRES_START_ADDR as u16, interpreter.pc);
+        assert_eq!(HIRES_RES_Y, interpreter.display.height());
+
+        // Sprites draw below row 32, and wrap at row 64.
+        interpreter.i_reg = 0x300;
+        interpreter.memory[0x300] = 0x80;
+        interpreter.v_regs[0x01] = 40;
+        interpreter.execute_instruction(&opcode::decode(0xD011, QuirkFlags::NONE)).unwrap();
+        assert_eq!(1, interpreter.display.get(0, 40));
+
+        interpreter.v_regs[0x01] = 70;
+        interpreter.execute_instruction(&opcode::decode(0xD011, QuirkFlags::NONE)).unwrap();
+        assert_eq!(1, interpreter.display.get(0, 6));
+
+        interpreter.execute_instruction(&opcode::decode(0x00E0, QuirkFlags::NONE)).unwrap();
+        assert!(interpreter.display.plane_words(0).iter().all(|word| *word == 0));
+    }
+
+    #[test]
+    fn chip8x_test() {
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), vec![0x02, 0xA0]).unwrap();
+        interpreter.apply_profile(Profile::Chip8X).unwrap();
+
+        // The program moves up a page.
+        assert_eq!(CHIP8X_START_ADDR as u16, interpreter.pc);
+        assert_eq!([0x02, 0xA0], interpreter.memory[CHIP8X_START_ADDR..CHIP8X_START_ADDR + 2]);
+        assert_eq!(0x00, interpreter.memory[START_ADDR]);
+
+        // Only once, however many times the profile is applied.
+        interpreter.apply_profile(Profile::Chip8X).unwrap();
+        assert_eq!([0x02, 0xA0], interpreter.memory[CHIP8X_START_ADDR..CHIP8X_START_ADDR + 2]);
+        assert_eq!(0x00, interpreter.memory[CHIP8X_START_ADDR + 0x100]);
+
+        // A ROM that only fits from 0x200 can't be moved up.
+        let mut too_large = Chip8Interpreter::new(MockPlatform::new(), vec![0x00; MEM_SZ - CHIP8X_START_ADDR]).unwrap();
+        assert_eq!(Err(InterpreterErr::RomTooLarge), too_large.apply_profile(Profile::Chip8X));
+
+        interpreter.step(600).unwrap();
+        assert_eq!(1, interpreter.background_color);
+
+        // BXY0: areas 1-2 across and 0-1 down, which is zones 1-2 and rows 0-7, to color 5.
+        interpreter.v_regs[0x00] = 0x11;
+        interpreter.v_regs[0x01] = 0x10;
+        interpreter.v_regs[0x02] = 0x05;
+        interpreter.execute_instruction(&opcode::decode(0xB020, interpreter.quirks)).unwrap();
+        assert_eq!([DEFAULT_FOREGROUND, 5, 5, DEFAULT_FOREGROUND], interpreter.color_map[7][0..4]);
+        assert_eq!(DEFAULT_FOREGROUND, interpreter.color_map[8][1]);
+
+        // Areas past the right or bottom edge.
+        let color_map = interpreter.color_map;
+        for (horizontal, vertical) in [(0x0F, 0x00), (0x00, 0x0F)].iter() {
+            interpreter.v_regs[0x00] = *horizontal;
+            interpreter.v_regs[0x01] = *vertical;
+            interpreter.execute_instruction(&opcode::decode(0xB020, interpreter.quirks)).unwrap();
+            assert_eq!(color_map, interpreter.color_map);
+        }
+
+        // BXYN: 2 rows from pixel (20, 31), wrapping to the top, to the color in VX+1.
+        interpreter.v_regs[0x03] = 20;
+        interpreter.v_regs[0x04] = 6;
+        interpreter.v_regs[0x05] = 31;
+        interpreter.execute_instruction(&opcode::decode(0xB352, interpreter.quirks)).unwrap();
+        assert_eq!(6, interpreter.color_map[31][2]);
+        assert_eq!(6, interpreter.color_map[0][2]);
+
+        // The second keypad.
+        interpreter.key_press_2 = Some(KeyCodes::Key5);
+        let original_pc_val = interpreter.pc;
+        interpreter.execute_instruction(&opcode::decode(0xE2F2, interpreter.quirks)).unwrap();
+        assert_eq!(original_pc_val + 2, interpreter.pc);
+        interpreter.execute_instruction(&opcode::decode(0xE2F5, interpreter.quirks)).unwrap();
+        assert_eq!(original_pc_val + 2, interpreter.pc);
+
+        // The I/O port, where IN waits for a byte.
+        interpreter.execute_instruction(&opcode::decode(0xF2F8, interpreter.quirks)).unwrap();
+        assert_eq!(vec![0x05], interpreter.platform_adapter.port_out);
+
+        let original_pc_val = interpreter.pc;
+        interpreter.execute_instruction(&opcode::decode(0xF7FB, interpreter.quirks)).unwrap();
+        assert_eq!(original_pc_val - 2, interpreter.pc);
+
+        interpreter.platform_adapter.port_in = Some(0x42);
+        interpreter.execute_instruction(&opcode::decode(0xF7FB, interpreter.quirks)).unwrap();
+        assert_eq!(0x42, interpreter.v_regs[0x07]);
+    }
+
+    #[test]
+    fn megachip_test() {
+        let rom = vec![0x00, 0x11, 0x01, 0x01, 0x00, 0x00]; // MEGAON; LDHI I, 0x010000
+        let mut interpreter =
+            Chip8Interpreter::with_memory_size(MockPlatform::new(), rom, Profile::MegaChip.memory_size()).unwrap();
+        interpreter.apply_profile(Profile::MegaChip).unwrap();
+
+        interpreter.step(600).unwrap();
+        assert!(interpreter.mega.is_some());
+
+        // Long I takes the next word with it.
+        interpreter.step(600).unwrap();
+        assert_eq!(0x01_0000, interpreter.i_reg);
+        assert_eq!(START_ADDR as u16 + 6, interpreter.pc);
+
+        interpreter.memory[0x01_0000..0x01_0004].copy_from_slice(&[0xFF, 0x12, 0x34, 0x56]);
+        interpreter.execute_instruction(&opcode::decode(0x0201, interpreter.quirks)).unwrap();
+        assert_eq!(0xFF12_3456, interpreter.mega.as_ref().unwrap().palette[1]);
+
+        // A 2x1 sprite whose second pixel is transparent, drawn twice to collide.
+        interpreter.execute_instruction(&opcode::decode(0x0302, interpreter.quirks)).unwrap();
+        interpreter.execute_instruction(&opcode::decode(0x0401, interpreter.quirks)).unwrap();
+        interpreter.i_reg = 0x01_0010;
+        interpreter.memory[0x01_0010] = 1;
+        interpreter.v_regs[0x00] = 3;
+        interpreter.v_regs[0x01] = 4;
+
+        interpreter.execute_instruction(&opcode::decode(0xD011, interpreter.quirks)).unwrap();
+        assert_eq!(0x00, interpreter.v_regs[0x0F]);
+        let mega = interpreter.mega.as_ref().unwrap();
+        assert_eq!([1, 0], mega.indices[4 * MEGA_RES_X + 3..4 * MEGA_RES_X + 5]);
+        assert_eq!(0xFF12_3456, mega.back[4 * MEGA_RES_X + 3]);
+
+        interpreter.execute_instruction(&opcode::decode(0xD011, interpreter.quirks)).unwrap();
+        assert_eq!(0x01, interpreter.v_regs[0x0F]);
+
+        // Clearing presents the frame.
+        interpreter.execute_instruction(&opcode::decode(0x00E0, interpreter.quirks)).unwrap();
+        assert_eq!(0xFF12_3456, interpreter.mega.as_ref().unwrap().front[4 * MEGA_RES_X + 3]);
+
+        interpreter.execute_instruction(&opcode::decode(0x0805, interpreter.quirks)).unwrap();
+        assert_eq!(BlendMode::Multiply, interpreter.mega.as_ref().unwrap().blend_mode);
+        assert_eq!(
+            Err(InterpreterErr::InvalidOpcode(0x0809)),
+            interpreter.execute_instruction(&opcode::decode(0x0809, interpreter.quirks))
+        );
+
+        // Digitized sound: 8000 Hz, two samples, looped.
+        interpreter.i_reg = 0x01_0020;
+        interpreter.memory[0x01_0020..0x01_0027].copy_from_slice(&[0x1F, 0x40, 0x00, 0x00, 0x02, 0x80, 0x81]);
+        interpreter.execute_instruction(&opcode::decode(0x0600, interpreter.quirks)).unwrap();
+        assert_eq!(Some((vec![0x80, 0x81], 8000, true)), interpreter.platform_adapter.digitized);
+        interpreter.execute_instruction(&opcode::decode(0x0700, interpreter.quirks)).unwrap();
+        assert_eq!(None, interpreter.platform_adapter.digitized);
+
+        interpreter.execute_instruction(&opcode::decode(0x0010, interpreter.quirks)).unwrap();
+        assert!(interpreter.mega.is_none());
+        assert_eq!(0x0020, interpreter.i_reg);
+    }
+
+    #[test]
+    fn dirty_rects_test() {
+        let rom = vec![0xA2, 0x08, 0xD0, 0x11, 0x00, 0xE0, 0x12, 0x06, 0xF0]; // LD I, 0x208; DRW V0, V0, 1; CLS; JP 0x206
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+        let full = Rect::new(0, 0, RES_X, RES_Y);
+
+        interpreter.step(600).unwrap();
+        assert_eq!(0, interpreter.display_version());
+        assert!(interpreter.take_dirty_rects().is_empty());
+
+        interpreter.step(600).unwrap();
+        assert_eq!(1, interpreter.display_version());
+        assert_eq!(vec![Rect::new(0, 0, 8, 1)], interpreter.take_dirty_rects());
+
+        interpreter.step(600).unwrap();
+        interpreter.step(600).unwrap();
+        assert_eq!(2, interpreter.display_version());
+        assert_eq!(vec![full], interpreter.take_dirty_rects());
+        assert!(interpreter.take_dirty_rects().is_empty());
+
+        assert_eq!(vec![(Rect::new(0, 0, 8, 1), 1), (full, 2)], interpreter.platform_adapter.display_changes);
+
+        // Rects nobody takes are merged rather than piling up.
+        for x in 0..=MAX_DIRTY_RECTS {
+            interpreter.add_dirty_rect(Rect::new(x % RES_X, x / RES_X, 1, 1));
+        }
+        assert_eq!(vec![Rect::new(0, 0, RES_X, 2)], interpreter.take_dirty_rects());
+    }
+
+    struct EventRecorder {
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Observer for EventRecorder {
+        fn on_instruction(&mut self, pc: u16, _instr: &DecodedInstruction) {
+            self.log.borrow_mut().push(format!("instr {:#05X}", pc));
+        }
+
+        fn on_reg_write(&mut self, reg_idx: u8, val: u8) {
+            self.log.borrow_mut().push(format!("V{:X} = {}", reg_idx, val));
+        }
+
+        fn on_call(&mut self, pc: u16, addr: u16) {
+            self.log.borrow_mut().push(format!("call {:#05X} -> {:#05X}", pc, addr));
+        }
+
+        fn on_return(&mut self, pc: u16, addr: u16) {
+            self.log.borrow_mut().push(format!("return {:#05X} -> {:#05X}", pc, addr));
+        }
+
+        fn on_timer_set(&mut self, timer: TimerKind, val: u8) {
+            self.log.borrow_mut().push(format!("{:?} timer = {}", timer, val));
+        }
+
+        fn on_sound_start(&mut self) {
+            self.log.borrow_mut().push("sound start".to_string());
+        }
+
+        fn on_key_wait_begin(&mut self, reg_idx: u8) {
+            self.log.borrow_mut().push(format!("wait V{:X}", reg_idx));
+        }
+
+        fn on_key_wait_end(&mut self, reg_idx: u8, key: KeyCodes) {
+            self.log.borrow_mut().push(format!("key V{:X} = {:?}", reg_idx, key));
+        }
+
+        fn on_error(&mut self, err: &ExecutionError) {
+            self.log.borrow_mut().push(format!("error {}", err.kind));
+        }
+    }
+
+    #[test]
+    fn observer_test() {
+        // LD V0, 5; CALL 0x208; LD V1, K; JP 0x206; LD ST, V0; RET
+        let rom = vec![0x60, 0x05, 0x22, 0x08, 0xF1, 0x0A, 0x12, 0x06, 0xF0, 0x18, 0x00, 0xEE];
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let recorder = EventRecorder { log: log.clone() };
+        let mut interpreter = Chip8Interpreter::with_observer(MockPlatform::new(), rom, MEM_SZ, recorder).unwrap();
+
+        for _ in 0..6 {
+            interpreter.step(600).unwrap();
+        }
+        interpreter.key_press = Some(KeyCodes::Key7);
+        interpreter.step(600).unwrap();
+
+        interpreter.pc = MEM_SZ as u16;
+        assert!(interpreter.step(600).is_err());
+
+        let expected = [
+            "V0 = 5", "instr 0x200",
+            "call 0x202 -> 0x208", "instr 0x202",
+            "Sound timer = 5", "sound start", "instr 0x208",
+            "return 0x20A -> 0x204", "instr 0x20A",
+            "wait V1", "instr 0x204",
+            "V1 = 7", "key V1 = Key7", "instr 0x206",
+            "error MemFault executing 0x1000",
+        ];
+        assert_eq!(expected.to_vec(), *log.borrow());
+    }
+
+    #[test]
+    fn observer_list_test() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let observers: Vec<Box<dyn Observer>> = vec![
+            Box::new(EventRecorder { log: log.clone() }),
+            Box::new(NoopObserver),
+            Box::new(EventRecorder { log: log.clone() }),
+        ];
+        let mut interpreter = Chip8Interpreter::with_observer(MockPlatform::new(), vec![0x60, 0x05], MEM_SZ, observers).unwrap();
+
+        interpreter.step(600).unwrap();
+        assert_eq!(vec!["V0 = 5", "V0 = 5", "instr 0x200", "instr 0x200"], *log.borrow());
+    }
+
+    #[test]
+    fn save_and_load_state_test() {
+        // Restoring a save state should replay identically, including random numbers.
+        let rom = vec![0xC0, 0xFF, 0x71, 0x01, 0x12, 0x00]; // RND V0, 0xFF; ADD V1, 0x01; JP 0x200
+        let mut interpreter = Chip8Interpreter::new(MockPlatform::new(), rom).unwrap();
+        interpreter.rng = Rng::new(RngKind::XorShift, 99);
+
+        interpreter.run_frame(600).unwrap();
+        let state = interpreter.save_state();
+
+        let mut first_run = Vec::new();
+        for _ in 0..5 {
+            interpreter.step(600).unwrap();
+            first_run.push(interpreter.v_regs);
+        }
+
+        interpreter.load_state(&state);
+        assert_eq!(state, interpreter.save_state());
+
+        let mut second_run = Vec::new();
+        for _ in 0..5 {
+            interpreter.step(600).unwrap();
+            second_run.push(interpreter.v_regs);
+        }
+
+        assert_eq!(first_run, second_run);
+    }
+
     #[test]
     fn execute_00e0_test() {
         // Tests instruction OOEO, which we expect to clear the display.
         let mut interpreter = get_new_interpreter();
         for y in 0..RES_Y {
             for x in 0..RES_X {
-                interpreter.display_buffer[y][x] = 0;
+                interpreter.display.set(0, x, y, false);
             }
         }
 
@@ -920,7 +2376,7 @@ mod tests {
 
         for y in 0..RES_Y {
             for x in 0..RES_X {
-                assert_ne!(1, interpreter.display_buffer[y][x]);
+                assert_ne!(1, interpreter.display.get(x, y));
             }
         }
     }
@@ -1320,7 +2776,9 @@ mod tests {
     fn execute_cxnn_test() {
         // Tests CXNN, which we expect to set VX to a random number masked by NN.
         let mut interpreter = get_new_interpreter();
-        interpreter.platform_adapter.random_val = 0b_0111_1111;
+        interpreter.rng = Rng::new(RngKind::XorShift, 1234);
+        let mut expected_rng = interpreter.rng;
+        let rand_val = expected_rng.next(&interpreter.memory);
 
         let mask = 0b_0000_0000_1001_1001;
         let instr = 0xC100 | mask;
@@ -1328,7 +2786,8 @@ mod tests {
         interpreter.execute_instruction(&opcode::decode(instr, QuirkFlags::NONE)).unwrap();
 
         let vx_val = interpreter.read_v_reg(0x01).unwrap();
-        assert_eq!(0b_0001_1001, vx_val);
+        assert_eq!(rand_val & mask as u8, vx_val);
+        assert_eq!(expected_rng, interpreter.rng);
     }
 
     //#[test] TODO: failing, fix assertions
@@ -1353,34 +2812,34 @@ mod tests {
         assert_eq!(0x00, vf_val);
 
         // This checks the first sprite.
-        assert_eq!(1, interpreter.display_buffer[5][4]);
-        assert_eq!(0, interpreter.display_buffer[5][5]);
-        assert_eq!(1, interpreter.display_buffer[5][6]);
-        assert_eq!(0, interpreter.display_buffer[5][7]);
-        assert_eq!(1, interpreter.display_buffer[5][8]);
-        assert_eq!(0, interpreter.display_buffer[5][9]);
-        assert_eq!(1, interpreter.display_buffer[5][10]);
-        assert_eq!(0, interpreter.display_buffer[5][11]);
+        assert_eq!(1, interpreter.display.get(4, 5));
+        assert_eq!(0, interpreter.display.get(5, 5));
+        assert_eq!(1, interpreter.display.get(6, 5));
+        assert_eq!(0, interpreter.display.get(7, 5));
+        assert_eq!(1, interpreter.display.get(8, 5));
+        assert_eq!(0, interpreter.display.get(9, 5));
+        assert_eq!(1, interpreter.display.get(10, 5));
+        assert_eq!(0, interpreter.display.get(11, 5));
 
         // This checks the second sprite.
-        assert_eq!(0, interpreter.display_buffer[6][4]);
-        assert_eq!(1, interpreter.display_buffer[6][5]);
-        assert_eq!(0, interpreter.display_buffer[6][6]);
-        assert_eq!(1, interpreter.display_buffer[6][7]);
-        assert_eq!(0, interpreter.display_buffer[6][8]);
-        assert_eq!(1, interpreter.display_buffer[6][9]);
-        assert_eq!(0, interpreter.display_buffer[6][10]);
-        assert_eq!(1, interpreter.display_buffer[6][11]);
+        assert_eq!(0, interpreter.display.get(4, 6));
+        assert_eq!(1, interpreter.display.get(5, 6));
+        assert_eq!(0, interpreter.display.get(6, 6));
+        assert_eq!(1, interpreter.display.get(7, 6));
+        assert_eq!(0, interpreter.display.get(8, 6));
+        assert_eq!(1, interpreter.display.get(9, 6));
+        assert_eq!(0, interpreter.display.get(10, 6));
+        assert_eq!(1, interpreter.display.get(11, 6));
 
         // This checks the third sprite. (Which was unset.)
-        assert_eq!(0, interpreter.display_buffer[7][4]);
-        assert_eq!(0, interpreter.display_buffer[7][5]);
-        assert_eq!(0, interpreter.display_buffer[7][6]);
-        assert_eq!(0, interpreter.display_buffer[7][7]);
-        assert_eq!(0, interpreter.display_buffer[7][8]);
-        assert_eq!(0, interpreter.display_buffer[7][9]);
-        assert_eq!(0, interpreter.display_buffer[7][10]);
-        assert_eq!(0, interpreter.display_buffer[7][11]);
+        assert_eq!(0, interpreter.display.get(4, 7));
+        assert_eq!(0, interpreter.display.get(5, 7));
+        assert_eq!(0, interpreter.display.get(6, 7));
+        assert_eq!(0, interpreter.display.get(7, 7));
+        assert_eq!(0, interpreter.display.get(8, 7));
+        assert_eq!(0, interpreter.display.get(9, 7));
+        assert_eq!(0, interpreter.display.get(10, 7));
+        assert_eq!(0, interpreter.display.get(11, 7));
 
         // Second check a colliding sprite.
         interpreter.i_reg = 0x01;
@@ -1393,14 +2852,14 @@ mod tests {
         let vf_val = interpreter.read_v_reg(0x0F).unwrap();
         assert_eq!(0x00, vf_val);
 
-        assert_eq!(1, interpreter.display_buffer[6][11]);
-        assert_eq!(0, interpreter.display_buffer[6][12]);
-        assert_eq!(0, interpreter.display_buffer[6][13]);
-        assert_eq!(0, interpreter.display_buffer[6][14]);
-        assert_eq!(0, interpreter.display_buffer[6][15]);
-        assert_eq!(0, interpreter.display_buffer[6][16]);
-        assert_eq!(0, interpreter.display_buffer[6][17]);
-        assert_eq!(0, interpreter.display_buffer[6][18]);
+        assert_eq!(1, interpreter.display.get(11, 6));
+        assert_eq!(0, interpreter.display.get(12, 6));
+        assert_eq!(0, interpreter.display.get(13, 6));
+        assert_eq!(0, interpreter.display.get(14, 6));
+        assert_eq!(0, interpreter.display.get(15, 6));
+        assert_eq!(0, interpreter.display.get(16, 6));
+        assert_eq!(0, interpreter.display.get(17, 6));
+        assert_eq!(0, interpreter.display.get(18, 6));
 
         // Third check wrapping.
         // TODO
@@ -1483,12 +2942,12 @@ mod tests {
         let start_addr = START_ADDR as u16;
 
         // Put opcode 0xF10A in memory. This is what we're testing.
-        interpreter.write_mem(start_addr, 0xF1).unwrap();
-        interpreter.write_mem(start_addr + 1, 0x0A).unwrap();
+        interpreter.write_mem(start_addr as u32, 0xF1).unwrap();
+        interpreter.write_mem(start_addr as u32 + 1, 0x0A).unwrap();
 
         // Put opcode 0xF207 in memory (this could be any opcode). This is so the second time we step, there's a valid instruction.
-        interpreter.write_mem(start_addr + 2, 0xF2).unwrap();
-        interpreter.write_mem(start_addr + 3, 0x07).unwrap();
+        interpreter.write_mem(start_addr as u32 + 2, 0xF2).unwrap();
+        interpreter.write_mem(start_addr as u32 + 3, 0x07).unwrap();
 
         // First check mnemonic for 0xF10A.
         interpreter.step(100).unwrap(); // Tick rate is irrelevant here.
@@ -1533,8 +2992,8 @@ mod tests {
         assert_eq!(1, interpreter.platform_adapter.play_count);
         assert_eq!(0, interpreter.platform_adapter.pause_count);
 
-        interpreter.write_mem(start_addr, 0xF2).unwrap();
-        interpreter.write_mem(start_addr + 1, 0x07).unwrap();
+        interpreter.write_mem(start_addr as u32, 0xF2).unwrap();
+        interpreter.write_mem(start_addr as u32 + 1, 0x07).unwrap();
         interpreter.step(1).unwrap();
         assert_eq!(false, interpreter.is_sound_playing);
         assert_eq!(1, interpreter.platform_adapter.play_count);
@@ -1650,7 +3109,7 @@ mod tests {
         for i in 0x234..i_reg_final {
             let x = i - 0x234;
             let mem_val = interpreter.read_mem(i).unwrap();
-            assert_eq!(x + 1, mem_val as u16);
+            assert_eq!(x + 1, mem_val as u32);
         }
     }
 
@@ -1672,7 +3131,7 @@ mod tests {
         for i in 0x234..=i_reg_final {
             let x = i - 0x234;
             let mem_val = interpreter.read_mem(i).unwrap();
-            assert_eq!(x + 1, mem_val as u16);
+            assert_eq!(x + 1, mem_val as u32);
         }
     }
 
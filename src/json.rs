@@ -0,0 +1,347 @@
+// A small JSON reader and writer, enough for the data files we load without pulling in a dependency.
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>), // In the order they appear.
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) struct JsonErr {
+    pub pos: usize, // Byte offset of the problem.
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, JsonErr> {
+        let mut parser = Parser { bytes: text.as_bytes(), pos: 0 };
+        let val = parser.value()?;
+
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(parser.err());
+        }
+
+        Ok(val)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, val)| val),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(val) => Some(val),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(val) => Some(*val),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(val) if *val >= 0.0 && val.fract() == 0.0 => Some(*val as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(vals) => Some(vals),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, Json)]> {
+        match self {
+            Json::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Compact, with no whitespace between tokens.
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(val) => write!(f, "{}", val),
+            Json::Number(val) if val.is_finite() => write!(f, "{}", val),
+            Json::Number(_) => write!(f, "null"), // JSON has no infinities or NaN.
+            Json::String(val) => write_string(f, val),
+            Json::Array(vals) => {
+                write!(f, "[")?;
+                for (idx, val) in vals.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", val)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (idx, (name, val)) in members.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", val)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, val: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in val.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn err(&self) -> JsonErr {
+        JsonErr { pos: self.pos }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), JsonErr> {
+        if !self.bytes[self.pos..].starts_with(literal.as_bytes()) {
+            return Err(self.err());
+        }
+
+        self.pos += literal.len();
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, JsonErr> {
+        self.skip_whitespace();
+
+        match self.peek().ok_or_else(|| self.err())? {
+            b'{' => self.object(),
+            b'[' => self.array(),
+            b'"' => Ok(Json::String(self.string()?)),
+            b't' => self.expect("true").map(|_| Json::Bool(true)),
+            b'f' => self.expect("false").map(|_| Json::Bool(false)),
+            b'n' => self.expect("null").map(|_| Json::Null),
+            b'-' | b'0'..=b'9' => self.number(),
+            _ => Err(self.err()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, JsonErr> {
+        self.pos += 1;
+        let mut members = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.err());
+            }
+            let name = self.string()?;
+
+            self.skip_whitespace();
+            self.expect(":")?;
+            members.push((name, self.value()?));
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return Err(self.err()),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, JsonErr> {
+        self.pos += 1;
+        let mut vals = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(vals));
+        }
+
+        loop {
+            vals.push(self.value()?);
+
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(vals));
+                }
+                _ => return Err(self.err()),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonErr> {
+        self.pos += 1;
+        let mut val = String::new();
+
+        loop {
+            let start = self.pos;
+            while let Some(byte) = self.peek() {
+                if byte == b'"' || byte == b'\\' || byte < 0x20 {
+                    break;
+                }
+                self.pos += 1;
+            }
+
+            // The input came from a &str and the run stops at an ASCII byte, so it's still valid UTF-8.
+            val.push_str(std::str::from_utf8(&self.bytes[start..self.pos]).map_err(|_| self.err())?);
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(val);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escape = self.peek().ok_or_else(|| self.err())?;
+                    self.pos += 1;
+
+                    match escape {
+                        b'"' => val.push('"'),
+                        b'\\' => val.push('\\'),
+                        b'/' => val.push('/'),
+                        b'b' => val.push('\u{8}'),
+                        b'f' => val.push('\u{c}'),
+                        b'n' => val.push('\n'),
+                        b'r' => val.push('\r'),
+                        b't' => val.push('\t'),
+                        b'u' => val.push(self.unicode_escape()?),
+                        _ => return Err(self.err()),
+                    }
+                }
+                _ => return Err(self.err()),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, JsonErr> {
+        let high = self.hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.err());
+        }
+
+        // Characters outside the basic plane come as a surrogate pair.
+        self.expect("\\u")?;
+        let low = self.hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.err());
+        }
+
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)).ok_or_else(|| self.err())
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonErr> {
+        let digits = self.bytes.get(self.pos..self.pos + 4).ok_or_else(|| self.err())?;
+        let digits = std::str::from_utf8(digits).map_err(|_| self.err())?;
+        let val = u32::from_str_radix(digits, 16).map_err(|_| self.err())?;
+
+        self.pos += 4;
+        Ok(val)
+    }
+
+    fn number(&mut self) -> Result<Json, JsonErr> {
+        let start = self.pos;
+        while let Some(b'-') | Some(b'+') | Some(b'.') | Some(b'e') | Some(b'E') | Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+
+        std::str::from_utf8(&self.bytes[start..self.pos]).ok()
+            .and_then(|text| text.parse::<f64>().ok())
+            .map(Json::Number)
+            .ok_or(JsonErr { pos: start })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let json = Json::parse(r#" { "title": "Pong \"2\" é", "tickrate": 15, "ok": true, "roms": [1, -2.5e1, null] } "#).unwrap();
+
+        assert_eq!(Some("Pong \"2\" é"), json.get("title").and_then(Json::as_str));
+        assert_eq!(Some(15), json.get("tickrate").and_then(Json::as_u64));
+        assert_eq!(Some(true), json.get("ok").and_then(Json::as_bool));
+        assert_eq!(Some(&[Json::Number(1.0), Json::Number(-25.0), Json::Null][..]), json.get("roms").and_then(Json::as_array));
+        assert_eq!(None, json.get("missing"));
+
+        assert_eq!(Json::String(String::from("😀")), Json::parse(r#""\ud83d\ude00""#).unwrap());
+    }
+
+    #[test]
+    fn to_string_test() {
+        let json = Json::Object(vec![
+            (String::from("program"), Json::String(String::from(": main\n\t\"loop\" \u{1}"))),
+            (String::from("options"), Json::Array(vec![Json::Number(15.0), Json::Number(-2.5), Json::Bool(false), Json::Null])),
+        ]);
+
+        let text = json.to_string();
+        assert_eq!(r#"{"program":": main\n\t\"loop\" \u0001","options":[15,-2.5,false,null]}"#, text);
+        assert_eq!(Ok(json), Json::parse(&text));
+    }
+
+    #[test]
+    fn parse_err_test() {
+        assert_eq!(Err(JsonErr { pos: 8 }), Json::parse(r#"{"a": 1,}"#));
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("\"unterminated").is_err());
+        assert!(Json::parse("1 2").is_err());
+        assert!(Json::parse("").is_err());
+    }
+}
@@ -17,3 +17,28 @@ pub enum KeyCodes {
     KeyE = 0x0E,
     KeyF = 0x0F,
 }
+
+
+impl KeyCodes {
+    pub fn from_u8(val: u8) -> Option<KeyCodes> {
+        match val {
+            0x00 => Some(KeyCodes::Key0),
+            0x01 => Some(KeyCodes::Key1),
+            0x02 => Some(KeyCodes::Key2),
+            0x03 => Some(KeyCodes::Key3),
+            0x04 => Some(KeyCodes::Key4),
+            0x05 => Some(KeyCodes::Key5),
+            0x06 => Some(KeyCodes::Key6),
+            0x07 => Some(KeyCodes::Key7),
+            0x08 => Some(KeyCodes::Key8),
+            0x09 => Some(KeyCodes::Key9),
+            0x0A => Some(KeyCodes::KeyA),
+            0x0B => Some(KeyCodes::KeyB),
+            0x0C => Some(KeyCodes::KeyC),
+            0x0D => Some(KeyCodes::KeyD),
+            0x0E => Some(KeyCodes::KeyE),
+            0x0F => Some(KeyCodes::KeyF),
+            _ => None,
+        }
+    }
+}
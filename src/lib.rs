@@ -4,4 +4,29 @@ pub mod keycodes;
 pub mod quirk_flags;
 pub mod callstack;
 pub mod timer;
-pub mod opcode;
\ No newline at end of file
+pub mod opcode;
+pub mod profile;
+pub mod render;
+pub mod gif;
+pub mod audio;
+pub mod rng;
+pub mod savestate;
+pub mod sha1;
+pub mod movie;
+pub mod tas;
+pub mod fault;
+pub mod sys;
+pub mod mega;
+pub mod display;
+pub mod observer;
+pub mod memory_map;
+pub mod smc;
+pub mod timing;
+pub mod romdb;
+pub mod infer;
+pub mod bundle;
+pub mod octo;
+pub mod patch;
+pub mod assembler;
+mod bytes;
+mod json;
@@ -0,0 +1,375 @@
+use crate::display::{DirtyRegion, Display, Rect};
+
+pub const MEGA_RES_X: usize = 256;
+pub const MEGA_RES_Y: usize = 192;
+pub const MEGA_MEM_SZ: usize = 0x100_0000; // Everything a 24-bit I can reach.
+pub const MEGA_PALETTE_SZ: usize = 256;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlendMode {
+    Normal,  // The sprite color, at the opacity set by 05NN.
+    Alpha25, // The sprite is drawn at 25% opacity, whatever 05NN set.
+    Alpha50,
+    Alpha75,
+    Add,
+    Multiply,
+}
+
+impl BlendMode {
+    pub fn from_u8(val: u8) -> Option<BlendMode> {
+        match val {
+            0 => Some(BlendMode::Normal),
+            1 => Some(BlendMode::Alpha25),
+            2 => Some(BlendMode::Alpha50),
+            3 => Some(BlendMode::Alpha75),
+            4 => Some(BlendMode::Add),
+            5 => Some(BlendMode::Multiply),
+            _ => None,
+        }
+    }
+
+    fn blend(&self, src: u32, dst: u32, alpha: u8) -> u32 {
+        let mix = |weight: u32| {
+            // Blend each channel by weight/4 of the sprite color.
+            map_channels(src, dst, |s, d| (s * weight + d * (4 - weight)) / 4)
+        };
+
+        match self {
+            BlendMode::Normal if alpha == 0xFF => src,
+            BlendMode::Normal => {
+                let alpha = alpha as u32;
+                map_channels(src, dst, |s, d| (s * alpha + d * (0xFF - alpha)) / 0xFF)
+            }
+            BlendMode::Alpha25 => mix(1),
+            BlendMode::Alpha50 => mix(2),
+            BlendMode::Alpha75 => mix(3),
+            BlendMode::Add => map_channels(src, dst, |s, d| std::cmp::min(0xFF, s + d)),
+            BlendMode::Multiply => map_channels(src, dst, |s, d| s * d / 0xFF),
+        }
+    }
+}
+
+fn map_channels<F: Fn(u32, u32) -> u32>(src: u32, dst: u32, f: F) -> u32 {
+    // Colors are ARGB. The result is always opaque.
+    let mut out = 0xFF00_0000;
+
+    for shift in [16, 8, 0].iter() {
+        let channel = f((src >> shift) & 0xFF, (dst >> shift) & 0xFF);
+        out |= (channel & 0xFF) << shift;
+    }
+
+    out
+}
+
+// The MEGA-CHIP8 display: a 256x192 framebuffer of 32-bit ARGB colors, drawn with sprites of palette indices.
+//
+// Drawing goes to a back buffer that 00E0 presents, which is the front buffer hosts should show. The palette
+// index of every drawn pixel is kept too, because collisions are detected by index rather than by the blended
+// color that ends up on screen.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MegaDisplay {
+    pub front: Vec<u32>,
+    pub back: Vec<u32>,
+    pub indices: Vec<u8>,
+    pub palette: [u32; MEGA_PALETTE_SZ],
+    pub sprite_width: usize,
+    pub sprite_height: usize,
+    pub alpha: u8,
+    pub blend_mode: BlendMode,
+    dirty: DirtyRegion,
+}
+
+impl MegaDisplay {
+    pub fn new() -> Self {
+        MegaDisplay {
+            front: vec![0xFF00_0000; MEGA_RES_X * MEGA_RES_Y],
+            back: vec![0xFF00_0000; MEGA_RES_X * MEGA_RES_Y],
+            indices: vec![0; MEGA_RES_X * MEGA_RES_Y],
+            palette: [0xFF00_0000; MEGA_PALETTE_SZ],
+            sprite_width: 0,
+            sprite_height: 0,
+            alpha: 0xFF,
+            blend_mode: BlendMode::Normal,
+            dirty: DirtyRegion::default(),
+        }
+    }
+
+    pub fn load_palette(&mut self, colors: &[u8]) {
+        // Colors are 4 bytes of ARGB each and fill the palette from index 1, since index 0 is transparent.
+        for (idx, color) in colors.chunks(4).enumerate().take(MEGA_PALETTE_SZ - 1) {
+            self.palette[idx + 1] = u32::from_be_bytes([color[0], color[1], color[2], color[3]]);
+        }
+    }
+
+    pub fn draw_indexed(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        // Sprites are sprite_width x sprite_height palette indices, one byte each. Index 0 is transparent, and
+        // anything off the edge of the screen is clipped. Returns whether the sprite overlapped a drawn pixel.
+        let mut collided = false;
+
+        for (row, line) in sprite.chunks(std::cmp::max(1, self.sprite_width)).enumerate() {
+            let screen_y = y + row;
+            if screen_y >= MEGA_RES_Y {
+                break;
+            }
+
+            for (col, idx) in line.iter().enumerate() {
+                let screen_x = x + col;
+                if *idx == 0 || screen_x >= MEGA_RES_X {
+                    continue;
+                }
+
+                let pos = screen_y * MEGA_RES_X + screen_x;
+                collided |= self.indices[pos] != 0;
+
+                self.indices[pos] = *idx;
+                self.back[pos] = self.blend_mode.blend(self.palette[*idx as usize], self.back[pos], self.alpha);
+            }
+        }
+
+        let rows = sprite.len() / std::cmp::max(1, self.sprite_width);
+        self.add_dirty(x, y, self.sprite_width, rows);
+
+        collided
+    }
+
+    fn add_dirty(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        // Sprites are clipped at the edges, so the area they changed is too. Nothing changed if they're entirely off
+        // the screen.
+        let width = std::cmp::min(width, MEGA_RES_X.saturating_sub(x));
+        let height = std::cmp::min(height, MEGA_RES_Y.saturating_sub(y));
+
+        if width > 0 && height > 0 {
+            self.dirty.add(Rect::new(x, y, width, height));
+        }
+    }
+
+    fn shift(&mut self, dx: isize, dy: isize) {
+        // Move the back buffer's pixels by (dx, dy), leaving transparent black where nothing moved in.
+        let mut back = vec![0xFF00_0000; self.back.len()];
+        let mut indices = vec![0; self.indices.len()];
+
+        for y in 0..MEGA_RES_Y {
+            for x in 0..MEGA_RES_X {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+
+                if src_x >= 0 && src_x < MEGA_RES_X as isize && src_y >= 0 && src_y < MEGA_RES_Y as isize {
+                    let src = src_y as usize * MEGA_RES_X + src_x as usize;
+                    back[y * MEGA_RES_X + x] = self.back[src];
+                    indices[y * MEGA_RES_X + x] = self.indices[src];
+                }
+            }
+        }
+
+        self.back = back;
+        self.indices = indices;
+        self.dirty.add(Rect::new(0, 0, MEGA_RES_X, MEGA_RES_Y));
+    }
+}
+
+// Seen as a Display, a MEGA-CHIP8 screen has one plane that is lit wherever something has been drawn since the last
+// 00E0. Clearing presents the frame first, as 00E0 does in megamode.
+impl Display for MegaDisplay {
+    fn width(&self) -> usize {
+        MEGA_RES_X
+    }
+
+    fn height(&self) -> usize {
+        MEGA_RES_Y
+    }
+
+    fn plane_count(&self) -> usize {
+        1
+    }
+
+    fn get(&self, x: usize, y: usize) -> u8 {
+        (self.indices[y * MEGA_RES_X + x] != 0) as u8
+    }
+
+    fn clear(&mut self, planes: u8) {
+        if planes & 1 == 0 {
+            return;
+        }
+
+        self.front.copy_from_slice(&self.back);
+        self.back.iter_mut().for_each(|pixel| *pixel = 0xFF00_0000);
+        self.indices.iter_mut().for_each(|idx| *idx = 0);
+        self.dirty.add(Rect::new(0, 0, MEGA_RES_X, MEGA_RES_Y));
+    }
+
+    fn draw_sprite(&mut self, plane: usize, x: usize, y: usize, sprite: &[u8], width: usize) -> bool {
+        // 1-bit sprites toggle pixels between transparent and palette index 1, clipped at the edges like indexed ones.
+        if plane != 0 {
+            return false;
+        }
+
+        let bytes_per_row = std::cmp::max(1, width / 8);
+        let mut collided = false;
+
+        for (row, line) in sprite.chunks(bytes_per_row).enumerate() {
+            for col in 0..bytes_per_row * 8 {
+                let (screen_x, screen_y) = (x + col, y + row);
+                let lit = line.get(col / 8).is_some_and(|byte| byte & (0x80 >> (col % 8)) != 0);
+                if !lit || screen_x >= MEGA_RES_X || screen_y >= MEGA_RES_Y {
+                    continue;
+                }
+
+                let pos = screen_y * MEGA_RES_X + screen_x;
+                if self.indices[pos] != 0 {
+                    collided = true;
+                    self.indices[pos] = 0;
+                    self.back[pos] = 0xFF00_0000;
+                } else {
+                    self.indices[pos] = 1;
+                    self.back[pos] = self.palette[1];
+                }
+            }
+        }
+
+        self.add_dirty(x, y, bytes_per_row * 8, sprite.len() / bytes_per_row);
+
+        collided
+    }
+
+    fn scroll_up(&mut self, planes: u8, rows: usize) {
+        if planes & 1 != 0 {
+            self.shift(0, -(std::cmp::min(rows, MEGA_RES_Y) as isize));
+        }
+    }
+
+    fn scroll_down(&mut self, planes: u8, rows: usize) {
+        if planes & 1 != 0 {
+            self.shift(0, std::cmp::min(rows, MEGA_RES_Y) as isize);
+        }
+    }
+
+    fn scroll_left(&mut self, planes: u8, cols: usize) {
+        if planes & 1 != 0 {
+            self.shift(-(std::cmp::min(cols, MEGA_RES_X) as isize), 0);
+        }
+    }
+
+    fn scroll_right(&mut self, planes: u8, cols: usize) {
+        if planes & 1 != 0 {
+            self.shift(std::cmp::min(cols, MEGA_RES_X) as isize, 0);
+        }
+    }
+
+    fn take_dirty(&mut self) -> Option<Rect> {
+        self.dirty.take()
+    }
+}
+
+impl Default for MegaDisplay {
+    fn default() -> Self {
+        MegaDisplay::new()
+    }
+}
+
+// The header MEGA-CHIP8 digitized sound starts with: a 16-bit sample rate and a 24-bit length, both big-endian,
+// followed by 8-bit unsigned samples.
+pub const SOUND_HEADER_SZ: usize = 5;
+
+pub fn parse_sound(data: &[u8]) -> Option<(u16, &[u8])> {
+    if data.len() < SOUND_HEADER_SZ {
+        return None;
+    }
+
+    let sample_rate = u16::from_be_bytes([data[0], data[1]]);
+    let len = u32::from_be_bytes([0, data[2], data[3], data[4]]) as usize;
+    let samples = data.get(SOUND_HEADER_SZ..SOUND_HEADER_SZ + len)?;
+
+    Some((sample_rate, samples))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::ALL_PLANES;
+
+    #[test]
+    fn draw_sprite_test() {
+        let mut display = MegaDisplay::new();
+        display.load_palette(&[0xFF, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0xFF]);
+        display.sprite_width = 2;
+        display.sprite_height = 2;
+
+        // A transparent pixel doesn't collide or draw.
+        assert!(!display.draw_indexed(10, 10, &[1, 0, 0, 2]));
+        assert_eq!(0xFFFF_0000, display.back[10 * MEGA_RES_X + 10]);
+        assert_eq!(0xFF00_0000, display.back[10 * MEGA_RES_X + 11]);
+        assert_eq!(0xFF00_00FF, display.back[11 * MEGA_RES_X + 11]);
+
+        assert!(display.draw_indexed(11, 11, &[1, 0, 0, 0]));
+        assert!(!display.draw_indexed(11, 10, &[1, 0, 0, 0]));
+
+        // Sprites clip at the edges rather than wrapping.
+        assert!(!display.draw_indexed(MEGA_RES_X - 1, MEGA_RES_Y - 1, &[1, 1, 1, 1]));
+        assert_eq!(0, display.indices[0]);
+
+        // Nothing shows until it's presented.
+        assert_eq!(0xFF00_0000, display.front[10 * MEGA_RES_X + 10]);
+        display.clear(ALL_PLANES);
+        assert_eq!(0xFFFF_0000, display.front[10 * MEGA_RES_X + 10]);
+        assert_eq!(0, display.indices[10 * MEGA_RES_X + 10]);
+    }
+
+    #[test]
+    fn blend_test() {
+        assert_eq!(0xFF3F_3F3F, BlendMode::Alpha25.blend(0xFFFF_FFFF, 0xFF00_0000, 0xFF));
+        assert_eq!(0xFFFF_8080, BlendMode::Add.blend(0xFFF0_0080, 0xFF20_8000, 0xFF));
+        assert_eq!(0xFF00_8000, BlendMode::Multiply.blend(0xFFFF_FFFF, 0xFF00_8000, 0xFF));
+
+        // The global alpha only applies to the normal blend mode.
+        assert_eq!(0xFF33_3333, BlendMode::Normal.blend(0xFFFF_FFFF, 0xFF00_0000, 0x33));
+        assert_eq!(0xFFFF_FFFF, BlendMode::Normal.blend(0xFFFF_FFFF, 0xFF00_0000, 0xFF));
+    }
+
+    #[test]
+    fn scroll_up_test() {
+        let mut display = MegaDisplay::new();
+        display.sprite_width = 1;
+        display.palette[1] = 0xFFFF_FFFF;
+        display.draw_indexed(0, 5, &[1]);
+
+        display.scroll_up(ALL_PLANES, 4);
+        assert_eq!(1, display.indices[MEGA_RES_X]);
+        assert_eq!(0xFFFF_FFFF, display.back[MEGA_RES_X]);
+        assert_eq!(0, display.indices[5 * MEGA_RES_X]);
+    }
+
+    #[test]
+    fn dirty_clip_test() {
+        let mut display = MegaDisplay::new();
+        display.sprite_width = 4;
+
+        display.draw_indexed(MEGA_RES_X - 2, MEGA_RES_Y - 1, &[1; 8]);
+        assert_eq!(Some(Rect::new(MEGA_RES_X - 2, MEGA_RES_Y - 1, 2, 1)), display.take_dirty());
+
+        display.draw_indexed(MEGA_RES_X, 0, &[1; 8]);
+        display.draw_sprite(0, 0, MEGA_RES_Y, &[0xFF], 8);
+        assert_eq!(None, display.take_dirty());
+    }
+
+    #[test]
+    fn bit_sprite_test() {
+        let mut display = MegaDisplay::new();
+        display.palette[1] = 0xFFFF_FFFF;
+
+        assert!(!display.draw_sprite(0, MEGA_RES_X - 4, 0, &[0xF8], 8));
+        assert_eq!(1, display.get(MEGA_RES_X - 1, 0));
+        assert_eq!(0xFFFF_FFFF, display.back[MEGA_RES_X - 1]);
+        assert_eq!(0, display.get(0, 0));
+        assert_eq!(Some(Rect::new(MEGA_RES_X - 4, 0, 4, 1)), display.take_dirty());
+
+        assert!(display.draw_sprite(0, MEGA_RES_X - 4, 0, &[0x80], 8));
+        assert_eq!(0, display.get(MEGA_RES_X - 4, 0));
+    }
+
+    #[test]
+    fn parse_sound_test() {
+        let data = [0x1F, 0x40, 0x00, 0x00, 0x03, 0x80, 0x81, 0x82, 0xFF];
+        assert_eq!(Some((8000, &data[5..8])), parse_sound(&data));
+        assert_eq!(None, parse_sound(&data[0..7]));
+    }
+}
@@ -0,0 +1,201 @@
+use bitflags::*;
+
+use crate::interpreter::{InterpreterErr, MemAccess, CHAR_TABLE_LEN};
+
+bitflags! {
+    pub struct Permissions : u8 {
+        const NONE = 0x00;
+        const READ = 0x01;
+        const WRITE = 0x02;
+        const EXECUTE = 0x04;
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RegionKind {
+    Reserved, // Where the interpreter itself lived on the original hardware.
+    Font,
+    Rom,
+    Ram,
+}
+
+impl RegionKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RegionKind::Reserved => "reserved",
+            RegionKind::Font => "font",
+            RegionKind::Rom => "ROM",
+            RegionKind::Ram => "RAM",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Region {
+    pub kind: RegionKind,
+    pub start: u32,
+    pub end: u32, // Exclusive.
+    pub permissions: Permissions,
+}
+
+impl Region {
+    pub fn new(kind: RegionKind, start: u32, end: u32, permissions: Permissions) -> Self {
+        Region { kind, start, end, permissions }
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        addr >= self.start && addr < self.end
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MapMode {
+    Permissive, // Regions are only descriptive, and any access is allowed.
+    Strict,     // Accesses a region doesn't permit raise an InterpreterErr::PermissionFault.
+}
+
+impl MapMode {
+    pub fn from_name(name: &str) -> Option<MapMode> {
+        match name.to_ascii_lowercase().as_str() {
+            "permissive" => Some(MapMode::Permissive),
+            "strict" => Some(MapMode::Strict),
+            _ => None,
+        }
+    }
+}
+
+// Divides memory into regions with their own permissions.
+//
+// Real interpreters kept their own code and the font below 0x200, and a ROM that wrote there would crash or
+// corrupt the machine. Strict mode catches those writes, along with writes into the program itself and jumps into
+// anything that isn't the program. Addresses outside every region allow everything.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryMap {
+    pub mode: MapMode,
+    regions: Vec<Region>,
+}
+
+impl MemoryMap {
+    pub fn new(mode: MapMode) -> Self {
+        MemoryMap { mode, regions: Vec::new() }
+    }
+
+    pub fn standard(rom_start: usize, rom_len: usize, mem_sz: usize) -> Self {
+        // The font and the interpreter area are read-only, the program can be read and executed, and the memory after
+        // it is the program's to read and write.
+        let rom_start = rom_start as u32;
+        let rom_end = rom_start + rom_len as u32;
+
+        MemoryMap {
+            mode: MapMode::Permissive,
+            regions: vec![
+                Region::new(RegionKind::Font, 0, CHAR_TABLE_LEN as u32, Permissions::READ),
+                Region::new(RegionKind::Reserved, CHAR_TABLE_LEN as u32, rom_start, Permissions::READ),
+                Region::new(RegionKind::Rom, rom_start, rom_end, Permissions::READ | Permissions::EXECUTE),
+                Region::new(RegionKind::Ram, rom_end, mem_sz as u32, Permissions::READ | Permissions::WRITE),
+            ],
+        }
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn add_region(&mut self, region: Region) {
+        // Regions added later take precedence where they overlap.
+        self.regions.push(region);
+    }
+
+    pub fn region_at(&self, addr: u32) -> Option<&Region> {
+        self.regions.iter().rev().find(|region| region.contains(addr))
+    }
+
+    pub fn check(&self, addr: u32, access: MemAccess) -> Result<(), InterpreterErr> {
+        if self.mode == MapMode::Permissive {
+            return Ok(());
+        }
+
+        let required = match access {
+            MemAccess::Read => Permissions::READ,
+            MemAccess::Write => Permissions::WRITE,
+            MemAccess::Execute => Permissions::EXECUTE,
+        };
+
+        match self.region_at(addr) {
+            Some(region) if !region.permissions.contains(required) => {
+                Err(InterpreterErr::PermissionFault(access, addr, region.kind))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn relocate_rom(&mut self, rom_start: usize) {
+        // Move the program's region, and the regions either side of it to meet it, for interpreters like CHIP-8X that
+        // load programs higher up.
+        let rom_start = rom_start as u32;
+        let rom_end = match self.regions.iter_mut().find(|region| region.kind == RegionKind::Rom) {
+            Some(rom) => {
+                rom.end = rom_start + (rom.end - rom.start);
+                rom.start = rom_start;
+                rom.end
+            }
+            None => return,
+        };
+
+        for region in self.regions.iter_mut() {
+            match region.kind {
+                RegionKind::Reserved => region.end = rom_start,
+                RegionKind::Ram => region.start = rom_end,
+                _ => (),
+            }
+        }
+    }
+
+    pub fn resize(&mut self, mem_sz: usize) {
+        // Extend RAM to the end of a memory that has grown.
+        for region in self.regions.iter_mut().filter(|region| region.kind == RegionKind::Ram) {
+            region.end = mem_sz as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_test() {
+        let mut map = MemoryMap::standard(0x200, 0x10, 0x1000);
+
+        // Permissive mode allows anything.
+        assert_eq!(Ok(()), map.check(0x10, MemAccess::Write));
+
+        map.mode = MapMode::Strict;
+        assert_eq!(Ok(()), map.check(0x10, MemAccess::Read));
+        assert_eq!(Err(InterpreterErr::PermissionFault(MemAccess::Write, 0x10, RegionKind::Font)), map.check(0x10, MemAccess::Write));
+        assert_eq!(Err(InterpreterErr::PermissionFault(MemAccess::Write, 0x1FF, RegionKind::Reserved)), map.check(0x1FF, MemAccess::Write));
+        assert_eq!(Err(InterpreterErr::PermissionFault(MemAccess::Write, 0x20F, RegionKind::Rom)), map.check(0x20F, MemAccess::Write));
+        assert_eq!(Ok(()), map.check(0x20F, MemAccess::Execute));
+        assert_eq!(Ok(()), map.check(0x210, MemAccess::Write));
+        assert_eq!(Err(InterpreterErr::PermissionFault(MemAccess::Execute, 0x210, RegionKind::Ram)), map.check(0x210, MemAccess::Execute));
+
+        // Outside every region.
+        assert_eq!(Ok(()), map.check(0x1000, MemAccess::Execute));
+
+        // Later regions override earlier ones.
+        map.add_region(Region::new(RegionKind::Ram, 0x300, 0x400, Permissions::all()));
+        assert_eq!(Ok(()), map.check(0x300, MemAccess::Execute));
+    }
+
+    #[test]
+    fn relocate_test() {
+        let mut map = MemoryMap::standard(0x200, 0x10, 0x1000);
+        map.relocate_rom(0x300);
+        map.resize(0x2000);
+
+        assert_eq!(Some(RegionKind::Reserved), map.region_at(0x2FF).map(|region| region.kind));
+        assert_eq!(Some(RegionKind::Rom), map.region_at(0x30F).map(|region| region.kind));
+        assert_eq!(Some(RegionKind::Ram), map.region_at(0x310).map(|region| region.kind));
+        assert_eq!(Some(RegionKind::Ram), map.region_at(0x1FFF).map(|region| region.kind));
+    }
+}
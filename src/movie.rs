@@ -0,0 +1,381 @@
+use crate::bytes::{ByteReader, Truncated};
+use crate::interpreter::{Chip8Interpreter, ExecutionError, InterpreterErr, MEM_SZ};
+use crate::keycodes::KeyCodes;
+use crate::observer::Observer;
+use crate::platform_adapter::PlatformAdapter;
+use crate::profile::Profile;
+use crate::quirk_flags::QuirkFlags;
+use crate::rng::{Rng, RngKind};
+use crate::sha1::{self, DIGEST_SZ};
+
+const MAGIC: &[u8; 4] = b"C8MV";
+// Version 2 added the final state hash, and version 3 the second keypad.
+const VERSION: u8 = 3;
+const MIN_VERSION: u8 = 1;
+
+const NO_KEY: u8 = 0xFF;
+
+#[derive(Debug, PartialEq)]
+pub enum MovieErr {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    InvalidKey(u8),
+    InvalidQuirks(u8),
+    InvalidRng(u8),
+    UnknownProfile(String),
+    RomMismatch,
+    Desync,
+    FrameOutOfRange(usize),
+    Interpreter(InterpreterErr),
+    Execution(ExecutionError),
+}
+
+impl From<Truncated> for MovieErr {
+    fn from(_: Truncated) -> MovieErr {
+        MovieErr::Truncated
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MovieHeader {
+    pub rom_hash: [u8; DIGEST_SZ],
+    pub quirks: QuirkFlags,
+    pub profile: Option<Profile>,
+    pub rng_kind: RngKind,
+    pub seed: u32,
+    pub tick_rate: u64,
+}
+
+// A recording of the keypad state on every frame since power-on. Together with the ROM, the quirks and the RNG
+// seed in the header, that's enough to replay a session exactly. The hash of the state it should finish in catches
+// playback that has drifted.
+//
+// CHIP-8X's second keypad is kept apart, and only from the first frame that presses it, so that movies of
+// everything else don't carry a column of empty inputs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub header: MovieHeader,
+    pub frames: Vec<Option<KeyCodes>>,
+    pub frames_2: Vec<Option<KeyCodes>>, // Empty, or as long as frames.
+    pub final_state_hash: Option<[u8; DIGEST_SZ]>,
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], quirks: QuirkFlags, profile: Option<Profile>, rng: Rng, tick_rate: u64) -> Self {
+        Movie {
+            header: MovieHeader {
+                rom_hash: sha1::sha1(rom),
+                quirks,
+                profile,
+                rng_kind: rng.kind,
+                seed: rng.seed,
+                tick_rate,
+            },
+            frames: Vec::new(),
+            frames_2: Vec::new(),
+            final_state_hash: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.frames.truncate(len);
+        self.frames_2.truncate(len);
+    }
+
+    pub fn key_2(&self, frame: usize) -> Option<KeyCodes> {
+        self.frames_2.get(frame).copied().flatten()
+    }
+
+    pub fn verify_rom(&self, rom: &[u8]) -> Result<(), MovieErr> {
+        if sha1::sha1(rom) != self.header.rom_hash {
+            return Err(MovieErr::RomMismatch);
+        }
+
+        Ok(())
+    }
+
+    pub fn start<T: PlatformAdapter>(&self, platform_adapter: T, rom: Vec<u8>) -> Result<Chip8Interpreter<T>, MovieErr> {
+        // Build an interpreter in the exact power-on state the movie was recorded from.
+        self.verify_rom(&rom)?;
+
+        let mem_sz = self.header.profile.map_or(MEM_SZ, |profile| profile.memory_size());
+        let mut interpreter = Chip8Interpreter::with_memory_size(platform_adapter, rom, mem_sz)
+            .map_err(MovieErr::Interpreter)?;
+        if let Some(profile) = self.header.profile {
+            interpreter.apply_profile(profile).map_err(MovieErr::Interpreter)?;
+        }
+        interpreter.quirks = self.header.quirks;
+        interpreter.rng = Rng::new(self.header.rng_kind, self.header.seed);
+
+        Ok(interpreter)
+    }
+
+    pub fn seal<T: PlatformAdapter, O: Observer>(&mut self, interpreter: &Chip8Interpreter<T, O>) {
+        // Call once recording is done, with the interpreter in the state the last frame left it in.
+        self.final_state_hash = Some(interpreter.save_state().hash());
+    }
+
+    pub fn verify_final_state<T: PlatformAdapter, O: Observer>(&self, interpreter: &Chip8Interpreter<T, O>) -> Result<(), MovieErr> {
+        match self.final_state_hash {
+            Some(hash) if hash != interpreter.save_state().hash() => Err(MovieErr::Desync),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_frame<T: PlatformAdapter, O: Observer>(&mut self, interpreter: &mut Chip8Interpreter<T, O>, key: Option<KeyCodes>) -> Result<(), ExecutionError> {
+        self.record_frame_keypads(interpreter, key, None)
+    }
+
+    pub fn record_frame_keypads<T: PlatformAdapter, O: Observer>(&mut self, interpreter: &mut Chip8Interpreter<T, O>, key: Option<KeyCodes>, key_2: Option<KeyCodes>) -> Result<(), ExecutionError> {
+        interpreter.key_press = key;
+        interpreter.key_press_2 = key_2;
+        self.frames.push(key);
+
+        if key_2.is_some() || !self.frames_2.is_empty() {
+            self.frames_2.resize(self.frames.len() - 1, None);
+            self.frames_2.push(key_2);
+        }
+
+        interpreter.run_frame(self.header.tick_rate)
+    }
+
+    pub fn play_frame<T: PlatformAdapter, O: Observer>(&self, frame: usize, interpreter: &mut Chip8Interpreter<T, O>) -> Result<(), MovieErr> {
+        let key = *self.frames.get(frame).ok_or(MovieErr::FrameOutOfRange(frame))?;
+
+        interpreter.key_press = key;
+        interpreter.key_press_2 = self.key_2(frame);
+        interpreter.run_frame(self.header.tick_rate).map_err(MovieErr::Execution)
+    }
+
+    pub fn play<T: PlatformAdapter>(&self, platform_adapter: T, rom: Vec<u8>) -> Result<Chip8Interpreter<T>, MovieErr> {
+        let mut interpreter = self.start(platform_adapter, rom)?;
+
+        for frame in 0..self.frames.len() {
+            self.play_frame(frame, &mut interpreter)?;
+        }
+
+        self.verify_final_state(&interpreter)?;
+        Ok(interpreter)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let profile_name = self.header.profile.map(|profile| profile.name()).unwrap_or("");
+
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend_from_slice(&self.header.rom_hash);
+        out.push(self.header.quirks.bits());
+        out.push(rng_kind_to_u8(self.header.rng_kind));
+        out.extend_from_slice(&self.header.seed.to_le_bytes());
+        out.extend_from_slice(&self.header.tick_rate.to_le_bytes());
+        out.push(profile_name.len() as u8);
+        out.extend_from_slice(profile_name.as_bytes());
+
+        for frames in [&self.frames, &self.frames_2] {
+            out.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+            for key in frames.iter() {
+                out.push(key.map(|keycode| keycode as u8).unwrap_or(NO_KEY));
+            }
+        }
+
+        match self.final_state_hash {
+            Some(hash) => {
+                out.push(1);
+                out.extend_from_slice(&hash);
+            }
+            None => out.push(0),
+        }
+
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, MovieErr> {
+        let mut reader = ByteReader::new(bytes);
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(MovieErr::BadMagic);
+        }
+
+        let version = reader.u8()?;
+        if !(MIN_VERSION..=VERSION).contains(&version) {
+            return Err(MovieErr::UnsupportedVersion(version));
+        }
+
+        let mut rom_hash = [0u8; DIGEST_SZ];
+        rom_hash.copy_from_slice(reader.take(DIGEST_SZ)?);
+
+        let quirk_bits = reader.u8()?;
+        let quirks = QuirkFlags::from_bits(quirk_bits).ok_or(MovieErr::InvalidQuirks(quirk_bits))?;
+        let rng_kind = rng_kind_from_u8(reader.u8()?)?;
+        let seed = u32::from_le_bytes(reader.array()?);
+        let tick_rate = u64::from_le_bytes(reader.array()?);
+
+        let profile_len = reader.u8()? as usize;
+        let profile_name = String::from_utf8_lossy(reader.take(profile_len)?).into_owned();
+        let profile = match profile_name.as_str() {
+            "" => None,
+            name => Some(Profile::from_name(name).ok_or_else(|| MovieErr::UnknownProfile(profile_name.clone()))?),
+        };
+
+        let frames = read_frames(&mut reader)?;
+        let frames_2 = match version {
+            1 | 2 => Vec::new(),
+            _ => read_frames(&mut reader)?,
+        };
+
+        let final_state_hash = match version {
+            1 => None,
+            _ => match reader.u8()? {
+                0 => None,
+                _ => Some(reader.array()?),
+            },
+        };
+
+        Ok(Movie {
+            header: MovieHeader { rom_hash, quirks, profile, rng_kind, seed, tick_rate },
+            frames,
+            frames_2,
+            final_state_hash,
+        })
+    }
+}
+
+fn read_frames(reader: &mut ByteReader) -> Result<Vec<Option<KeyCodes>>, MovieErr> {
+    let frame_count = u32::from_le_bytes(reader.array()?) as usize;
+    reader.take(frame_count)?
+        .iter()
+        .map(|byte| match *byte {
+            NO_KEY => Ok(None),
+            val => KeyCodes::from_u8(val).map(Some).ok_or(MovieErr::InvalidKey(val)),
+        })
+        .collect()
+}
+
+fn rng_kind_to_u8(kind: RngKind) -> u8 {
+    match kind {
+        RngKind::XorShift => 0,
+        RngKind::CosmacVip => 1,
+    }
+}
+
+fn rng_kind_from_u8(val: u8) -> Result<RngKind, MovieErr> {
+    match val {
+        0 => Ok(RngKind::XorShift),
+        1 => Ok(RngKind::CosmacVip),
+        _ => Err(MovieErr::InvalidRng(val)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockPlatform;
+
+    impl PlatformAdapter for MockPlatform {
+        fn play_sound(&mut self) {}
+        fn pause_sound(&mut self) {}
+    }
+
+    // Waits for a key, adds a random number into V1 and loops back to wait again.
+    const ROM: [u8; 8] = [0xF0, 0x0A, 0xC2, 0xFF, 0x81, 0x24, 0x12, 0x00];
+
+    fn record_movie() -> (Movie, Chip8Interpreter<MockPlatform>) {
+        let rng = Rng::new(RngKind::XorShift, 42);
+        let mut movie = Movie::new(&ROM, Profile::CosmacVip.quirks(), Some(Profile::CosmacVip), rng, 600);
+        let mut interpreter = movie.start(MockPlatform, ROM.to_vec()).unwrap();
+
+        let inputs = [None, Some(KeyCodes::Key5), None, None, Some(KeyCodes::KeyA), Some(KeyCodes::KeyA), None];
+        for key in inputs.iter() {
+            movie.record_frame(&mut interpreter, *key).unwrap();
+        }
+        movie.seal(&interpreter);
+
+        (movie, interpreter)
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let (movie, _) = record_movie();
+        assert_eq!(movie, Movie::from_bytes(&movie.to_bytes()).unwrap());
+    }
+
+    #[test]
+    fn playback_test() {
+        // Playing a movie back should land in exactly the state the recording finished in.
+        let (movie, recorded) = record_movie();
+        let played = movie.play(MockPlatform, ROM.to_vec()).unwrap();
+
+        assert_eq!(recorded.save_state(), played.save_state());
+        assert_ne!(0, played.v_regs[0x01]);
+
+        let mut interpreter = movie.start(MockPlatform, ROM.to_vec()).unwrap();
+        assert_eq!(Err(MovieErr::FrameOutOfRange(movie.len())), movie.play_frame(movie.len(), &mut interpreter));
+    }
+
+    #[test]
+    fn wrong_rom_test() {
+        let (movie, _) = record_movie();
+        let mut other_rom = ROM.to_vec();
+        other_rom[1] = 0x0B;
+
+        assert_eq!(Some(MovieErr::RomMismatch), movie.play(MockPlatform, other_rom).err());
+    }
+
+    #[test]
+    fn malformed_movie_test() {
+        let (movie, _) = record_movie();
+        let bytes = movie.to_bytes();
+
+        assert_eq!(Some(MovieErr::BadMagic), Movie::from_bytes(b"NOPE").err());
+        assert_eq!(Some(MovieErr::Truncated), Movie::from_bytes(&bytes[..bytes.len() - 1]).err());
+
+        let mut bad_key = bytes.clone();
+        let last_key = bytes.len() - 1 - 1 - DIGEST_SZ - 4;
+        bad_key[last_key] = 0x10;
+        assert_eq!(Some(MovieErr::InvalidKey(0x10)), Movie::from_bytes(&bad_key).err());
+    }
+
+    #[test]
+    fn second_keypad_test() {
+        // CHIP-8X: counts in V1 the frames where key 0 isn't held on the second keypad.
+        let rom = [0xE0, 0xF2, 0x71, 0x01, 0x13, 0x00];
+        let rng = Rng::new(RngKind::XorShift, 42);
+        let mut movie = Movie::new(&rom, Profile::Chip8X.quirks(), Some(Profile::Chip8X), rng, 60);
+        let mut interpreter = movie.start(MockPlatform, rom.to_vec()).unwrap();
+
+        let inputs = [None, None, Some(KeyCodes::Key0), Some(KeyCodes::Key0), None];
+        for key_2 in inputs.iter() {
+            movie.record_frame_keypads(&mut interpreter, None, *key_2).unwrap();
+        }
+        movie.seal(&interpreter);
+
+        // The second keypad is only stored from the first frame that uses it.
+        assert_eq!(vec![None, None, Some(KeyCodes::Key0), Some(KeyCodes::Key0), None], movie.frames_2);
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        let played = movie.play(MockPlatform, rom.to_vec()).unwrap();
+        assert_eq!(interpreter.save_state(), played.save_state());
+
+        let (plain, _) = record_movie();
+        assert!(plain.frames_2.is_empty());
+    }
+
+    #[test]
+    fn desync_test() {
+        // An input that differs from the one recorded must be caught by the final state hash.
+        let (mut movie, _) = record_movie();
+        movie.frames[5] = Some(KeyCodes::KeyB);
+
+        assert_eq!(Some(MovieErr::Desync), movie.play(MockPlatform, ROM.to_vec()).err());
+    }
+}
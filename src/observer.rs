@@ -0,0 +1,102 @@
+use crate::interpreter::ExecutionError;
+use crate::keycodes::KeyCodes;
+use crate::opcode::DecodedInstruction;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TimerKind {
+    Delay,
+    Sound,
+}
+
+// Callbacks for instrumenting the interpreter: profilers, tracers, achievements and the like.
+//
+// Every callback does nothing by default, so an observer only implements the ones it needs. The interpreter takes
+// its observer as a type parameter defaulting to NoopObserver, which compiles away to nothing.
+pub trait Observer {
+    // After an instruction has executed successfully, with the address it was fetched from.
+    fn on_instruction(&mut self, _pc: u16, _instr: &DecodedInstruction) {}
+
+    // Memory accesses, including instruction fetches, with the address actually accessed.
+    fn on_mem_read(&mut self, _addr: u32, _val: u8) {}
+    fn on_mem_write(&mut self, _addr: u32, _val: u8) {}
+
+    fn on_reg_write(&mut self, _reg_idx: u8, _val: u8) {}
+
+    // 2NNN and 00EE, with the address of the instruction and where it went.
+    fn on_call(&mut self, _pc: u16, _addr: u16) {}
+    fn on_return(&mut self, _pc: u16, _addr: u16) {}
+
+    // DXYN, with the sprite's position and height.
+    fn on_sprite(&mut self, _x: u8, _y: u8, _rows: usize, _collided: bool) {}
+
+    fn on_timer_set(&mut self, _timer: TimerKind, _val: u8) {}
+
+    fn on_sound_start(&mut self) {}
+    fn on_sound_stop(&mut self) {}
+
+    // FX0A starting to wait, and the key press that ended the wait.
+    fn on_key_wait_begin(&mut self, _reg_idx: u8) {}
+    fn on_key_wait_end(&mut self, _reg_idx: u8, _key: KeyCodes) {}
+
+    fn on_error(&mut self, _err: &ExecutionError) {}
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct NoopObserver;
+
+impl Observer for NoopObserver {}
+
+// Several observers at once, called in order.
+impl Observer for Vec<Box<dyn Observer>> {
+    fn on_instruction(&mut self, pc: u16, instr: &DecodedInstruction) {
+        self.iter_mut().for_each(|observer| observer.on_instruction(pc, instr));
+    }
+
+    fn on_mem_read(&mut self, addr: u32, val: u8) {
+        self.iter_mut().for_each(|observer| observer.on_mem_read(addr, val));
+    }
+
+    fn on_mem_write(&mut self, addr: u32, val: u8) {
+        self.iter_mut().for_each(|observer| observer.on_mem_write(addr, val));
+    }
+
+    fn on_reg_write(&mut self, reg_idx: u8, val: u8) {
+        self.iter_mut().for_each(|observer| observer.on_reg_write(reg_idx, val));
+    }
+
+    fn on_call(&mut self, pc: u16, addr: u16) {
+        self.iter_mut().for_each(|observer| observer.on_call(pc, addr));
+    }
+
+    fn on_return(&mut self, pc: u16, addr: u16) {
+        self.iter_mut().for_each(|observer| observer.on_return(pc, addr));
+    }
+
+    fn on_sprite(&mut self, x: u8, y: u8, rows: usize, collided: bool) {
+        self.iter_mut().for_each(|observer| observer.on_sprite(x, y, rows, collided));
+    }
+
+    fn on_timer_set(&mut self, timer: TimerKind, val: u8) {
+        self.iter_mut().for_each(|observer| observer.on_timer_set(timer, val));
+    }
+
+    fn on_sound_start(&mut self) {
+        self.iter_mut().for_each(|observer| observer.on_sound_start());
+    }
+
+    fn on_sound_stop(&mut self) {
+        self.iter_mut().for_each(|observer| observer.on_sound_stop());
+    }
+
+    fn on_key_wait_begin(&mut self, reg_idx: u8) {
+        self.iter_mut().for_each(|observer| observer.on_key_wait_begin(reg_idx));
+    }
+
+    fn on_key_wait_end(&mut self, reg_idx: u8, key: KeyCodes) {
+        self.iter_mut().for_each(|observer| observer.on_key_wait_end(reg_idx, key));
+    }
+
+    fn on_error(&mut self, err: &ExecutionError) {
+        self.iter_mut().for_each(|observer| observer.on_error(err));
+    }
+}
@@ -0,0 +1,329 @@
+use crate::assembler::{self, AsmErr};
+use crate::bundle::RomBundle;
+use crate::bytes::{ByteReader, Truncated};
+use crate::gif::{self, GifErr, GifFrame};
+use crate::json::Json;
+use crate::profile::Profile;
+use crate::quirk_flags::QuirkFlags;
+use crate::render::{rgb_to_rgba, Palette};
+
+// The size of the cartridges we write. Readers take whatever size the GIF is.
+const CARTRIDGE_WIDTH: u16 = 128;
+const CARTRIDGE_HEIGHT: u16 = 64;
+
+// Octo's default colors, for options a cartridge leaves out.
+const DEFAULT_COLORS: [(&str, u32); 4] = [
+    ("backgroundColor", 0x996600),
+    ("fillColor", 0xFFCC00),
+    ("fillColor2", 0xFF6600),
+    ("blendColor", 0x662200),
+];
+
+// Options Octo uses for its largest memory sizes, S-CHIP's 3583 bytes and XO-CHIP's 65024.
+const SCHIP_MAX_SIZE: u64 = 3583;
+const XOCHIP_MAX_SIZE: u64 = 65024;
+
+#[derive(Debug, PartialEq)]
+pub enum CartridgeErr {
+    Gif(GifErr),
+    Truncated,
+    InvalidText,
+    InvalidJson(usize), // Byte offset of the syntax error in the payload.
+    MissingProgram,
+}
+
+impl From<Truncated> for CartridgeErr {
+    fn from(_: Truncated) -> CartridgeErr {
+        CartridgeErr::Truncated
+    }
+}
+
+// A game shared as an Octo "cartridge": a GIF whose pixels carry the program and its options.
+//
+// The low four bits of every pixel's color index hold one nibble of the payload, high nibble first, reading each
+// frame in turn. The high four bits are free for the label art, with the palette drawing all sixteen indices of a
+// label color in near-identical shades. The payload is a big-endian u32 length followed by UTF-8 JSON of the form
+// {"program": ..., "options": {...}}.
+//
+// The program is Octo source, kept as it is so that cartridges round-trip even when it uses parts of Octo our
+// assembler doesn't support. rom assembles it, and to_bundle carries the ROM and the options over into a ROM bundle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OctoCartridge {
+    pub program: String,
+    pub options: Vec<(String, String)>, // In the cartridge's order. Strings unquoted, numbers and booleans as written.
+}
+
+impl OctoCartridge {
+    pub fn new(program: String) -> Self {
+        OctoCartridge { program, options: Vec::new() }
+    }
+
+    pub fn is_cartridge(bytes: &[u8]) -> bool {
+        bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a")
+    }
+
+    pub fn from_gif(bytes: &[u8]) -> Result<OctoCartridge, CartridgeErr> {
+        let gif = gif::decode(bytes).map_err(CartridgeErr::Gif)?;
+
+        let nibbles: Vec<u8> = gif.frames.iter().flat_map(|frame| frame.pixels.iter().map(|idx| idx & 0x0F)).collect();
+        let payload: Vec<u8> = nibbles.chunks_exact(2).map(|pair| (pair[0] << 4) | pair[1]).collect();
+
+        let mut reader = ByteReader::new(&payload);
+        let len = u32::from_be_bytes(reader.array()?) as usize;
+        let text = std::str::from_utf8(reader.take(len)?).map_err(|_| CartridgeErr::InvalidText)?;
+        let json = Json::parse(text).map_err(|err| CartridgeErr::InvalidJson(err.pos))?;
+
+        let program = json.get("program").and_then(Json::as_str).ok_or(CartridgeErr::MissingProgram)?;
+        let options = json.get("options").and_then(Json::as_object).unwrap_or(&[])
+            .iter()
+            .map(|(name, val)| {
+                let val = match val {
+                    Json::String(text) => text.clone(),
+                    val => val.to_string(),
+                };
+                (name.clone(), val)
+            })
+            .collect();
+
+        Ok(OctoCartridge { program: String::from(program), options })
+    }
+
+    pub fn to_gif(&self) -> Vec<u8> {
+        let options = self.options.iter()
+            .map(|(name, val)| (name.clone(), option_to_json(val)))
+            .collect();
+        let json = Json::Object(vec![
+            (String::from("program"), Json::String(self.program.clone())),
+            (String::from("options"), Json::Object(options)),
+        ]);
+
+        let text = json.to_string();
+        let mut payload = (text.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(text.as_bytes());
+
+        let nibbles: Vec<u8> = payload.iter().flat_map(|byte| [byte >> 4, byte & 0x0F]).collect();
+        let label = label_art();
+
+        let frames = nibbles.chunks(label.len())
+            .map(|chunk| {
+                let pixels = label.iter().enumerate()
+                    .map(|(idx, color)| (color << 4) | chunk.get(idx).copied().unwrap_or(0))
+                    .collect();
+                GifFrame { pixels, delay_cs: 0 }
+            })
+            .collect::<Vec<_>>();
+
+        gif::encode(CARTRIDGE_WIDTH, CARTRIDGE_HEIGHT, &self.gif_palette(), &frames, true)
+    }
+
+    pub fn option(&self, name: &str) -> Option<&str> {
+        self.options.iter().find(|(option, _)| option == name).map(|(_, val)| val.as_str())
+    }
+
+    pub fn profile(&self) -> Profile {
+        // Octo's quirk settings are all CHIP-8 or S-CHIP behaviour. XO-CHIP is closest to S-CHIP of the profiles we
+        // have.
+        let schip = self.flag("shiftQuirks") == Some(true)
+            || self.flag("loadStoreQuirks") == Some(true)
+            || self.number("maxSize").is_some_and(|size| size == SCHIP_MAX_SIZE || size == XOCHIP_MAX_SIZE);
+
+        match schip {
+            true => Profile::SChip,
+            false => Profile::CosmacVip,
+        }
+    }
+
+    pub fn quirks(&self) -> QuirkFlags {
+        // Only the quirks we model. "shiftQuirks" means 8XY6 and 8XYE shift VX in place rather than VY, and
+        // "loadStoreQuirks" that FX55 and FX65 don't advance I.
+        let mut quirks = self.profile().quirks();
+
+        if let Some(shift) = self.flag("shiftQuirks") {
+            quirks.set(QuirkFlags::QUIRK_8XY6 | QuirkFlags::QUIRK_8XYE, !shift);
+        }
+
+        if let Some(load_store) = self.flag("loadStoreQuirks") {
+            quirks.set(QuirkFlags::QUIRK_FX55 | QuirkFlags::QUIRK_FX65, !load_store);
+        }
+
+        quirks
+    }
+
+    pub fn tick_rate(&self) -> Option<u64> {
+        // Octo's tickrate is instructions per frame.
+        self.number("tickrate").map(|ipf| ipf * 60)
+    }
+
+    pub fn palette(&self) -> Option<Palette> {
+        // Only when the cartridge sets at least one color, with Octo's defaults filling in the rest.
+        if !DEFAULT_COLORS.iter().any(|(name, _)| self.color(name).is_some()) {
+            return None;
+        }
+
+        Some(self.colors())
+    }
+
+    pub fn rom(&self) -> Result<Vec<u8>, AsmErr> {
+        assembler::assemble(&self.program)
+    }
+
+    pub fn to_bundle(&self) -> Result<RomBundle, AsmErr> {
+        let mut bundle = RomBundle::new(self.rom()?);
+        bundle.profile = Some(self.profile());
+        bundle.quirks = Some(self.quirks());
+        bundle.tick_rate = self.tick_rate();
+        bundle.palette = self.palette();
+        bundle.octo_options = self.options.clone();
+        Ok(bundle)
+    }
+
+    fn flag(&self, name: &str) -> Option<bool> {
+        match self.option(name)? {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    fn number(&self, name: &str) -> Option<u64> {
+        self.option(name)?.parse().ok()
+    }
+
+    fn color(&self, name: &str) -> Option<u32> {
+        u32::from_str_radix(self.option(name)?.strip_prefix('#')?, 16).ok().filter(|rgb| *rgb <= 0xFFFFFF)
+    }
+
+    fn colors(&self) -> Palette {
+        let mut palette = Palette::monochrome();
+        for (idx, (name, default)) in DEFAULT_COLORS.iter().enumerate() {
+            palette.colors[idx] = rgb_to_rgba(self.color(name).unwrap_or(*default));
+        }
+
+        palette
+    }
+
+    fn gif_palette(&self) -> Vec<[u8; 3]> {
+        // Every index of a label color is the same shade, give or take the lowest two bits of red and green.
+        let palette = self.colors();
+
+        (0..256)
+            .map(|idx| {
+                let [r, g, b, _] = palette.colors[(idx >> 4) % palette.colors.len()];
+                let nibble = idx as u8 & 0x0F;
+                [(r & 0xFC) | (nibble & 0x03), (g & 0xFC) | (nibble >> 2), b]
+            })
+            .collect()
+    }
+}
+
+fn option_to_json(val: &str) -> Json {
+    // Options are kept as text, so numbers and booleans go back out as themselves and everything else as a string.
+    match val {
+        "true" => Json::Bool(true),
+        "false" => Json::Bool(false),
+        _ => match val.parse::<f64>() {
+            Ok(num) if num.is_finite() => Json::Number(num),
+            _ => Json::String(String::from(val)),
+        },
+    }
+}
+
+fn label_art() -> Vec<u8> {
+    // A plain label: the fill color with a border in the blend color, on the background.
+    let (width, height) = (CARTRIDGE_WIDTH as usize, CARTRIDGE_HEIGHT as usize);
+
+    let mut pixels = vec![0u8; width * height];
+    for y in 4..height - 4 {
+        for x in 4..width - 4 {
+            let border = x < 6 || y < 6 || x >= width - 6 || y >= height - 6;
+            pixels[y * width + x] = if border { 3 } else { 1 };
+        }
+    }
+
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::AsmErrKind;
+
+    fn example() -> OctoCartridge {
+        OctoCartridge {
+            program: String::from(": main\n\tloop again\n# \"quoted\" é\n"),
+            options: vec![
+                (String::from("tickrate"), String::from("20")),
+                (String::from("fillColor"), String::from("#3D8026")),
+                (String::from("shiftQuirks"), String::from("true")),
+                (String::from("loadStoreQuirks"), String::from("false")),
+                (String::from("fontStyle"), String::from("octo")),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let cartridge = example();
+        let bytes = cartridge.to_gif();
+        assert!(OctoCartridge::is_cartridge(&bytes));
+        assert_eq!(Ok(cartridge), OctoCartridge::from_gif(&bytes));
+
+        // A program too long for one frame spills into more.
+        let long = OctoCartridge::new(": main\n".repeat(1000));
+        let gif = gif::decode(&long.to_gif()).unwrap();
+        assert_eq!(2, gif.frames.len());
+        assert_eq!(Ok(long.clone()), OctoCartridge::from_gif(&long.to_gif()));
+    }
+
+    #[test]
+    fn options_test() {
+        let cartridge = example();
+        assert_eq!(Profile::SChip, cartridge.profile());
+        assert_eq!(QuirkFlags::QUIRK_FX55 | QuirkFlags::QUIRK_FX65, cartridge.quirks());
+        assert_eq!(Some(1200), cartridge.tick_rate());
+
+        let palette = cartridge.palette().unwrap();
+        assert_eq!([0x99, 0x66, 0x00, 0xFF], palette.colors[0]);
+        assert_eq!([0x3D, 0x80, 0x26, 0xFF], palette.colors[1]);
+
+        let bare = OctoCartridge::new(String::new());
+        assert_eq!(Profile::CosmacVip, bare.profile());
+        assert_eq!(Profile::CosmacVip.quirks(), bare.quirks());
+        assert_eq!(None, bare.tick_rate());
+        assert_eq!(None, bare.palette());
+
+        assert_eq!(Ok(vec![0x12, 0x00]), cartridge.rom());
+        let bundle = cartridge.to_bundle().unwrap();
+        assert_eq!(vec![0x12, 0x00], bundle.rom);
+        assert_eq!(Some(cartridge.quirks()), bundle.quirks);
+        assert_eq!(cartridge.options, bundle.octo_options);
+    }
+
+    #[test]
+    fn from_gif_err_test() {
+        let palette = [[0, 0, 0], [255, 255, 255]];
+        let frame = |pixels: Vec<u8>| vec![GifFrame { pixels, delay_cs: 0 }];
+
+        assert_eq!(Err(CartridgeErr::Gif(GifErr::BadMagic)), OctoCartridge::from_gif(b"C8RB\x01\x00\x00"));
+
+        // A length of 0x10 with no payload after it.
+        let short = gif::encode(8, 1, &palette, &frame(vec![0, 0, 0, 0, 0, 0, 1, 0]), false);
+        assert_eq!(Err(CartridgeErr::Truncated), OctoCartridge::from_gif(&short));
+
+        // A length of 0 leaves nothing to parse.
+        let empty = gif::encode(8, 1, &palette, &frame(vec![0; 8]), false);
+        assert_eq!(Err(CartridgeErr::InvalidJson(0)), OctoCartridge::from_gif(&empty));
+    }
+
+    #[test]
+    fn unassembled_test() {
+        // Source the assembler can't handle still reads and writes, and only fails once it's assembled.
+        let mut cartridge = OctoCartridge::new(String::from(": main\n:stringmode hex \"0123456789ABCDEF\" { v0 := VALUE }"));
+        cartridge.options.push((String::from("tickrate"), String::from("100")));
+        assert_eq!(Ok(cartridge.clone()), OctoCartridge::from_gif(&cartridge.to_gif()));
+
+        let err = AsmErr { line: 2, kind: AsmErrKind::Unsupported(String::from(":stringmode")) };
+        assert_eq!(Err(err.clone()), cartridge.rom());
+        assert_eq!(Err(err), cartridge.to_bundle());
+    }
+}
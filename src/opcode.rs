@@ -1,6 +1,6 @@
 use crate::quirk_flags::QuirkFlags;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct DecodedInstruction {
     pub instr: u16,
     pub opcode: OpCode,
@@ -14,11 +14,12 @@ impl DecodedInstruction {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum OpCode {
     // Mnenomic notation based on "Cowgod's Chip-8 Technical Reference v1.0"
     // http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
 
+    OpCode0nnn(u16),         // SYS  addr
     OpCode00e0(),            // CLS
     OpCode00ee(),            // RET
     OpCode1nnn(u16),         // JP   addr
@@ -53,6 +54,24 @@ pub enum OpCode {
     OpCodeFx33(u8),          // LD   B,   Vx
     OpCodeFx55(u8),          // LD   [I], Vx         ; quirked
     OpCodeFx65(u8),          // LD   Vx,  [I]        ; quirked
+    OpCode02a0(),            // STEP BG              ; CHIP-8X
+    OpCodeBxy0(u8, u8),      // COL  Vx,  Vy         ; CHIP-8X
+    OpCodeBxyn(u8, u8, u8),  // COL  Vx,  Vy, nibble ; CHIP-8X
+    OpCodeExf2(u8),          // SKP2 Vx              ; CHIP-8X
+    OpCodeExf5(u8),          // SKNP2 Vx             ; CHIP-8X
+    OpCodeFxf8(u8),          // OUT  Vx              ; CHIP-8X
+    OpCodeFxfb(u8),          // IN   Vx              ; CHIP-8X
+    OpCode0010(),            // MEGAOFF              ; MEGA-CHIP8
+    OpCode0011(),            // MEGAON               ; MEGA-CHIP8
+    OpCode01nn(u8),          // LDHI I,   long addr  ; MEGA-CHIP8, followed by the low 16 bits
+    OpCode02nn(u8),          // LDPAL byte           ; MEGA-CHIP8
+    OpCode03nn(u8),          // SPRW byte            ; MEGA-CHIP8
+    OpCode04nn(u8),          // SPRH byte            ; MEGA-CHIP8
+    OpCode05nn(u8),          // ALPHA byte           ; MEGA-CHIP8
+    OpCode060n(u8),          // DIGISND nibble       ; MEGA-CHIP8
+    OpCode0700(),            // STOPSND              ; MEGA-CHIP8
+    OpCode080n(u8),          // BMODE nibble         ; MEGA-CHIP8
+    OpCode00bn(u8),          // SCRU nibble          ; MEGA-CHIP8
     OpCodeInvalid(),
 }
 
@@ -82,7 +101,140 @@ pub fn decode(instr: u16, quirk_flags: QuirkFlags) -> DecodedInstruction  {
                     }
                 },
 
-                _ => invalid_instruction(instr)
+                // 02A0
+                0x02A0 if quirk_flags.contains(QuirkFlags::CHIP8X) => {
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode02a0(),
+                        mnemonic: String::from("STEP BG")
+                    }
+                },
+
+                // 0010
+                0x0010 if quirk_flags.contains(QuirkFlags::MEGACHIP) => {
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode0010(),
+                        mnemonic: String::from("MEGAOFF")
+                    }
+                },
+
+                // 0011
+                0x0011 if quirk_flags.contains(QuirkFlags::MEGACHIP) => {
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode0011(),
+                        mnemonic: String::from("MEGAON")
+                    }
+                },
+
+                // 00BN
+                0x00B0..=0x00BF if quirk_flags.contains(QuirkFlags::MEGACHIP) => {
+                    let rows = get_n4(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode00bn(rows),
+                        mnemonic: format!("SCRU {:#03X}", rows)
+                    }
+                },
+
+                // 01NN
+                0x0100..=0x01FF if quirk_flags.contains(QuirkFlags::MEGACHIP) => {
+                    let val = get_nn(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode01nn(val),
+                        mnemonic: format!("LDHI I, {:#04X}", val)
+                    }
+                },
+
+                // 02NN
+                0x0200..=0x02FF if quirk_flags.contains(QuirkFlags::MEGACHIP) => {
+                    let val = get_nn(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode02nn(val),
+                        mnemonic: format!("LDPAL {:#04X}", val)
+                    }
+                },
+
+                // 03NN
+                0x0300..=0x03FF if quirk_flags.contains(QuirkFlags::MEGACHIP) => {
+                    let val = get_nn(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode03nn(val),
+                        mnemonic: format!("SPRW {:#04X}", val)
+                    }
+                },
+
+                // 04NN
+                0x0400..=0x04FF if quirk_flags.contains(QuirkFlags::MEGACHIP) => {
+                    let val = get_nn(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode04nn(val),
+                        mnemonic: format!("SPRH {:#04X}", val)
+                    }
+                },
+
+                // 05NN
+                0x0500..=0x05FF if quirk_flags.contains(QuirkFlags::MEGACHIP) => {
+                    let val = get_nn(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode05nn(val),
+                        mnemonic: format!("ALPHA {:#04X}", val)
+                    }
+                },
+
+                // 060N
+                0x0600..=0x060F if quirk_flags.contains(QuirkFlags::MEGACHIP) => {
+                    let mode = get_n4(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode060n(mode),
+                        mnemonic: format!("DIGISND {:#03X}", mode)
+                    }
+                },
+
+                // 0700
+                0x0700 if quirk_flags.contains(QuirkFlags::MEGACHIP) => {
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode0700(),
+                        mnemonic: String::from("STOPSND")
+                    }
+                },
+
+                // 080N
+                0x0800..=0x080F if quirk_flags.contains(QuirkFlags::MEGACHIP) => {
+                    let mode = get_n4(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode080n(mode),
+                        mnemonic: format!("BMODE {:#03X}", mode)
+                    }
+                },
+
+                // 0NNN
+                _ => {
+                    let addr = get_nnn(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCode0nnn(addr),
+                        mnemonic: format!("SYS {:#05X}", addr)
+                    }
+                }
             }
         },
 
@@ -338,6 +490,31 @@ pub fn decode(instr: u16, quirk_flags: QuirkFlags) -> DecodedInstruction  {
         },
 
         // BNNN
+        0xB if quirk_flags.contains(QuirkFlags::CHIP8X) => {
+            let vx_idx = get_n2(instr);
+            let vy_idx = get_n3(instr);
+
+            match get_n4(instr) {
+                // BXY0
+                0x0 => {
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCodeBxy0(vx_idx, vy_idx),
+                        mnemonic: format!("COL V{:X}, V{:X}", vx_idx, vy_idx)
+                    }
+                },
+
+                // BXYN
+                count => {
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCodeBxyn(vx_idx, vy_idx, count),
+                        mnemonic: format!("COL V{:X}, V{:X}, {:#X}", vx_idx, vy_idx, count)
+                    }
+                }
+            }
+        },
+
         0xB => {
             let addr = get_nnn(instr);
 
@@ -400,6 +577,28 @@ pub fn decode(instr: u16, quirk_flags: QuirkFlags) -> DecodedInstruction  {
                     }
                 },
 
+                // EXF2
+                0xF2 if quirk_flags.contains(QuirkFlags::CHIP8X) => {
+                    let vx_idx = get_n2(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCodeExf2(vx_idx),
+                        mnemonic: format!("SKP2 V{:X}", vx_idx)
+                    }
+                },
+
+                // EXF5
+                0xF5 if quirk_flags.contains(QuirkFlags::CHIP8X) => {
+                    let vx_idx = get_n2(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCodeExf5(vx_idx),
+                        mnemonic: format!("SKNP2 V{:X}", vx_idx)
+                    }
+                },
+
                 _ => invalid_instruction(instr)
             }
         },
@@ -506,6 +705,28 @@ pub fn decode(instr: u16, quirk_flags: QuirkFlags) -> DecodedInstruction  {
                     }
                 },
 
+                // FXF8
+                0xF8 if quirk_flags.contains(QuirkFlags::CHIP8X) => {
+                    let vx_idx = get_n2(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCodeFxf8(vx_idx),
+                        mnemonic: format!("OUT V{:X}", vx_idx)
+                    }
+                },
+
+                // FXFB
+                0xFB if quirk_flags.contains(QuirkFlags::CHIP8X) => {
+                    let vx_idx = get_n2(instr);
+
+                    DecodedInstruction {
+                        instr,
+                        opcode: OpCode::OpCodeFxfb(vx_idx),
+                        mnemonic: format!("IN V{:X}", vx_idx)
+                    }
+                },
+
                 _ => invalid_instruction(instr)
             }
         }
@@ -560,6 +781,12 @@ mod test {
         assert_eq!(expected, *decoded_instr);
     }
 
+    #[test]
+    fn decode_0nnn_test() {
+        let decoded_instr = decode(0x0230, QuirkFlags::NONE);
+        assert_decoded_instr(0x0230, OpCode::OpCode0nnn(0x230), "SYS 0x230".to_string(), &decoded_instr)
+    }
+
     #[test]
     fn decode_00e0_test() {
         let decoded_instr = decode(0x00E0, QuirkFlags::NONE);
@@ -775,4 +1002,73 @@ mod test {
         let decoded_instr = decode(0xF165, QuirkFlags::NONE);
         assert_decoded_instr(0xF165, OpCode::OpCodeFx65(0x1), "LD V1, [I]".to_string(), &decoded_instr)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn decode_chip8x_test() {
+        let decoded_instr = decode(0x02A0, QuirkFlags::CHIP8X);
+        assert_decoded_instr(0x02A0, OpCode::OpCode02a0(), "STEP BG".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0xB120, QuirkFlags::CHIP8X);
+        assert_decoded_instr(0xB120, OpCode::OpCodeBxy0(0x1, 0x2), "COL V1, V2".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0xB123, QuirkFlags::CHIP8X);
+        assert_decoded_instr(0xB123, OpCode::OpCodeBxyn(0x1, 0x2, 0x3), "COL V1, V2, 0x3".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0xE1F2, QuirkFlags::CHIP8X);
+        assert_decoded_instr(0xE1F2, OpCode::OpCodeExf2(0x1), "SKP2 V1".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0xE1F5, QuirkFlags::CHIP8X);
+        assert_decoded_instr(0xE1F5, OpCode::OpCodeExf5(0x1), "SKNP2 V1".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0xF1F8, QuirkFlags::CHIP8X);
+        assert_decoded_instr(0xF1F8, OpCode::OpCodeFxf8(0x1), "OUT V1".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0xF1FB, QuirkFlags::CHIP8X);
+        assert_decoded_instr(0xF1FB, OpCode::OpCodeFxfb(0x1), "IN V1".to_string(), &decoded_instr);
+
+        // Without the flag these keep their usual meaning.
+        assert_eq!(OpCode::OpCode0nnn(0x2A0), decode(0x02A0, QuirkFlags::NONE).opcode);
+        assert_eq!(OpCode::OpCodeBnnn(0x120), decode(0xB120, QuirkFlags::NONE).opcode);
+        assert_eq!(OpCode::OpCodeInvalid(), decode(0xF1F8, QuirkFlags::NONE).opcode);
+    }
+
+    #[test]
+    fn decode_megachip_test() {
+        let decoded_instr = decode(0x0011, QuirkFlags::MEGACHIP);
+        assert_decoded_instr(0x0011, OpCode::OpCode0011(), "MEGAON".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0x0010, QuirkFlags::MEGACHIP);
+        assert_decoded_instr(0x0010, OpCode::OpCode0010(), "MEGAOFF".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0x0112, QuirkFlags::MEGACHIP);
+        assert_decoded_instr(0x0112, OpCode::OpCode01nn(0x12), "LDHI I, 0x12".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0x0204, QuirkFlags::MEGACHIP);
+        assert_decoded_instr(0x0204, OpCode::OpCode02nn(0x04), "LDPAL 0x04".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0x0310, QuirkFlags::MEGACHIP);
+        assert_decoded_instr(0x0310, OpCode::OpCode03nn(0x10), "SPRW 0x10".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0x0400, QuirkFlags::MEGACHIP);
+        assert_decoded_instr(0x0400, OpCode::OpCode04nn(0x00), "SPRH 0x00".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0x0580, QuirkFlags::MEGACHIP);
+        assert_decoded_instr(0x0580, OpCode::OpCode05nn(0x80), "ALPHA 0x80".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0x0601, QuirkFlags::MEGACHIP);
+        assert_decoded_instr(0x0601, OpCode::OpCode060n(0x1), "DIGISND 0x1".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0x0700, QuirkFlags::MEGACHIP);
+        assert_decoded_instr(0x0700, OpCode::OpCode0700(), "STOPSND".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0x0804, QuirkFlags::MEGACHIP);
+        assert_decoded_instr(0x0804, OpCode::OpCode080n(0x4), "BMODE 0x4".to_string(), &decoded_instr);
+
+        let decoded_instr = decode(0x00B3, QuirkFlags::MEGACHIP);
+        assert_decoded_instr(0x00B3, OpCode::OpCode00bn(0x3), "SCRU 0x3".to_string(), &decoded_instr);
+
+        // Without the flag these are SYS calls.
+        assert_eq!(OpCode::OpCode0nnn(0x011), decode(0x0011, QuirkFlags::NONE).opcode);
+        assert_eq!(OpCode::OpCode0nnn(0x204), decode(0x0204, QuirkFlags::NONE).opcode);
+    }
+}
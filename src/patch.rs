@@ -0,0 +1,489 @@
+use std::collections::HashMap;
+
+use crate::bytes::{ByteReader, Truncated};
+use crate::mega::MEGA_MEM_SZ;
+use crate::render::crc32;
+
+pub const IPS_MAGIC: &[u8; 5] = b"PATCH";
+pub const BPS_MAGIC: &[u8; 4] = b"BPS1";
+
+const IPS_EOF: &[u8; 3] = b"EOF";
+const IPS_MAX_OFFSET: usize = 0xFF_FFFF;
+const IPS_MAX_RECORD_SZ: usize = 0xFFFF;
+const IPS_MIN_RLE_SZ: usize = 4; // Shorter runs take no more room as plain records.
+
+const BPS_FOOTER_SZ: usize = 12;
+const BPS_MIN_MATCH_SZ: usize = 4;
+const BPS_MAX_CANDIDATES: usize = 32;
+
+// BPS actions, in the low two bits of each action's number. The fourth, 3, copies from the target so far.
+const SOURCE_READ: u64 = 0;
+const TARGET_READ: u64 = 1;
+const SOURCE_COPY: u64 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PatchFormat {
+    Ips,
+    Bps,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PatchErr {
+    UnknownFormat,
+    Truncated,
+    InvalidNumber,
+    OutOfBounds,
+    TooLarge, // IPS can't address past 16M, and no target can be bigger than MEGA-CHIP8's memory.
+    SourceSize { expected: usize, actual: usize },
+    SourceChecksum { expected: u32, actual: u32 },
+    TargetChecksum { expected: u32, actual: u32 },
+    PatchChecksum { expected: u32, actual: u32 },
+}
+
+impl From<Truncated> for PatchErr {
+    fn from(_: Truncated) -> PatchErr {
+        PatchErr::Truncated
+    }
+}
+
+impl PatchFormat {
+    pub fn detect(patch: &[u8]) -> Option<PatchFormat> {
+        if patch.starts_with(IPS_MAGIC) {
+            Some(PatchFormat::Ips)
+        } else if patch.starts_with(BPS_MAGIC) {
+            Some(PatchFormat::Bps)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchErr> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(rom, patch),
+        Some(PatchFormat::Bps) => apply_bps(rom, patch),
+        None => Err(PatchErr::UnknownFormat),
+    }
+}
+
+pub fn create(format: PatchFormat, original: &[u8], modified: &[u8]) -> Result<Vec<u8>, PatchErr> {
+    match format {
+        PatchFormat::Ips => create_ips(original, modified),
+        PatchFormat::Bps => Ok(create_bps(original, modified)),
+    }
+}
+
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchErr> {
+    // Records of a 24-bit offset and 16-bit size, then the bytes, or for a size of 0 a 16-bit count and the byte to
+    // repeat. All big-endian. IPS has no checksums, so a patch for a different ROM applies without complaint.
+    let mut reader = ByteReader::new(patch);
+    if reader.take(IPS_MAGIC.len())? != IPS_MAGIC {
+        return Err(PatchErr::UnknownFormat);
+    }
+
+    let mut out = rom.to_vec();
+    loop {
+        let offset = reader.take(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+
+        let offset = be(offset);
+        let size = be(reader.take(2)?);
+        let (len, data) = match size {
+            0 => {
+                let count = be(reader.take(2)?);
+                (count, None)
+            }
+            size => (size, Some(reader.take(size)?)),
+        };
+
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+
+        match data {
+            Some(data) => out[offset..offset + len].copy_from_slice(data),
+            None => {
+                let byte = reader.u8()?;
+                out[offset..offset + len].fill(byte);
+            }
+        }
+    }
+
+    // A common extension puts the size to truncate the file to after EOF.
+    if !reader.is_empty() {
+        let len = reader.take(3)?;
+        out.truncate(be(len));
+    }
+
+    Ok(out)
+}
+
+pub fn create_ips(original: &[u8], modified: &[u8]) -> Result<Vec<u8>, PatchErr> {
+    if modified.len() > IPS_MAX_OFFSET {
+        return Err(PatchErr::TooLarge);
+    }
+
+    let differs = |idx: usize| original.get(idx) != Some(&modified[idx]);
+
+    let mut out = IPS_MAGIC.to_vec();
+    let mut pos = 0;
+    while pos < modified.len() {
+        if !differs(pos) {
+            pos += 1;
+            continue;
+        }
+
+        // An offset that spells "EOF" would end the patch, so start that record a byte early.
+        let start = if pos == 0x454F46 { pos - 1 } else { pos };
+        let mut end = pos;
+        while end < modified.len() && end - start < IPS_MAX_RECORD_SZ && differs(end) {
+            end += 1;
+        }
+
+        let data = &modified[start..end];
+        out.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+
+        if data.len() >= IPS_MIN_RLE_SZ && data.iter().all(|byte| *byte == data[0]) {
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            out.push(data[0]);
+        } else {
+            out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            out.extend_from_slice(data);
+        }
+
+        pos = end;
+    }
+
+    out.extend_from_slice(IPS_EOF);
+
+    if modified.len() < original.len() {
+        out.extend_from_slice(&(modified.len() as u32).to_be_bytes()[1..]);
+    }
+
+    Ok(out)
+}
+
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchErr> {
+    // The sizes and metadata, then actions that build the target from the source, the target so far or the patch
+    // itself, then CRC-32s of the source, target and patch.
+    if patch.len() < BPS_MAGIC.len() + BPS_FOOTER_SZ {
+        return Err(PatchErr::Truncated);
+    }
+
+    let footer = patch.len() - BPS_FOOTER_SZ;
+    let checksum = |idx: usize| u32::from_le_bytes([patch[idx], patch[idx + 1], patch[idx + 2], patch[idx + 3]]);
+    let (source_crc, target_crc, patch_crc) = (checksum(footer), checksum(footer + 4), checksum(footer + 8));
+
+    let actual = crc32(&patch[..footer + 8]);
+    if actual != patch_crc {
+        return Err(PatchErr::PatchChecksum { expected: patch_crc, actual });
+    }
+
+    let mut reader = ByteReader::new(&patch[..footer]);
+    if reader.take(BPS_MAGIC.len())? != BPS_MAGIC {
+        return Err(PatchErr::UnknownFormat);
+    }
+
+    let source_sz = read_varint(&mut reader)? as usize;
+    let target_sz = read_varint(&mut reader)? as usize;
+    let metadata_sz = read_varint(&mut reader)? as usize;
+    reader.take(metadata_sz)?;
+
+    // The target size is only the patch's say-so, so it can't be trusted with an allocation.
+    if target_sz > MEGA_MEM_SZ {
+        return Err(PatchErr::TooLarge);
+    }
+
+    if source_sz != rom.len() {
+        return Err(PatchErr::SourceSize { expected: source_sz, actual: rom.len() });
+    }
+
+    let actual = crc32(rom);
+    if actual != source_crc {
+        return Err(PatchErr::SourceChecksum { expected: source_crc, actual });
+    }
+
+    let mut out = Vec::with_capacity(target_sz);
+    let mut source_offset = 0usize;
+    let mut target_offset = 0usize;
+
+    while !reader.is_empty() {
+        let action = read_varint(&mut reader)?;
+        let len = (action >> 2) as usize + 1;
+
+        if out.len() + len > target_sz {
+            return Err(PatchErr::OutOfBounds);
+        }
+
+        match action & 3 {
+            SOURCE_READ => {
+                let start = out.len();
+                out.extend_from_slice(rom.get(start..start + len).ok_or(PatchErr::OutOfBounds)?);
+            }
+            TARGET_READ => out.extend_from_slice(reader.take(len)?),
+            SOURCE_COPY => {
+                source_offset = read_relative_offset(&mut reader, source_offset)?;
+                out.extend_from_slice(rom.get(source_offset..source_offset + len).ok_or(PatchErr::OutOfBounds)?);
+                source_offset += len;
+            }
+            _ => {
+                // Target copy, which can overlap what it's writing, so it goes a byte at a time.
+                target_offset = read_relative_offset(&mut reader, target_offset)?;
+                for _ in 0..len {
+                    let byte = *out.get(target_offset).ok_or(PatchErr::OutOfBounds)?;
+                    out.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if out.len() != target_sz {
+        return Err(PatchErr::Truncated);
+    }
+
+    let actual = crc32(&out);
+    if actual != target_crc {
+        return Err(PatchErr::TargetChecksum { expected: target_crc, actual });
+    }
+
+    Ok(out)
+}
+
+pub fn create_bps(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    // Greedy: at each point take whichever is longer of the unchanged bytes in place and the longest match
+    // elsewhere in the original, falling back to literal bytes. Good enough for ROM-sized files.
+    let mut index: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (pos, window) in original.windows(BPS_MIN_MATCH_SZ).enumerate() {
+        index.entry(window).or_default().push(pos);
+    }
+
+    let mut out = BPS_MAGIC.to_vec();
+    write_varint(&mut out, original.len() as u64);
+    write_varint(&mut out, modified.len() as u64);
+    write_varint(&mut out, 0); // No metadata.
+
+    let mut source_offset = 0usize;
+    let mut literal_start = 0usize;
+    let mut pos = 0usize;
+
+    while pos < modified.len() {
+        let in_place = match_len(original.get(pos..).unwrap_or(&[]), &modified[pos..]);
+
+        let (copy_pos, copy_len) = modified.get(pos..pos + BPS_MIN_MATCH_SZ)
+            .and_then(|window| index.get(window))
+            .map(|candidates| {
+                candidates.iter().rev().take(BPS_MAX_CANDIDATES)
+                    .map(|&candidate| (candidate, match_len(&original[candidate..], &modified[pos..])))
+                    .max_by_key(|&(_, len)| len)
+                    .unwrap_or((0, 0))
+            })
+            .unwrap_or((0, 0));
+
+        let len = std::cmp::max(in_place, copy_len);
+        if len < BPS_MIN_MATCH_SZ {
+            pos += 1;
+            continue;
+        }
+
+        write_target_read(&mut out, &modified[literal_start..pos]);
+
+        if in_place >= copy_len {
+            write_action(&mut out, SOURCE_READ, len);
+        } else {
+            write_action(&mut out, SOURCE_COPY, len);
+            let delta = copy_pos as i64 - source_offset as i64;
+            write_varint(&mut out, (delta.unsigned_abs() << 1) | (delta < 0) as u64);
+            source_offset = copy_pos + len;
+        }
+
+        pos += len;
+        literal_start = pos;
+    }
+
+    write_target_read(&mut out, &modified[literal_start..]);
+
+    out.extend_from_slice(&crc32(original).to_le_bytes());
+    out.extend_from_slice(&crc32(modified).to_le_bytes());
+    let patch_crc = crc32(&out);
+    out.extend_from_slice(&patch_crc.to_le_bytes());
+    out
+}
+
+fn match_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+fn write_target_read(out: &mut Vec<u8>, data: &[u8]) {
+    if !data.is_empty() {
+        write_action(out, TARGET_READ, data.len());
+        out.extend_from_slice(data);
+    }
+}
+
+fn write_action(out: &mut Vec<u8>, action: u64, len: usize) {
+    write_varint(out, (((len - 1) as u64) << 2) | action);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut val: u64) {
+    // Seven bits at a time with the high bit marking the last byte. Each continuation also drops one, so that every
+    // number has exactly one encoding.
+    loop {
+        let bits = (val & 0x7F) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(0x80 | bits);
+            return;
+        }
+        out.push(bits);
+        val -= 1;
+    }
+}
+
+fn be(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |val, byte| (val << 8) | *byte as usize)
+}
+
+fn read_varint(reader: &mut ByteReader) -> Result<u64, PatchErr> {
+    let mut val = 0u64;
+    let mut shift = 1u64;
+
+    loop {
+        let byte = reader.u8()?;
+        val = (byte as u64 & 0x7F).checked_mul(shift).and_then(|bits| val.checked_add(bits))
+            .ok_or(PatchErr::InvalidNumber)?;
+
+        if byte & 0x80 != 0 {
+            return Ok(val);
+        }
+
+        shift = shift.checked_mul(0x80).ok_or(PatchErr::InvalidNumber)?;
+        val = val.checked_add(shift).ok_or(PatchErr::InvalidNumber)?;
+    }
+}
+
+fn read_relative_offset(reader: &mut ByteReader, offset: usize) -> Result<usize, PatchErr> {
+    // The low bit is the sign of the distance from where the last copy left off.
+    let val = read_varint(reader)?;
+    let distance = (val >> 1) as usize;
+
+    match val & 1 {
+        0 => offset.checked_add(distance),
+        _ => offset.checked_sub(distance),
+    }
+    .ok_or(PatchErr::OutOfBounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions() -> (Vec<u8>, Vec<u8>) {
+        // A ROM and a fixed version with an instruction changed, a block moved and a few bytes added at the end.
+        let original: Vec<u8> = (0..600u32).map(|idx| (idx * 7 % 251) as u8).collect();
+
+        let mut modified = original.clone();
+        modified[0x10..0x12].copy_from_slice(&[0x8E, 0x06]);
+        modified.copy_within(0x100..0x140, 0x40);
+        modified.extend_from_slice(&[0x12, 0x00, 0x00, 0x00, 0x00, 0x00]);
+
+        (original, modified)
+    }
+
+    #[test]
+    fn ips_test() {
+        let (original, modified) = versions();
+        let patch = create_ips(&original, &modified).unwrap();
+        assert_eq!(Some(PatchFormat::Ips), PatchFormat::detect(&patch));
+        assert_eq!(Ok(modified.clone()), apply(&original, &patch));
+
+        // Shrinking uses the truncation extension.
+        let patch = create_ips(&modified, &original).unwrap();
+        assert_eq!(Ok(original.clone()), apply_ips(&modified, &patch));
+
+        // Run-length records.
+        let patch = b"PATCH\x00\x00\x02\x00\x00\x00\x03\xAA\x00\x00\x06\x00\x01\xBBEOF";
+        assert_eq!(Ok(vec![0x00, 0x01, 0xAA, 0xAA, 0xAA, 0x05, 0xBB]), apply_ips(&[0, 1, 2, 3, 4, 5], patch));
+        assert_eq!(b"PATCH\x00\x00\x01\x00\x00\x00\x05\xAAEOF".to_vec(),
+            create_ips(&[0; 8], &[0, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0, 0]).unwrap());
+
+        assert_eq!(Err(PatchErr::Truncated), apply_ips(&original, b"PATCH\x00\x00\x10\x00\x02\x8E"));
+        assert_eq!(Err(PatchErr::UnknownFormat), apply(&original, b"UPS1"));
+    }
+
+    #[test]
+    fn ips_eof_offset_test() {
+        let original = vec![0u8; 0x454F50];
+        let mut modified = original.clone();
+        modified[0x454F46] = 1;
+
+        let patch = create_ips(&original, &modified).unwrap();
+        assert_eq!(&[0x45, 0x4F, 0x45, 0x00, 0x02, 0x00, 0x01], &patch[5..12]);
+        assert_eq!(Ok(modified), apply_ips(&original, &patch));
+    }
+
+    #[test]
+    fn bps_test() {
+        let (original, modified) = versions();
+        let patch = create_bps(&original, &modified);
+        assert_eq!(Some(PatchFormat::Bps), PatchFormat::detect(&patch));
+        assert!(patch.len() < 100);
+        assert_eq!(Ok(modified.clone()), apply(&original, &patch));
+        assert_eq!(Ok(original.clone()), apply_bps(&modified, &create_bps(&modified, &original)));
+        assert_eq!(Ok(Vec::new()), apply_bps(&[], &create_bps(&[], &[])));
+
+        // A target copy repeating the byte before it.
+        let mut patch = b"BPS1\x80\x85\x80\x81\xAB\x8F\x80".to_vec();
+        patch.extend_from_slice(&crc32(&[]).to_le_bytes());
+        patch.extend_from_slice(&crc32(&[0xAB; 5]).to_le_bytes());
+        let patch_crc = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        assert_eq!(Ok(vec![0xAB; 5]), apply_bps(&[], &patch));
+    }
+
+    #[test]
+    fn bps_checksum_test() {
+        let (original, modified) = versions();
+        let patch = create_bps(&original, &modified);
+
+        let mut wrong_rom = original.clone();
+        wrong_rom[0] ^= 1;
+        assert!(matches!(apply_bps(&wrong_rom, &patch), Err(PatchErr::SourceChecksum { .. })));
+        assert_eq!(Err(PatchErr::SourceSize { expected: 600, actual: 2 }), apply_bps(&[0x12, 0x00], &patch));
+
+        let mut corrupt = patch.clone();
+        corrupt[8] ^= 1;
+        assert!(matches!(apply_bps(&original, &corrupt), Err(PatchErr::PatchChecksum { .. })));
+
+        assert_eq!(Err(PatchErr::Truncated), apply_bps(&original, b"BPS1"));
+    }
+
+    #[test]
+    fn bps_too_large_test() {
+        // An otherwise valid, empty patch claiming a 2^60 byte target.
+        let mut patch = BPS_MAGIC.to_vec();
+        write_varint(&mut patch, 0);
+        write_varint(&mut patch, 1 << 60);
+        write_varint(&mut patch, 0);
+        patch.extend_from_slice(&crc32(&[]).to_le_bytes());
+        patch.extend_from_slice(&crc32(&[]).to_le_bytes());
+        let patch_crc = crc32(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+
+        assert_eq!(Err(PatchErr::TooLarge), apply_bps(&[], &patch));
+    }
+
+    #[test]
+    fn varint_test() {
+        for val in [0, 1, 127, 128, 129, 16511, 16512, 1 << 40] {
+            let mut out = Vec::new();
+            write_varint(&mut out, val);
+            assert_eq!(Ok(val), read_varint(&mut ByteReader::new(&out)));
+        }
+
+        assert_eq!(Err(PatchErr::InvalidNumber), read_varint(&mut ByteReader::new(&[0x7F; 12])));
+    }
+}
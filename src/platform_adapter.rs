@@ -1,5 +1,36 @@
+use crate::display::Rect;
+use crate::fault::FaultPolicy;
+use crate::interpreter::InterpreterErr;
+use crate::sys::{Machine, SysCallResult};
+
 pub trait PlatformAdapter {
     fn play_sound(&mut self);
     fn pause_sound(&mut self);
-    fn get_random_val(&self) -> u8;
-}
\ No newline at end of file
+
+    // Called for faults whose policy is FaultPolicy::Hook. Returning Hook again is treated as Trap.
+    fn on_fault(&mut self, _fault: &InterpreterErr) -> FaultPolicy {
+        FaultPolicy::Trap
+    }
+
+    // Called for 0NNN, which ran a machine-code routine at NNN on the original hardware.
+    fn sys_call(&mut self, _addr: u16, _machine: &mut Machine) -> SysCallResult {
+        SysCallResult::Unhandled
+    }
+
+    // The CHIP-8X I/O port, written by FXF8 and read by FXFB. FXFB waits until port_in returns a byte.
+    fn port_out(&mut self, _val: u8) {}
+
+    fn port_in(&mut self) -> Option<u8> {
+        None
+    }
+
+    // MEGA-CHIP8 digitized sound: 8-bit unsigned samples, played once or looped until stop_digitized. Starting a
+    // new sound replaces the one playing.
+    fn play_digitized(&mut self, _samples: &[u8], _sample_rate: u16, _looping: bool) {}
+
+    fn stop_digitized(&mut self) {}
+
+    // Called whenever the active display changes, with the region that changed and the new display version. A
+    // frontend can redraw just that region instead of polling take_dirty_rects.
+    fn on_display_changed(&mut self, _rect: &Rect, _version: u64) {}
+}
@@ -0,0 +1,99 @@
+use crate::interpreter::MEM_SZ;
+use crate::mega::MEGA_MEM_SZ;
+use crate::quirk_flags::QuirkFlags;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Profile {
+    CosmacVip, // The original CHIP-8 interpreter for the RCA COSMAC VIP.
+    VipHires,  // The VIP's two-page hires CHIP-8, with a 64x64 display.
+    Chip8X,    // CHIP-8X for the VIP with the VP-590 color board and a second keypad.
+    SChip,     // CHIP-48 and S-CHIP on the HP-48 calculators.
+    MegaChip,  // MEGA-CHIP8, S-CHIP extended with a 256x192 color display, long I and digitized sound.
+    Amiga,     // The Amiga CHIP-8 interpreter, which sets VF when FX1E overflows.
+}
+
+impl Profile {
+    pub fn from_name(name: &str) -> Option<Profile> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac-vip" | "chip8" | "chip-8" => Some(Profile::CosmacVip),
+            "vip-hires" | "hires" | "chip-8-hires" => Some(Profile::VipHires),
+            "chip8x" | "chip-8x" => Some(Profile::Chip8X),
+            "schip" | "s-chip" | "chip48" | "chip-48" => Some(Profile::SChip),
+            "megachip" | "mega-chip" | "megachip8" | "mega-chip8" => Some(Profile::MegaChip),
+            "amiga" => Some(Profile::Amiga),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Profile::CosmacVip => "vip",
+            Profile::VipHires => "vip-hires",
+            Profile::Chip8X => "chip-8x",
+            Profile::SChip => "schip",
+            Profile::MegaChip => "megachip",
+            Profile::Amiga => "amiga",
+        }
+    }
+
+    pub fn quirks(&self) -> QuirkFlags {
+        match self {
+            Profile::CosmacVip | Profile::VipHires => {
+                QuirkFlags::QUIRK_8XY6 | QuirkFlags::QUIRK_8XYE | QuirkFlags::QUIRK_FX55 | QuirkFlags::QUIRK_FX65
+            }
+            Profile::Chip8X => {
+                QuirkFlags::QUIRK_8XY6 | QuirkFlags::QUIRK_8XYE | QuirkFlags::QUIRK_FX55 | QuirkFlags::QUIRK_FX65
+                    | QuirkFlags::CHIP8X
+            }
+            Profile::SChip => QuirkFlags::NONE,
+            Profile::MegaChip => QuirkFlags::MEGACHIP,
+            Profile::Amiga => QuirkFlags::QUIRK_FX1E,
+        }
+    }
+
+    pub fn memory_size(&self) -> usize {
+        // What to pass to Chip8Interpreter::with_memory_size, so that ROMs bigger than 4K load.
+        match self {
+            Profile::MegaChip => MEGA_MEM_SZ,
+            _ => MEM_SZ,
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        *self == Profile::VipHires
+    }
+
+    pub fn detect(rom: &[u8]) -> Option<Profile> {
+        // Hires programs begin with 1260, the jump over the hires interpreter's code that the ROM image includes.
+        if rom.starts_with(&[0x12, 0x60]) {
+            return Some(Profile::VipHires);
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name_test() {
+        assert_eq!(Some(Profile::CosmacVip), Profile::from_name("VIP"));
+        assert_eq!(Some(Profile::SChip), Profile::from_name("chip-48"));
+        assert_eq!(None, Profile::from_name("xo-chip"));
+
+        for profile in [
+            Profile::CosmacVip, Profile::VipHires, Profile::Chip8X, Profile::SChip, Profile::MegaChip, Profile::Amiga
+        ].iter() {
+            assert_eq!(Some(*profile), Profile::from_name(profile.name()));
+        }
+    }
+
+    #[test]
+    fn detect_test() {
+        assert_eq!(Some(Profile::VipHires), Profile::detect(&[0x12, 0x60, 0x00, 0xE0]));
+        assert_eq!(None, Profile::detect(&[0x12, 0x00]));
+        assert_eq!(None, Profile::detect(&[]));
+    }
+}
@@ -8,5 +8,23 @@ bitflags! {
         const QUIRK_FX1E = 0x04;
         const QUIRK_FX55 = 0x08;
         const QUIRK_FX65 = 0x10;
+        const CHIP8X = 0x20; // Not a quirk, but the CHIP-8X instruction set changes how opcodes decode.
+        const MEGACHIP = 0x40; // Likewise for the MEGA-CHIP8 instructions, which only decode in this mode.
     }
-}
\ No newline at end of file
+}
+
+impl QuirkFlags {
+    pub fn from_name(name: &str) -> Option<QuirkFlags> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(QuirkFlags::NONE),
+            "8xy6" => Some(QuirkFlags::QUIRK_8XY6),
+            "8xye" => Some(QuirkFlags::QUIRK_8XYE),
+            "fx1e" => Some(QuirkFlags::QUIRK_FX1E),
+            "fx55" => Some(QuirkFlags::QUIRK_FX55),
+            "fx65" => Some(QuirkFlags::QUIRK_FX65),
+            "chip8x" => Some(QuirkFlags::CHIP8X),
+            "megachip" => Some(QuirkFlags::MEGACHIP),
+            _ => None,
+        }
+    }
+}
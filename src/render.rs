@@ -0,0 +1,584 @@
+use crate::display::Display;
+use crate::interpreter::{COLOR_ZONES_X, COLOR_ZONE_WIDTH};
+use crate::mega::{MegaDisplay, MEGA_RES_X, MEGA_RES_Y};
+
+// Each pixel of a frame is a bitmask of the display planes it's lit on, which indexes into a palette.
+// A 1-bit CHIP-8 display only ever uses indices 0 and 1.
+pub const PALETTE_SZ: usize = 4;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Frame {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn from_display(display: &dyn Display) -> Self {
+        let mut frame = Frame::new(display.width(), display.height());
+
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                frame.pixels[y * frame.width + x] = display.get(x, y);
+            }
+        }
+
+        frame
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn to_pbm(&self) -> Vec<u8> {
+        // Binary PBM (P4): rows are packed MSB first and padded to a whole byte, 1 is black.
+        let mut out = format!("P4\n{} {}\n", self.width, self.height).into_bytes();
+
+        for row in self.pixels.chunks(self.width) {
+            for chunk in row.chunks(8) {
+                let mut byte = 0u8;
+                for (bit, pixel) in chunk.iter().enumerate() {
+                    if *pixel != 0 {
+                        byte |= 0x80 >> bit;
+                    }
+                }
+                out.push(byte);
+            }
+        }
+
+        out
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Palette {
+    // Background, plane 1, plane 2 and both planes, as RGBA.
+    pub colors: [[u8; 4]; PALETTE_SZ],
+}
+
+impl Palette {
+    pub fn new(colors: [[u8; 4]; PALETTE_SZ]) -> Self {
+        Palette { colors }
+    }
+
+    pub fn monochrome() -> Self {
+        Palette::new([
+            [0x00, 0x00, 0x00, 0xFF],
+            [0xFF, 0xFF, 0xFF, 0xFF],
+            [0xAA, 0xAA, 0xAA, 0xFF],
+            [0x55, 0x55, 0x55, 0xFF],
+        ])
+    }
+
+    // The built-in color schemes of the Octo IDE.
+    pub fn from_name(name: &str) -> Option<Palette> {
+        let colors = match name.to_ascii_lowercase().as_str() {
+            "mono" | "monochrome" => return Some(Palette::monochrome()),
+            "octo" => [0x996600, 0xFFCC00, 0xFF6600, 0x662200],
+            "lcd" => [0xF9FFB3, 0x3D8026, 0xABCC47, 0x00131A],
+            "hotdog" => [0x000000, 0xFF0000, 0xFFFF00, 0xFFFFFF],
+            "gray" => [0xAAAAAA, 0x000000, 0xFFFFFF, 0x666666],
+            "cga0" => [0x000000, 0x00FF00, 0xFF0000, 0xFFFF00],
+            "cga1" => [0x000000, 0xFF00FF, 0x00FFFF, 0xFFFFFF],
+            _ => return None,
+        };
+
+        let mut palette = Palette::monochrome();
+        for (idx, rgb) in colors.iter().enumerate() {
+            palette.colors[idx] = rgb_to_rgba(*rgb);
+        }
+
+        Some(palette)
+    }
+}
+
+pub fn rgb_to_rgba(rgb: u32) -> [u8; 4] {
+    [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 0xFF]
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RenderOptions {
+    pub palette: Palette,
+    pub scale: usize,
+    pub grid_color: Option<[u8; 4]>,
+    pub scanlines: bool,
+}
+
+impl RenderOptions {
+    pub fn new() -> Self {
+        RenderOptions {
+            palette: Palette::monochrome(),
+            scale: 1,
+            grid_color: None,
+            scanlines: false,
+        }
+    }
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions::new()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+impl Image {
+    pub fn to_ppm(&self) -> Vec<u8> {
+        // Binary PPM (P6) has no alpha channel, so it's dropped.
+        let mut out = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+
+        for pixel in self.rgba.chunks(4) {
+            out.extend_from_slice(&pixel[0..3]);
+        }
+
+        out
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        let mut out = PNG_SIGNATURE.to_vec();
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(self.width as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(self.height as u32).to_be_bytes());
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]); // 8-bit RGBA, deflate, no filtering, no interlacing.
+        write_png_chunk(&mut out, b"IHDR", &ihdr);
+
+        // Every scanline is prefixed with its filter type, which is always 0 (none).
+        let stride = self.width * 4;
+        let mut raw = Vec::with_capacity((stride + 1) * self.height);
+        for row in self.rgba.chunks(stride) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        write_png_chunk(&mut out, b"IDAT", &zlib_compress(&raw));
+        write_png_chunk(&mut out, b"IEND", &[]);
+
+        out
+    }
+}
+
+pub fn render(frame: &Frame, options: &RenderOptions) -> Image {
+    let scale = std::cmp::max(1, options.scale);
+    let width = frame.width * scale;
+    let height = frame.height * scale;
+    let mut rgba = Vec::with_capacity(width * height * 4);
+
+    for y in 0..height {
+        let is_scanline = options.scanlines && scale > 1 && y % scale == scale - 1;
+
+        for x in 0..width {
+            let is_grid_line = scale > 1 && (x % scale == scale - 1 || y % scale == scale - 1);
+
+            let mut color = match options.grid_color {
+                Some(grid_color) if is_grid_line => grid_color,
+                _ => {
+                    let idx = frame.get(x / scale, y / scale) as usize % PALETTE_SZ;
+                    options.palette.colors[idx]
+                }
+            };
+
+            if is_scanline {
+                for channel in color.iter_mut().take(3) {
+                    *channel /= 2;
+                }
+            }
+
+            rgba.extend_from_slice(&color);
+        }
+    }
+
+    Image { width, height, rgba }
+}
+
+// The VP-590 color board's colors, by CHIP-8X color number.
+pub const CHIP8X_FOREGROUNDS: [u32; 8] = [
+    0x000000, // Black
+    0xFF0000, // Red
+    0x0000FF, // Blue
+    0xFF00FF, // Violet
+    0x00FF00, // Green
+    0xFFFF00, // Yellow
+    0x00FFFF, // Aqua
+    0xFFFFFF, // White
+];
+pub const CHIP8X_BACKGROUNDS: [u32; 4] = [
+    0x000080, // Blue
+    0x000000, // Black
+    0x008000, // Green
+    0x800000, // Red
+];
+
+pub fn render_chip8x(frame: &Frame, color_map: &[[u8; COLOR_ZONES_X]], background_color: u8, scale: usize) -> Image {
+    // Lit pixels take the color of their zone, and everything else the background.
+    let scale = std::cmp::max(1, scale);
+    let width = frame.width * scale;
+    let height = frame.height * scale;
+    let mut rgba = Vec::with_capacity(width * height * 4);
+
+    let background = rgb_to_rgba(CHIP8X_BACKGROUNDS[background_color as usize % CHIP8X_BACKGROUNDS.len()]);
+
+    for y in 0..height {
+        let zones = &color_map[(y / scale) % color_map.len()];
+
+        for x in 0..width {
+            let color = if frame.get(x / scale, y / scale) != 0 {
+                let zone = zones[(x / scale / COLOR_ZONE_WIDTH) % COLOR_ZONES_X];
+                rgb_to_rgba(CHIP8X_FOREGROUNDS[zone as usize % CHIP8X_FOREGROUNDS.len()])
+            } else {
+                background
+            };
+
+            rgba.extend_from_slice(&color);
+        }
+    }
+
+    Image { width, height, rgba }
+}
+
+pub fn render_mega(display: &MegaDisplay, scale: usize) -> Image {
+    // Render the MEGA-CHIP8 front buffer, which is what was on screen at the last 00E0.
+    let scale = std::cmp::max(1, scale);
+    let width = MEGA_RES_X * scale;
+    let height = MEGA_RES_Y * scale;
+    let mut rgba = Vec::with_capacity(width * height * 4);
+
+    for y in 0..height {
+        for x in 0..width {
+            let argb = display.front[(y / scale) * MEGA_RES_X + x / scale];
+            rgba.extend_from_slice(&rgb_to_rgba(argb));
+        }
+    }
+
+    Image { width, height, rgba }
+}
+
+fn write_png_chunk(out: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = out.len();
+    out.extend_from_slice(chunk_type);
+    out.extend_from_slice(data);
+
+    // The CRC covers the chunk type and data, but not the length.
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let mut a = 1u32;
+    let mut b = 0u32;
+
+    // Summing up to 5552 bytes at a time can't overflow before the modulo.
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+
+    (b << 16) | a
+}
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const WINDOW_SZ: usize = 32768;
+const HASH_SZ: usize = 1 << 15;
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+struct BitWriter {
+    out: Vec<u8>,
+    bit_buf: u32,
+    bit_count: u8,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter { out: Vec::new(), bit_buf: 0, bit_count: 0 }
+    }
+
+    // Deflate packs values LSB first.
+    fn write_bits(&mut self, val: u32, count: u8) {
+        self.bit_buf |= val << self.bit_count;
+        self.bit_count += count;
+
+        while self.bit_count >= 8 {
+            self.out.push(self.bit_buf as u8);
+            self.bit_buf >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    // Huffman codes are defined MSB first, so they're reversed before packing.
+    fn write_code(&mut self, code: u32, len: u8) {
+        let mut reversed = 0;
+        for bit in 0..len {
+            reversed |= ((code >> bit) & 1) << (len - 1 - bit);
+        }
+
+        self.write_bits(reversed, len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.out.push(self.bit_buf as u8);
+        }
+
+        self.out
+    }
+}
+
+fn write_literal(writer: &mut BitWriter, symbol: u16) {
+    // The fixed Huffman code from RFC 1951 section 3.2.6.
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xC0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, len: usize, dist: usize) {
+    let len_idx = LENGTH_BASES.iter().rposition(|base| *base as usize <= len).unwrap();
+    write_literal(writer, 257 + len_idx as u16);
+    writer.write_bits((len - LENGTH_BASES[len_idx] as usize) as u32, LENGTH_EXTRA_BITS[len_idx]);
+
+    let dist_idx = DIST_BASES.iter().rposition(|base| *base as usize <= dist).unwrap();
+    writer.write_code(dist_idx as u32, 5);
+    writer.write_bits((dist - DIST_BASES[dist_idx] as usize) as u32, DIST_EXTRA_BITS[dist_idx]);
+}
+
+fn hash3(data: &[u8], pos: usize) -> usize {
+    let val = (data[pos] as usize) << 16 | (data[pos + 1] as usize) << 8 | data[pos + 2] as usize;
+    (val.wrapping_mul(2_654_435_761) >> 7) % HASH_SZ
+}
+
+pub(crate) fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    // A single fixed-Huffman deflate block with greedy LZ77 matching. Pixel art is mostly runs and repeated
+    // rows, so checking the last occurrence of each 3-byte sequence plus the previous pixel is plenty.
+    let mut writer = BitWriter::new();
+    writer.write_bits(1, 1); // BFINAL
+    writer.write_bits(1, 2); // BTYPE = fixed Huffman
+
+    let mut head = vec![usize::MAX; HASH_SZ];
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+
+        if pos + MIN_MATCH <= data.len() {
+            let hash = hash3(data, pos);
+            let mut candidates = [head[hash], pos.wrapping_sub(4)];
+            if candidates[1] >= pos {
+                candidates[1] = usize::MAX;
+            }
+
+            for candidate in candidates.iter().copied().filter(|candidate| *candidate != usize::MAX) {
+                let dist = pos - candidate;
+                if dist > WINDOW_SZ {
+                    continue;
+                }
+
+                let max_len = std::cmp::min(MAX_MATCH, data.len() - pos);
+                let mut len = 0;
+                while len < max_len && data[candidate + len] == data[pos + len] {
+                    len += 1;
+                }
+
+                if len > best_len {
+                    best_len = len;
+                    best_dist = dist;
+                }
+            }
+
+            head[hash] = pos;
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(&mut writer, best_len, best_dist);
+
+            for skipped in pos + 1..pos + best_len {
+                if skipped + MIN_MATCH <= data.len() {
+                    head[hash3(data, skipped)] = skipped;
+                }
+            }
+
+            pos += best_len;
+        } else {
+            write_literal(&mut writer, data[pos] as u16);
+            pos += 1;
+        }
+    }
+
+    write_literal(&mut writer, 256); // End of block.
+
+    // CMF/FLG for a 32K window with no preset dictionary, followed by the deflate stream and its checksum.
+    let mut out = vec![0x78, 0x01];
+    out.extend(writer.finish());
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::BitDisplay;
+    use crate::interpreter::{RES_X, RES_Y};
+
+    #[test]
+    fn checksum_test() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+        assert_eq!(0x11E6_0398, adler32(b"Wikipedia"));
+    }
+
+    #[test]
+    fn render_scale_and_palette_test() {
+        let mut frame = Frame::new(2, 1);
+        frame.pixels[1] = 1;
+
+        let mut options = RenderOptions::new();
+        options.palette = Palette::from_name("hotdog").unwrap();
+        options.scale = 2;
+
+        let image = render(&frame, &options);
+        assert_eq!(4, image.width);
+        assert_eq!(2, image.height);
+
+        // Both rows should be two black pixels followed by two red pixels.
+        let black = [0x00, 0x00, 0x00, 0xFF];
+        let red = [0xFF, 0x00, 0x00, 0xFF];
+        let expected_row = [black, black, red, red].concat();
+        assert_eq!([expected_row.clone(), expected_row].concat(), image.rgba);
+    }
+
+    #[test]
+    fn render_grid_and_scanlines_test() {
+        let mut frame = Frame::new(1, 1);
+        frame.pixels[0] = 1;
+
+        let mut options = RenderOptions::new();
+        options.scale = 3;
+        options.scanlines = true;
+
+        let image = render(&frame, &options);
+        assert_eq!([0xFF, 0xFF, 0xFF, 0xFF], image.rgba[0..4]);
+        assert_eq!([0x7F, 0x7F, 0x7F, 0xFF], image.rgba[(2 * 3) * 4..(2 * 3) * 4 + 4]);
+
+        options.scanlines = false;
+        options.grid_color = Some([0x10, 0x20, 0x30, 0xFF]);
+
+        let image = render(&frame, &options);
+        assert_eq!([0xFF, 0xFF, 0xFF, 0xFF], image.rgba[0..4]);
+        assert_eq!([0x10, 0x20, 0x30, 0xFF], image.rgba[2 * 4..2 * 4 + 4]);
+    }
+
+    #[test]
+    fn render_chip8x_test() {
+        // Lit pixels take their zone's color, and the second zone starts at x = 8.
+        let mut frame = Frame::new(16, 1);
+        frame.pixels[0] = 1;
+        frame.pixels[8] = 1;
+
+        let mut color_map = [[1; COLOR_ZONES_X]];
+        color_map[0][1] = 4;
+
+        let image = render_chip8x(&frame, &color_map, 2, 1);
+        assert_eq!(rgb_to_rgba(CHIP8X_FOREGROUNDS[1]), image.rgba[0..4]);
+        assert_eq!(rgb_to_rgba(CHIP8X_BACKGROUNDS[2]), image.rgba[4..8]);
+        assert_eq!(rgb_to_rgba(CHIP8X_FOREGROUNDS[4]), image.rgba[8 * 4..8 * 4 + 4]);
+    }
+
+    #[test]
+    fn render_mega_test() {
+        let mut display = MegaDisplay::new();
+        display.front[MEGA_RES_X + 1] = 0xFF12_3456;
+
+        let image = render_mega(&display, 2);
+        assert_eq!((MEGA_RES_X * 2, MEGA_RES_Y * 2), (image.width, image.height));
+
+        let pos = (2 * image.width + 2) * 4;
+        assert_eq!([0x12, 0x34, 0x56, 0xFF], image.rgba[pos..pos + 4]);
+        assert_eq!([0x00, 0x00, 0x00, 0xFF], image.rgba[0..4]);
+    }
+
+    #[test]
+    fn pbm_test() {
+        let mut frame = Frame::new(10, 1);
+        frame.pixels[0] = 1;
+        frame.pixels[9] = 1;
+
+        let mut expected = b"P4\n10 1\n".to_vec();
+        expected.extend_from_slice(&[0b1000_0000, 0b0100_0000]);
+        assert_eq!(expected, frame.to_pbm());
+    }
+
+    #[test]
+    fn png_test() {
+        let frame = Frame::from_display(&BitDisplay::new(RES_X, RES_Y, 1));
+        let image = render(&frame, &RenderOptions::new());
+        let png = image.to_png();
+
+        // Identical frames should encode to identical bytes, so snapshots can be compared directly.
+        assert_eq!(png, image.to_png());
+        assert_eq!(PNG_SIGNATURE, png[0..8]);
+        assert_eq!(b"IHDR", &png[12..16]);
+        assert_eq!((RES_X as u32).to_be_bytes(), png[16..20]);
+        assert_eq!((RES_Y as u32).to_be_bytes(), png[20..24]);
+        assert_eq!(b"IEND", &png[png.len() - 8..png.len() - 4]);
+
+        // A blank frame is almost entirely one long run, so it should compress well.
+        assert!(png.len() < 200);
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::json::Json;
+use crate::keycodes::KeyCodes;
+use crate::profile::Profile;
+use crate::quirk_flags::QuirkFlags;
+use crate::sha1;
+
+// The database built into the library, in the same format as the community CHIP-8 database's programs.json. Entries
+// are only added for ROM files whose hashes have been checked against the files themselves, which so far is just the
+// IBM logo. Until programs.json itself is vendored here, hosts should merge it in with from_json.
+const EMBEDDED_DB: &str = include_str!("romdb.json");
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RomDbErr {
+    InvalidJson(usize), // Byte offset of the syntax error.
+    InvalidEntry(String),
+}
+
+impl fmt::Display for RomDbErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomDbErr::InvalidJson(pos) => write!(f, "invalid JSON at byte {}", pos),
+            RomDbErr::InvalidEntry(msg) => write!(f, "invalid entry: {}", msg),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub release: Option<String>,
+    pub profile: Option<Profile>, // None if the ROM only runs on platforms we don't emulate.
+    pub quirks: QuirkFlags,
+    pub instructions_per_frame: Option<u64>,
+    pub keys: Vec<(String, KeyCodes)>, // What each key does, such as "up" or "a", in the database's order.
+}
+
+impl RomInfo {
+    pub fn tick_rate(&self) -> Option<u64> {
+        // What to pass to run_frame for the recommended speed.
+        self.instructions_per_frame.map(|ipf| ipf * 60)
+    }
+}
+
+// Known ROMs keyed by SHA-1, with what they need to run properly.
+//
+// Reads the community CHIP-8 database's programs.json, where every program lists its ROM files by hash along with
+// the platforms they run on, per-platform quirks, speed and key bindings. Each ROM takes the first of its platforms
+// that we have a profile for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RomDb {
+    roms: HashMap<String, RomInfo>, // Keyed by lowercase hex digest.
+}
+
+impl RomDb {
+    pub fn embedded() -> Self {
+        RomDb::from_json(EMBEDDED_DB).expect("the embedded ROM database is valid")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, RomDbErr> {
+        let programs = Json::parse(json).map_err(|err| RomDbErr::InvalidJson(err.pos))?;
+        let programs = programs.as_array().ok_or_else(|| invalid("expected an array of programs"))?;
+
+        let mut db = RomDb::default();
+        for program in programs {
+            db.add_program(program)?;
+        }
+
+        Ok(db)
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.lookup_hash(&sha1::to_hex(&sha1::sha1(rom)))
+    }
+
+    pub fn lookup_hash(&self, digest: &str) -> Option<&RomInfo> {
+        self.roms.get(&digest.to_ascii_lowercase())
+    }
+
+    pub fn merge(&mut self, other: RomDb) {
+        // Entries in the other database win.
+        self.roms.extend(other.roms);
+    }
+
+    fn add_program(&mut self, program: &Json) -> Result<(), RomDbErr> {
+        let title = program.get("title").and_then(Json::as_str).ok_or_else(|| invalid("program without a title"))?;
+        let authors = program.get("authors").and_then(Json::as_array).unwrap_or(&[])
+            .iter()
+            .filter_map(|author| author.as_str().map(String::from))
+            .collect::<Vec<_>>();
+        let release = program.get("release").and_then(Json::as_str).map(String::from);
+
+        let roms = program.get("roms").and_then(Json::as_object)
+            .ok_or_else(|| invalid(&format!("'{}' has no roms", title)))?;
+
+        for (digest, rom) in roms {
+            let platforms = rom.get("platforms").and_then(Json::as_array).unwrap_or(&[]);
+            let (platform, profile) = match platforms.iter().filter_map(Json::as_str)
+                .find_map(|platform| profile_for_platform(platform).map(|profile| (platform, profile))) {
+                Some((platform, profile)) => (Some(platform), Some(profile)),
+                None => (None, None),
+            };
+
+            let mut quirks = profile.map(|profile| profile.quirks()).unwrap_or(QuirkFlags::NONE);
+            if let Some(overrides) = platform.and_then(|platform| rom.get("quirkyPlatforms")?.get(platform)) {
+                apply_quirk_overrides(&mut quirks, overrides);
+            }
+
+            let keys = rom.get("keys").and_then(Json::as_object).unwrap_or(&[])
+                .iter()
+                .filter_map(|(name, key)| {
+                    let key = KeyCodes::from_u8(key.as_u64().filter(|&key| key <= 0xF)? as u8)?;
+                    Some((name.clone(), key))
+                })
+                .collect();
+
+            self.roms.insert(digest.to_ascii_lowercase(), RomInfo {
+                title: String::from(title),
+                authors: authors.clone(),
+                release: release.clone(),
+                profile,
+                quirks,
+                instructions_per_frame: rom.get("tickrate").and_then(Json::as_u64),
+                keys,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid(msg: &str) -> RomDbErr {
+    RomDbErr::InvalidEntry(String::from(msg))
+}
+
+fn profile_for_platform(platform: &str) -> Option<Profile> {
+    // The database's platform ids.
+    match platform {
+        // modernChip8 is CHIP-8 as most interpreters run it today, which shifts VY and moves I on like the VIP.
+        "originalChip8" | "hybridVIP" | "modernChip8" => Some(Profile::CosmacVip),
+        "chip8x" => Some(Profile::Chip8X),
+        "chip48" | "superchip1" | "superchip" => Some(Profile::SChip),
+        "megachip8" => Some(Profile::MegaChip),
+        _ => None,
+    }
+}
+
+fn apply_quirk_overrides(quirks: &mut QuirkFlags, overrides: &Json) {
+    // Only the quirks we model. "shift" means 8XY6 and 8XYE shift VX in place rather than VY, and
+    // "memoryLeaveIUnchanged" that FX55 and FX65 don't advance I.
+    if let Some(shift) = overrides.get("shift").and_then(Json::as_bool) {
+        quirks.set(QuirkFlags::QUIRK_8XY6 | QuirkFlags::QUIRK_8XYE, !shift);
+    }
+
+    if let Some(leave_i) = overrides.get("memoryLeaveIUnchanged").and_then(Json::as_bool) {
+        quirks.set(QuirkFlags::QUIRK_FX55 | QuirkFlags::QUIRK_FX65, !leave_i);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DB: &str = r#"[
+        {
+            "title": "Test Program",
+            "authors": ["Someone", "Someone Else"],
+            "release": "1978",
+            "roms": {
+                "0123456789ABCDEF0123456789ABCDEF01234567": {
+                    "file": "test.ch8",
+                    "platforms": ["xochip", "originalChip8", "superchip"],
+                    "quirkyPlatforms": { "originalChip8": { "shift": true } },
+                    "tickrate": 15,
+                    "keys": { "up": 5, "down": 8, "a": 6, "bad": 16 }
+                },
+                "89abcdef0123456789abcdef0123456789abcdef": { "platforms": ["xochip"] }
+            }
+        }
+    ]"#;
+
+    #[test]
+    fn from_json_test() {
+        let db = RomDb::from_json(DB).unwrap();
+        assert_eq!(2, db.len());
+
+        let info = db.lookup_hash("0123456789abcdef0123456789abcdef01234567").unwrap();
+        assert_eq!("Test Program", info.title);
+        assert_eq!(vec!["Someone", "Someone Else"], info.authors);
+        assert_eq!(Some(String::from("1978")), info.release);
+        assert_eq!(Some(Profile::CosmacVip), info.profile);
+        assert_eq!(QuirkFlags::QUIRK_FX55 | QuirkFlags::QUIRK_FX65, info.quirks);
+        assert_eq!(Some(900), info.tick_rate());
+        assert_eq!(vec![
+            (String::from("up"), KeyCodes::Key5),
+            (String::from("down"), KeyCodes::Key8),
+            (String::from("a"), KeyCodes::Key6),
+        ], info.keys);
+
+        let info = db.lookup_hash("89ABCDEF0123456789ABCDEF0123456789ABCDEF").unwrap();
+        assert_eq!(None, info.profile);
+        assert_eq!(None, info.tick_rate());
+    }
+
+    #[test]
+    fn lookup_test() {
+        let rom = [0x12, 0x00];
+        let json = format!(r#"[{{ "title": "Loop", "roms": {{ "{}": {{ "platforms": ["chip8x"] }} }} }}]"#,
+            sha1::to_hex(&sha1::sha1(&rom)));
+
+        let db = RomDb::from_json(&json).unwrap();
+        assert_eq!(Some(Profile::Chip8X), db.lookup(&rom).and_then(|info| info.profile));
+        assert_eq!(None, db.lookup(&[0x12, 0x02]));
+    }
+
+    #[test]
+    fn modern_chip8_test() {
+        let json = r#"[{ "title": "Modern", "roms": {
+            "0123456789abcdef0123456789abcdef01234567": { "platforms": ["modernChip8", "superchip"] },
+            "89abcdef0123456789abcdef0123456789abcdef": {
+                "platforms": ["modernChip8"],
+                "quirkyPlatforms": { "modernChip8": { "memoryLeaveIUnchanged": true } }
+            }
+        } }]"#;
+        let db = RomDb::from_json(json).unwrap();
+
+        let info = db.lookup_hash("0123456789abcdef0123456789abcdef01234567").unwrap();
+        assert_eq!(Some(Profile::CosmacVip), info.profile);
+        assert_eq!(Profile::CosmacVip.quirks(), info.quirks);
+
+        let info = db.lookup_hash("89abcdef0123456789abcdef0123456789abcdef").unwrap();
+        assert_eq!(QuirkFlags::QUIRK_8XY6 | QuirkFlags::QUIRK_8XYE, info.quirks);
+    }
+
+    #[test]
+    fn from_json_err_test() {
+        assert_eq!(Err(RomDbErr::InvalidJson(1)), RomDb::from_json("[,]"));
+        assert!(RomDb::from_json("{}").is_err());
+        assert!(RomDb::from_json(r#"[{ "roms": {} }]"#).is_err());
+
+        // Panics if the embedded database doesn't parse.
+        RomDb::embedded();
+    }
+
+    #[test]
+    fn embedded_test() {
+        const IBM_LOGO: [u8; 132] = [
+            0x00, 0xE0, 0xA2, 0x2A, 0x60, 0x0C, 0x61, 0x08, 0xD0, 0x1F, 0x70, 0x09, 0xA2, 0x39, 0xD0, 0x1F,
+            0xA2, 0x48, 0x70, 0x08, 0xD0, 0x1F, 0x70, 0x04, 0xA2, 0x57, 0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x66,
+            0xD0, 0x1F, 0x70, 0x08, 0xA2, 0x75, 0xD0, 0x1F, 0x12, 0x28, 0xFF, 0x00, 0xFF, 0x00, 0x3C, 0x00,
+            0x3C, 0x00, 0x3C, 0x00, 0x3C, 0x00, 0xFF, 0x00, 0xFF, 0xFF, 0x00, 0xFF, 0x00, 0x38, 0x00, 0x3F,
+            0x00, 0x3F, 0x00, 0x38, 0x00, 0xFF, 0x00, 0xFF, 0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00,
+            0x80, 0x00, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0xF8, 0x00, 0xFC, 0x00, 0x3E, 0x00, 0x3F, 0x00, 0x3B,
+            0x00, 0x39, 0x00, 0xF8, 0x00, 0xF8, 0x03, 0x00, 0x07, 0x00, 0x0F, 0x00, 0xBF, 0x00, 0xFB, 0x00,
+            0xF3, 0x00, 0xE3, 0x00, 0x43, 0xE0, 0x00, 0xE0, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80, 0x00, 0x80,
+            0x00, 0xE0, 0x00, 0xE0,
+        ];
+
+        let db = RomDb::embedded();
+        let info = db.lookup(&IBM_LOGO).unwrap();
+        assert_eq!("IBM Logo", info.title);
+        assert_eq!(Some(Profile::CosmacVip), info.profile);
+        assert_eq!(Profile::CosmacVip.quirks(), info.quirks);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::callstack::CallStack;
+use crate::display::{BitDisplay, Display};
+use crate::interpreter::{KeyAwaitOp, COLOR_ZONES_X, MEM_SZ, REG_COUNT, RES_X, RES_Y};
+use crate::keycodes::KeyCodes;
+use crate::mega::MegaDisplay;
+use crate::quirk_flags::QuirkFlags;
+use crate::rng::Rng;
+use crate::sha1::{self, DIGEST_SZ};
+use crate::timer::Timer;
+use crate::timing::VipTiming;
+
+// A complete snapshot of the machine, including the random number generator, so that restoring it replays
+// exactly the same way. The platform adapter isn't part of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveState {
+    pub quirks: QuirkFlags,
+    pub key_press: Option<KeyCodes>,
+    pub key_press_2: Option<KeyCodes>,
+    pub display: BitDisplay,
+    pub color_map: [[u8; COLOR_ZONES_X]; RES_Y],
+    pub background_color: u8,
+    pub memory: Vec<u8>,
+    pub pc: u16,
+    pub v_regs: [u8; REG_COUNT],
+    pub i_reg: u32,
+    pub stack: CallStack,
+    pub key_await_dest_reg: Option<KeyAwaitOp>,
+    pub delay_timer: Timer,
+    pub sound_timer: Timer,
+    pub is_sound_playing: bool,
+    pub rng: Rng,
+    pub mega: Option<MegaDisplay>,
+    pub timing: Option<VipTiming>,
+}
+
+impl SaveState {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // A flat, fixed-order encoding of every field. It's what state hashes are computed over, so two states
+        // hash the same exactly when they'd run the same.
+        let mut out = Vec::with_capacity(MEM_SZ + RES_X * RES_Y + 64);
+
+        out.push(self.quirks.bits());
+        out.push(self.key_press.map(|keycode| keycode as u8).unwrap_or(0xFF));
+        out.push(self.key_press_2.map(|keycode| keycode as u8).unwrap_or(0xFF));
+        out.push((self.display.width() / 8) as u8);
+        out.push(self.display.height() as u8);
+        out.push(self.display.plane_count() as u8);
+        for plane in 0..self.display.plane_count() {
+            for word in self.display.plane_words(plane) {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        for row in self.color_map.iter() {
+            out.extend_from_slice(row);
+        }
+        out.push(self.background_color);
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.extend_from_slice(&self.v_regs);
+        out.extend_from_slice(&self.i_reg.to_le_bytes());
+
+        let stack = self.stack.snapshot().unwrap_or_default();
+        out.push(stack.len() as u8);
+        for addr in stack {
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+
+        out.push(self.key_await_dest_reg.map(|op| op.dest_v_reg).unwrap_or(0xFF));
+        for timer in [&self.delay_timer, &self.sound_timer].iter() {
+            out.push(timer.start_val);
+            out.push(timer.current_val);
+            out.extend_from_slice(&timer.count_ticks().to_le_bytes());
+        }
+        out.push(self.is_sound_playing as u8);
+
+        out.push(self.rng.kind as u8);
+        out.extend_from_slice(&self.rng.seed.to_le_bytes());
+        out.extend_from_slice(&self.rng.state.to_le_bytes());
+
+        out.push(self.mega.is_some() as u8);
+        if let Some(mega) = &self.mega {
+            for buffer in [&mega.front, &mega.back].iter() {
+                for pixel in buffer.iter() {
+                    out.extend_from_slice(&pixel.to_le_bytes());
+                }
+            }
+            out.extend_from_slice(&mega.indices);
+            for color in mega.palette.iter() {
+                out.extend_from_slice(&color.to_le_bytes());
+            }
+            out.extend_from_slice(&(mega.sprite_width as u16).to_le_bytes());
+            out.extend_from_slice(&(mega.sprite_height as u16).to_le_bytes());
+            out.push(mega.alpha);
+            out.push(mega.blend_mode as u8);
+        }
+
+        // Only written with VIP timing on, so that states without it hash the same as they did before it existed.
+        if let Some(timing) = &self.timing {
+            out.extend_from_slice(&timing.cycles.to_le_bytes());
+            out.extend_from_slice(&timing.next_interrupt().to_le_bytes());
+        }
+
+        out
+    }
+
+    pub fn hash(&self) -> [u8; DIGEST_SZ] {
+        sha1::sha1(&self.to_bytes())
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SmcKind {
+    WriteToExecuted, // A write into an instruction that has already run.
+    ExecuteWritten,  // Running an instruction whose bytes were written since they were loaded or last run.
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SmcEvent {
+    pub kind: SmcKind,
+    pub writer_pc: u16, // Address of the instruction that did the writing.
+    pub addr: u32,      // The byte written, or the instruction run.
+    pub old_instr: u16,
+    pub new_instr: u16,
+}
+
+impl fmt::Display for SmcEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self.kind {
+            SmcKind::WriteToExecuted => "wrote executed code at",
+            SmcKind::ExecuteWritten => "wrote code executed at",
+        };
+
+        write!(f, "{:#05X} {} {:#05X}: {:04X} -> {:04X}", self.writer_pc, action, self.addr, self.old_instr, self.new_instr)
+    }
+}
+
+// Tracks self-modifying code: which instructions have run and which bytes were written at runtime, reporting
+// wherever the two meet.
+//
+// An instruction that's modified and run over and over reports a pair of events each time round, since after
+// running it counts as executed again.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SmcTracker {
+    executed: HashSet<u32>,            // Addresses instructions have been fetched from.
+    written: HashMap<u32, (u16, u8)>, // Bytes written since they last ran, with the last writer and the original byte.
+    current_pc: u16,
+    events: Vec<SmcEvent>,
+}
+
+impl SmcTracker {
+    pub fn new() -> Self {
+        SmcTracker::default()
+    }
+
+    pub fn events(&self) -> &[SmcEvent] {
+        &self.events
+    }
+
+    pub fn take_events(&mut self) -> Vec<SmcEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub(crate) fn on_fetch(&mut self, pc: u16, memory: &[u8]) {
+        self.current_pc = pc;
+
+        let addr = pc as u32;
+        let first = self.written.remove(&addr);
+        let second = self.written.remove(&(addr + 1));
+
+        if first.is_some() || second.is_some() {
+            let new_instr = read_instr(memory, addr);
+            let [hi, lo] = new_instr.to_be_bytes();
+
+            // The last of the two writes, with whatever was there before either.
+            let writer_pc = second.or(first).map(|(writer_pc, _)| writer_pc).unwrap_or(pc);
+            let old_hi = first.map(|(_, byte)| byte).unwrap_or(hi);
+            let old_lo = second.map(|(_, byte)| byte).unwrap_or(lo);
+
+            self.events.push(SmcEvent {
+                kind: SmcKind::ExecuteWritten,
+                writer_pc,
+                addr,
+                old_instr: u16::from_be_bytes([old_hi, old_lo]),
+                new_instr,
+            });
+        }
+
+        self.executed.insert(addr);
+    }
+
+    pub(crate) fn on_write(&mut self, addr: u32, old_byte: u8, memory: &[u8]) {
+        // Called after memory has been written, with the byte that was there before. Writing the same value back
+        // changes nothing.
+        if memory.get(addr as usize) == Some(&old_byte) {
+            return;
+        }
+
+        let writer_pc = self.current_pc;
+        let original = self.written.get(&addr).map(|(_, byte)| *byte).unwrap_or(old_byte);
+        self.written.insert(addr, (writer_pc, original));
+
+        let instr_addr = if self.executed.contains(&addr) {
+            addr
+        } else if addr > 0 && self.executed.contains(&(addr - 1)) {
+            addr - 1
+        } else {
+            return;
+        };
+
+        let new_instr = read_instr(memory, instr_addr);
+        let mut old_bytes = new_instr.to_be_bytes();
+        old_bytes[(addr - instr_addr) as usize] = old_byte;
+        let old_instr = u16::from_be_bytes(old_bytes);
+
+        self.events.push(SmcEvent { kind: SmcKind::WriteToExecuted, writer_pc, addr, old_instr, new_instr });
+    }
+}
+
+fn read_instr(memory: &[u8], addr: u32) -> u16 {
+    let byte = |addr: u32| memory.get(addr as usize).copied().unwrap_or(0);
+    u16::from_be_bytes([byte(addr), byte(addr + 1)])
+}
@@ -0,0 +1,51 @@
+use crate::display::{Display, ALL_PLANES};
+use crate::interpreter::REG_COUNT;
+
+// The parts of the machine a machine-code routine can get at, lent to the platform adapter for the length of a SYS
+// call. Memory is a copy, and changes to it are checked against the memory map when the call returns.
+pub struct Machine<'a> {
+    pub memory: &'a mut [u8],
+    pub display: &'a mut dyn Display,
+    pub v_regs: &'a mut [u8; REG_COUNT],
+    pub i_reg: &'a mut u32,
+    pub pc: &'a mut u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SysCallResult {
+    Handled, // The host emulated or deliberately skipped the routine.
+    Unhandled, // Fall back to the built-in routines, then to the invalid opcode fault policy.
+}
+
+// 1802 routines that CHIP-8 programs for the COSMAC VIP called with 0NNN and that we can run without the 1802.
+//
+// Only one is well documented: 0230, which the two-page hires interpreters patch in to clear the 64x64 display, and
+// which their ROMs call in place of 00E0. Other routines were machine code shipped inside the program that called
+// them, so what they do varies from ROM to ROM. Those are left to the platform adapter's sys_call.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SysRoutine {
+    HiresClear, // 0230: clears the screen in the 64x64 hires CHIP-8 interpreter.
+}
+
+impl SysRoutine {
+    pub fn lookup(addr: u16) -> Option<SysRoutine> {
+        match addr {
+            0x230 => Some(SysRoutine::HiresClear),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SysRoutine::HiresClear => "hires clear screen",
+        }
+    }
+
+    pub fn run(&self, machine: &mut Machine) {
+        match self {
+            SysRoutine::HiresClear => {
+                machine.display.clear(ALL_PLANES);
+            }
+        }
+    }
+}
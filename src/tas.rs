@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+
+use crate::interpreter::{Chip8Interpreter, ExecutionError};
+use crate::keycodes::KeyCodes;
+use crate::movie::{Movie, MovieErr};
+use crate::platform_adapter::PlatformAdapter;
+use crate::savestate::SaveState;
+use crate::sha1::DIGEST_SZ;
+
+#[derive(Debug, PartialEq)]
+pub enum TasErr {
+    UnknownSlot(String),
+    FrameOutOfRange(usize),
+    Movie(MovieErr),
+    Execution(ExecutionError),
+}
+
+struct Slot {
+    state: SaveState,
+    frames: Vec<Option<KeyCodes>>, // The inputs that led to the state, so a slot can be loaded from any branch.
+    frames_2: Vec<Option<KeyCodes>>, // Empty, or as long as frames, like the movie's.
+}
+
+// A tool-assisted play session: an interpreter driven one frame at a time, with the movie of every input given to
+// it so far, and named save state slots to branch from.
+//
+// Advancing with a new input records it at the current frame and drops the rest of the movie, so loading a slot
+// and playing on re-records from there. Advancing with replay_frame instead follows the inputs already in the
+// movie, so the frames after a slot aren't lost until something different is recorded over them.
+pub struct TasSession<T>
+where
+    T: PlatformAdapter,
+{
+    interpreter: Chip8Interpreter<T>,
+    movie: Movie,
+    frame: usize,
+    power_on: SaveState,
+    slots: HashMap<String, Slot>,
+}
+
+impl<T> TasSession<T>
+where
+    T: PlatformAdapter,
+{
+    pub fn new(platform_adapter: T, rom: Vec<u8>, movie: Movie) -> Result<Self, TasErr> {
+        // Starts at power-on. Any frames already in the movie can be followed with replay_frame.
+        let interpreter = movie.start(platform_adapter, rom).map_err(TasErr::Movie)?;
+        let power_on = interpreter.save_state();
+
+        Ok(TasSession {
+            interpreter,
+            movie,
+            frame: 0,
+            power_on,
+            slots: HashMap::new(),
+        })
+    }
+
+    pub fn interpreter(&self) -> &Chip8Interpreter<T> {
+        &self.interpreter
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn state_hash(&self) -> [u8; DIGEST_SZ] {
+        self.interpreter.save_state().hash()
+    }
+
+    pub fn advance(&mut self, key: Option<KeyCodes>) -> Result<(), TasErr> {
+        self.advance_keypads(key, None)
+    }
+
+    pub fn advance_keypads(&mut self, key: Option<KeyCodes>, key_2: Option<KeyCodes>) -> Result<(), TasErr> {
+        self.movie.truncate(self.frame);
+        self.movie.final_state_hash = None;
+
+        self.movie.record_frame_keypads(&mut self.interpreter, key, key_2).map_err(TasErr::Execution)?;
+        self.frame += 1;
+
+        Ok(())
+    }
+
+    pub fn replay_frame(&mut self) -> Result<bool, TasErr> {
+        // Returns false, without running anything, once the end of the movie is reached.
+        if self.frame >= self.movie.len() {
+            return Ok(false);
+        }
+
+        self.movie.play_frame(self.frame, &mut self.interpreter).map_err(TasErr::Movie)?;
+        self.frame += 1;
+
+        Ok(true)
+    }
+
+    pub fn save_slot(&mut self, name: &str) {
+        let slot = Slot {
+            state: self.interpreter.save_state(),
+            frames: self.movie.frames[..self.frame].to_vec(),
+            frames_2: self.movie.frames_2.iter().take(self.frame).copied().collect(),
+        };
+
+        self.slots.insert(name.to_string(), slot);
+    }
+
+    pub fn load_slot(&mut self, name: &str) -> Result<(), TasErr> {
+        let slot = self.slots.get(name).ok_or_else(|| TasErr::UnknownSlot(name.to_string()))?;
+
+        // If the slot was saved on another branch, its inputs replace the movie's.
+        if !on_branch(&self.movie, slot) {
+            self.movie.frames = slot.frames.clone();
+            self.movie.frames_2 = slot.frames_2.clone();
+            self.movie.final_state_hash = None;
+        }
+
+        self.interpreter.load_state(&slot.state);
+        self.frame = slot.frames.len();
+
+        Ok(())
+    }
+
+    pub fn delete_slot(&mut self, name: &str) -> bool {
+        self.slots.remove(name).is_some()
+    }
+
+    pub fn slot_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.slots.keys().map(|name| name.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    pub fn set_input(&mut self, frame: usize, key: Option<KeyCodes>) -> Result<(), TasErr> {
+        // Leaves the second keypad as it was on that frame.
+        self.set_input_keypads(frame, key, self.movie.key_2(frame))
+    }
+
+    pub fn set_input_keypads(&mut self, frame: usize, key: Option<KeyCodes>, key_2: Option<KeyCodes>) -> Result<(), TasErr> {
+        // Edit a single frame of the movie. If it has already been played, the session rewinds to the nearest
+        // slot before it (or power-on) and plays forward again to where it was.
+        if frame >= self.movie.len() {
+            return Err(TasErr::FrameOutOfRange(frame));
+        }
+
+        if self.movie.frames[frame] == key && self.movie.key_2(frame) == key_2 {
+            return Ok(());
+        }
+
+        self.movie.frames[frame] = key;
+        if key_2.is_some() || !self.movie.frames_2.is_empty() {
+            self.movie.frames_2.resize(self.movie.len(), None);
+            self.movie.frames_2[frame] = key_2;
+        }
+        self.movie.final_state_hash = None;
+
+        if frame >= self.frame {
+            return Ok(());
+        }
+
+        let target = self.frame;
+        self.rewind_before(frame);
+
+        while self.frame < target {
+            self.replay_frame()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn to_movie(&self) -> Movie {
+        // The movie up to the current frame, sealed with the current state's hash.
+        let mut movie = self.movie.clone();
+        movie.truncate(self.frame);
+        movie.final_state_hash = Some(self.state_hash());
+        movie
+    }
+
+    fn rewind_before(&mut self, frame: usize) {
+        let nearest = self.slots.values()
+            .filter(|slot| slot.frames.len() <= frame && on_branch(&self.movie, slot))
+            .max_by_key(|slot| slot.frames.len());
+
+        match nearest {
+            Some(slot) => {
+                self.interpreter.load_state(&slot.state);
+                self.frame = slot.frames.len();
+            }
+            None => {
+                self.interpreter.load_state(&self.power_on);
+                self.frame = 0;
+            }
+        }
+    }
+}
+
+fn on_branch(movie: &Movie, slot: &Slot) -> bool {
+    // Whether the movie starts with the slot's inputs, on both keypads.
+    movie.frames.starts_with(&slot.frames)
+        && (0..slot.frames.len()).all(|frame| movie.key_2(frame) == slot.frames_2.get(frame).copied().flatten())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::Profile;
+    use crate::rng::{Rng, RngKind};
+
+    struct MockPlatform;
+
+    impl PlatformAdapter for MockPlatform {
+        fn play_sound(&mut self) {}
+        fn pause_sound(&mut self) {}
+    }
+
+    // Waits for a key, adds a random number into V1 and loops back to wait again.
+    const ROM: [u8; 8] = [0xF0, 0x0A, 0xC2, 0xFF, 0x81, 0x24, 0x12, 0x00];
+
+    fn new_session() -> TasSession<MockPlatform> {
+        let rng = Rng::new(RngKind::XorShift, 99);
+        let movie = Movie::new(&ROM, Profile::CosmacVip.quirks(), Some(Profile::CosmacVip), rng, 600);
+        TasSession::new(MockPlatform, ROM.to_vec(), movie).unwrap()
+    }
+
+    #[test]
+    fn rerecord_from_slot_test() {
+        let mut session = new_session();
+        session.advance(None).unwrap();
+        session.advance(Some(KeyCodes::Key1)).unwrap();
+        session.save_slot("a");
+        let slot_hash = session.state_hash();
+
+        session.advance(Some(KeyCodes::Key2)).unwrap();
+        session.advance(None).unwrap();
+        assert_eq!(4, session.movie().len());
+
+        // Loading keeps the later frames around for replay until something new is recorded over them.
+        session.load_slot("a").unwrap();
+        assert_eq!(2, session.frame());
+        assert_eq!(slot_hash, session.state_hash());
+        assert_eq!(4, session.movie().len());
+
+        session.advance(Some(KeyCodes::Key3)).unwrap();
+        assert_eq!(3, session.movie().len());
+        assert_eq!(0x03, session.interpreter().v_regs[0x00]);
+
+        assert_eq!(Some(TasErr::UnknownSlot(String::from("b"))), session.load_slot("b").err());
+    }
+
+    #[test]
+    fn branch_slots_test() {
+        // A slot from an abandoned branch brings its own inputs back with it.
+        let mut session = new_session();
+        session.advance(Some(KeyCodes::Key1)).unwrap();
+        session.advance(Some(KeyCodes::Key2)).unwrap();
+        session.save_slot("first");
+
+        session.load_slot("first").unwrap();
+        session.set_input(0, Some(KeyCodes::Key4)).unwrap();
+        assert_eq!(Some(KeyCodes::Key4), session.movie().frames[0]);
+
+        session.load_slot("first").unwrap();
+        assert_eq!(vec![Some(KeyCodes::Key1), Some(KeyCodes::Key2)], session.movie().frames);
+        assert_eq!(vec!["first"], session.slot_names());
+    }
+
+    #[test]
+    fn set_input_test() {
+        let mut session = new_session();
+        for _ in 0..5 {
+            session.advance(None).unwrap();
+        }
+        session.save_slot("late");
+
+        // Editing a frame before every slot replays from power-on, and matches recording the edit directly.
+        session.set_input(1, Some(KeyCodes::KeyC)).unwrap();
+        assert_eq!(5, session.frame());
+        assert_eq!(0x0C, session.interpreter().v_regs[0x00]);
+
+        let mut direct = new_session();
+        for key in session.movie().frames.clone() {
+            direct.advance(key).unwrap();
+        }
+        assert_eq!(direct.state_hash(), session.state_hash());
+
+        assert_eq!(Some(TasErr::FrameOutOfRange(5)), session.set_input(5, None).err());
+    }
+
+    #[test]
+    fn second_keypad_test() {
+        // Branches that differ only on the second keypad are still different branches.
+        let mut session = new_session();
+        session.advance_keypads(None, Some(KeyCodes::Key1)).unwrap();
+        session.advance(None).unwrap();
+        session.save_slot("first");
+        let slot_hash = session.state_hash();
+
+        session.set_input_keypads(0, None, Some(KeyCodes::Key2)).unwrap();
+        assert_eq!(vec![Some(KeyCodes::Key2), None], session.movie().frames_2);
+
+        session.load_slot("first").unwrap();
+        assert_eq!(vec![Some(KeyCodes::Key1), None], session.movie().frames_2);
+        assert_eq!(slot_hash, session.state_hash());
+
+        // Editing the first keypad leaves the second one alone.
+        session.set_input(1, Some(KeyCodes::Key3)).unwrap();
+        assert_eq!(Some(KeyCodes::Key1), session.movie().key_2(0));
+
+        let movie = session.to_movie();
+        let played = movie.play(MockPlatform, ROM.to_vec()).unwrap();
+        assert_eq!(session.state_hash(), played.save_state().hash());
+    }
+
+    #[test]
+    fn to_movie_test() {
+        let mut session = new_session();
+        session.advance(Some(KeyCodes::Key7)).unwrap();
+        session.advance(None).unwrap();
+        session.advance(Some(KeyCodes::Key8)).unwrap();
+
+        let movie = session.to_movie();
+        let played = movie.play(MockPlatform, ROM.to_vec()).unwrap();
+        assert_eq!(session.state_hash(), played.save_state().hash());
+
+        let mut replayed = TasSession::new(MockPlatform, ROM.to_vec(), movie).unwrap();
+        while replayed.replay_frame().unwrap() {}
+        assert_eq!(3, replayed.frame());
+        assert_eq!(session.state_hash(), replayed.state_hash());
+    }
+}
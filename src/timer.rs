@@ -1,3 +1,4 @@
+#[derive(Clone, Debug, PartialEq)]
 pub struct Timer {
     pub start_val: u8,
     pub current_val: u8,
@@ -19,6 +20,17 @@ impl Timer {
         self.count_ticks = 0.0;
     }
 
+    pub fn count_ticks(&self) -> f64 {
+        self.count_ticks
+    }
+
+    pub fn decrement(&mut self) -> u8 {
+        // Count down once, for when something else keeps time at 60Hz.
+        self.current_val = self.current_val.saturating_sub(1);
+        self.count_ticks = 0.0;
+        self.current_val
+    }
+
     pub fn tick(&mut self, tick_rate: u64) -> u8 {
 
         let ticks_per_decrement = tick_rate as f64 / 60.0; // Timer is supposed to decrement at 60Hz.
@@ -0,0 +1,128 @@
+use crate::opcode::OpCode;
+
+// The COSMAC VIP's 1802 ran at 1.76 MHz with 8 clocks to a machine cycle, and the video chip interrupted it 60 times a
+// second, so a frame lasts about 3668 machine cycles.
+pub const VIP_CYCLES_PER_FRAME: u64 = 3668;
+
+// Each frame the video chip takes 8 cycles of DMA for each of the 128 lines it displays, and the interrupt routine
+// that counts the timers down takes a few more.
+pub const VIP_INTERRUPT_CYCLES: u64 = 128 * 8 + 46;
+
+// Every instruction goes through the interpreter's fetch and decode loop first.
+const FETCH_CYCLES: u64 = 40;
+
+// Tracks time in machine cycles for cycle-accurate VIP timing.
+//
+// Cycle counts are approximations of the original interpreter's routines, as measured by people who have
+// disassembled it, and are what decide how fast a program runs: the tick rate doesn't apply.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VipTiming {
+    pub cycles: u64,     // Elapsed since timing started.
+    next_interrupt: u64,
+}
+
+impl VipTiming {
+    pub fn new() -> Self {
+        VipTiming::default()
+    }
+
+    pub fn frames(&self) -> u64 {
+        // The number of interrupts so far.
+        self.next_interrupt / VIP_CYCLES_PER_FRAME
+    }
+
+    pub fn next_interrupt(&self) -> u64 {
+        // The cycle count the next interrupt is due at.
+        self.next_interrupt
+    }
+
+    pub(crate) fn is_interrupt_due(&self) -> bool {
+        self.cycles >= self.next_interrupt
+    }
+
+    pub(crate) fn interrupt(&mut self) {
+        self.cycles = self.cycles.max(self.next_interrupt) + VIP_INTERRUPT_CYCLES;
+        self.next_interrupt += VIP_CYCLES_PER_FRAME;
+    }
+
+    pub(crate) fn wait_for_interrupt(&mut self) {
+        // Idle until the interrupt, as DXYN does and FX0A does while no key is down.
+        self.cycles = self.cycles.max(self.next_interrupt);
+    }
+}
+
+// The machine cycles an instruction takes on the VIP, given VX's value before it ran and whether it skipped.
+//
+// Instructions the VIP didn't have only cost the fetch. DXYN's time doesn't include waiting for the interrupt.
+pub fn cycle_cost(opcode: &OpCode, vx: u8, skipped: bool) -> u64 {
+    let skip = if skipped { 4 } else { 0 };
+
+    let cycles = match *opcode {
+        OpCode::OpCode00e0() => 24 + 3078,
+        OpCode::OpCode00ee() => 10,
+        OpCode::OpCode1nnn(_) => 12,
+        OpCode::OpCode2nnn(_) => 26,
+        OpCode::OpCode3xnn(_, _) | OpCode::OpCode4xnn(_, _) => 10 + skip,
+        OpCode::OpCode5xy0(_, _) | OpCode::OpCode9xy0(_, _) => 14 + skip,
+        OpCode::OpCode6xnn(_, _) => 6,
+        OpCode::OpCode7xnn(_, _) => 10,
+        OpCode::OpCode8xy0(_, _) => 12,
+        OpCode::OpCode8xy1(_, _) | OpCode::OpCode8xy2(_, _) | OpCode::OpCode8xy3(_, _) | OpCode::OpCode8xy4(_, _)
+        | OpCode::OpCode8xy5(_, _) | OpCode::OpCode8xy6(_, _) | OpCode::OpCode8xy7(_, _) | OpCode::OpCode8xye(_, _) => 44,
+        OpCode::OpCodeAnnn(_) => 12,
+        OpCode::OpCodeBnnn(_) => 22,
+        OpCode::OpCodeCxnn(_, _) => 36,
+        OpCode::OpCodeDxyn(_, _, rows) => {
+            // Each row is shifted into place a bit at a time, so sprites off a byte boundary are slower.
+            let shift = (vx % 8) as u64;
+            26 + rows as u64 * (46 + 8 * shift)
+        }
+        OpCode::OpCodeEx9e(_) | OpCode::OpCodeExa1(_) => 14 + skip,
+        OpCode::OpCodeFx07(_) | OpCode::OpCodeFx15(_) | OpCode::OpCodeFx18(_) => 10,
+        OpCode::OpCodeFx0a(_) => 18,
+        OpCode::OpCodeFx1e(_) | OpCode::OpCodeFx29(_) => 16,
+        OpCode::OpCodeFx33(_) => {
+            // Each digit is found by repeated subtraction.
+            let digits = (vx / 100 + vx / 10 % 10 + vx % 10) as u64;
+            80 + 16 * digits
+        }
+        OpCode::OpCodeFx55(x) | OpCode::OpCodeFx65(x) => 14 + 14 * (x as u64 + 1),
+        _ => 0,
+    };
+
+    FETCH_CYCLES + cycles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_cost_test() {
+        assert_eq!(FETCH_CYCLES + 6, cycle_cost(&OpCode::OpCode6xnn(0, 0), 0, false));
+        assert_eq!(FETCH_CYCLES + 14, cycle_cost(&OpCode::OpCode3xnn(0, 0), 0, true));
+
+        // Sprites cost more the more rows they have and the further off a byte boundary they are.
+        let sprite = OpCode::OpCodeDxyn(0, 1, 5);
+        assert!(cycle_cost(&sprite, 3, false) > cycle_cost(&sprite, 0, false));
+        assert_eq!(cycle_cost(&sprite, 0, false), cycle_cost(&sprite, 8, false));
+        assert!(cycle_cost(&OpCode::OpCodeDxyn(0, 1, 15), 0, false) > cycle_cost(&sprite, 0, false));
+
+        assert_eq!(FETCH_CYCLES + 80 + 16 * 12, cycle_cost(&OpCode::OpCodeFx33(0), 255, false));
+    }
+
+    #[test]
+    fn interrupt_test() {
+        let mut timing = VipTiming::new();
+        assert!(timing.is_interrupt_due());
+
+        timing.interrupt();
+        assert_eq!(VIP_INTERRUPT_CYCLES, timing.cycles);
+        assert_eq!(1, timing.frames());
+        assert!(!timing.is_interrupt_due());
+
+        timing.wait_for_interrupt();
+        assert_eq!(VIP_CYCLES_PER_FRAME, timing.cycles);
+        assert!(timing.is_interrupt_due());
+    }
+}